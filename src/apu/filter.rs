@@ -0,0 +1,149 @@
+use super::resampler::HEADROOM;
+
+/// charge the output capacitor keeps per 4 MHz clock
+const DMG_CHARGE: f64 = 0.999958;
+const CGB_CHARGE: f64 = 0.998943;
+
+/// The capacitor between the mixer and the output, blocking its dc offset.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum HighPass {
+    /// No capacitor, only the resampler's own, slower dc removal.
+    Off,
+    Dmg,
+    /// A faster one, the cgb's bass is a little thinner.
+    Cgb,
+}
+
+/// Analog filters applied to the audio output.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct OutputFilter {
+    pub high_pass: HighPass,
+    /// Cutoff in Hz of a low pass, muffling the output like the built in
+    /// speaker does.
+    pub low_pass: Option<f32>,
+}
+
+impl OutputFilter {
+    /// The high pass of the model, without a low pass.
+    pub fn hardware(cgb: bool) -> Self {
+        OutputFilter {
+            high_pass: if cgb { HighPass::Cgb } else { HighPass::Dmg },
+            low_pass: None,
+        }
+    }
+}
+
+/// State of the output filters, on interleaved stereo samples. They restore
+/// the level the resampler left headroom for, saturating what doesn't fit.
+pub(crate) struct Filter {
+    config: OutputFilter,
+    /// charge kept per output sample
+    charge: f32,
+    /// share of the input the low pass takes per output sample
+    alpha: f32,
+    capacitor: [f32; 2],
+    low: [f32; 2],
+}
+
+impl Filter {
+    pub fn new(config: OutputFilter, sample_rate: f64) -> Self {
+        let mut filter = Filter {
+            config,
+            charge: 1.0,
+            alpha: 1.0,
+            capacitor: [0.0; 2],
+            low: [0.0; 2],
+        };
+        filter.set_rate(sample_rate);
+        filter
+    }
+
+    pub fn config(&self) -> OutputFilter {
+        self.config
+    }
+
+    pub fn set_config(&mut self, config: OutputFilter, sample_rate: f64) {
+        self.config = config;
+        self.set_rate(sample_rate);
+    }
+
+    pub fn set_rate(&mut self, sample_rate: f64) {
+        let clocks = crate::GB_CLOCK_SPEED as f64 / sample_rate;
+        self.charge = match self.config.high_pass {
+            HighPass::Off => 1.0,
+            HighPass::Dmg => DMG_CHARGE.powf(clocks) as f32,
+            HighPass::Cgb => CGB_CHARGE.powf(clocks) as f32,
+        };
+        self.alpha = match self.config.low_pass {
+            Some(cutoff) => {
+                let omega = 2.0 * std::f64::consts::PI * cutoff.max(0.0) as f64 / sample_rate;
+                (1.0 - (-omega).exp()) as f32
+            }
+            None => 1.0,
+        };
+    }
+
+    pub fn apply(&mut self, buf: &mut [i16]) {
+        let high_pass = self.config.high_pass != HighPass::Off;
+        let low_pass = self.config.low_pass.is_some();
+
+        for frame in buf.chunks_mut(2) {
+            for (side, sample) in frame.iter_mut().enumerate() {
+                let mut value = *sample as f32 * HEADROOM as f32;
+                if high_pass {
+                    let input = value;
+                    value = input - self.capacitor[side];
+                    self.capacitor[side] = input - value * self.charge;
+                }
+                if low_pass {
+                    self.low[side] += (value - self.low[side]) * self.alpha;
+                    value = self.low[side];
+                }
+                *sample = value.clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter_dc(high_pass: HighPass, level: i16) -> Vec<i16> {
+        let config = OutputFilter {
+            high_pass,
+            low_pass: None,
+        };
+        let mut filter = Filter::new(config, 48000.0);
+        // a second of stereo samples
+        let mut buf = vec![level; 2 * 48000];
+        filter.apply(&mut buf);
+        buf
+    }
+
+    #[test]
+    fn high_pass_removes_dc() {
+        for &high_pass in [HighPass::Dmg, HighPass::Cgb].iter() {
+            let buf = filter_dc(high_pass, 14400);
+            assert_eq!(buf[..2], [28800, 28800]);
+            assert!(buf[2] < 28800);
+            assert!(buf[buf.len() - 2..].iter().all(|&s| s == 0));
+        }
+
+        let buf = filter_dc(HighPass::Off, 14400);
+        assert!(buf.iter().all(|&s| s == 28800));
+    }
+
+    #[test]
+    fn output_saturates() {
+        let buf = filter_dc(HighPass::Off, 28800);
+        assert!(buf.iter().all(|&s| s == i16::MAX));
+        let buf = filter_dc(HighPass::Off, -28800);
+        assert!(buf.iter().all(|&s| s == i16::MIN));
+
+        // the capacitor still sees the whole level
+        let buf = filter_dc(HighPass::Dmg, 28800);
+        assert_eq!(buf[..2], [i16::MAX, i16::MAX]);
+        assert!(buf[buf.len() - 2..].iter().all(|&s| s == 0));
+    }
+}
@@ -1,4 +1,54 @@
+use crate::state::{Savestate, StateReader, StateWriter};
+
 const MASK_VEC: [u8; 2] = [0x00, 0xff];
+/// channel weights are in 1/256ths
+const UNITY: u32 = 0x100;
+/// output per channel level and volume step
+pub(crate) const LEVEL_SCALE: u32 = 120;
+/// loudest mix, 4 channels at 15 with volume 8
+const MAX_LEVEL: u32 = 15 * 4 * 8 * LEVEL_SCALE;
+
+/// The four sound channels, in register order.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Channel {
+    Square1,
+    Square2,
+    Wave,
+    Noise,
+}
+
+impl Channel {
+    pub const ALL: [Channel; 4] = [
+        Channel::Square1,
+        Channel::Square2,
+        Channel::Wave,
+        Channel::Noise,
+    ];
+}
+
+/// Host side settings of a channel, applied on top of the game's mixing
+/// without touching the registers.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ChannelControl {
+    pub mute: bool,
+    /// While any channel is soloed, only soloed channels play.
+    pub solo: bool,
+    /// 1.0 plays the channel as the game set it.
+    pub gain: f32,
+    /// Replaces the game's panning, from -1.0 for left to 1.0 for right.
+    pub pan: Option<f32>,
+}
+
+impl Default for ChannelControl {
+    fn default() -> Self {
+        ChannelControl {
+            mute: false,
+            solo: false,
+            gain: 1.0,
+            pan: None,
+        }
+    }
+}
 
 pub struct Mixer {
     so1_masks: [u8; 4],
@@ -6,6 +56,10 @@ pub struct Mixer {
 
     so1_volume: u8,
     so2_volume: u8,
+
+    controls: [ChannelControl; 4],
+    /// so1 and so2 weight of each channel, from the masks and the controls
+    weights: [[u32; 2]; 4],
 }
 
 impl Mixer {
@@ -15,6 +69,9 @@ impl Mixer {
             so2_masks: [0u8; 4],
             so1_volume: 0,
             so2_volume: 0,
+
+            controls: [ChannelControl::default(); 4],
+            weights: [[0; 2]; 4],
         }
     }
 
@@ -23,16 +80,50 @@ impl Mixer {
         let mut so2 = 0;
 
         for i in 0..4 {
-            so1 += self.so1_masks[i] & chs[i];
-            so2 += self.so2_masks[i] & chs[i];
+            so1 += chs[i] as u32 * self.weights[i][0];
+            so2 += chs[i] as u32 * self.weights[i][1];
         }
 
         // (((0..15) * 4) * (1..8)) * 120
         // maps to (0..57600)
-        let so1 = (so1 as u16 * self.so1_volume as u16) * 120;
-        let so2 = (so2 as u16 * self.so2_volume as u16) * 120;
+        let so1 = (so1 * self.so1_volume as u32 * LEVEL_SCALE / UNITY).min(MAX_LEVEL);
+        let so2 = (so2 * self.so2_volume as u32 * LEVEL_SCALE / UNITY).min(MAX_LEVEL);
 
-        (so2, so1)
+        (so2 as u16, so1 as u16)
+    }
+
+    pub fn channel_control(&self, channel: Channel) -> ChannelControl {
+        self.controls[channel as usize]
+    }
+
+    pub fn set_channel_control(&mut self, channel: Channel, control: ChannelControl) {
+        self.controls[channel as usize] = control;
+        self.update_weights();
+    }
+
+    fn update_weights(&mut self) {
+        let solo = self.controls.iter().any(|c| c.solo);
+
+        for i in 0..4 {
+            let control = &self.controls[i];
+            let audible = !control.mute && (control.solo || !solo);
+            let gain = if audible { control.gain.max(0.0) } else { 0.0 };
+
+            // so1 is the right side, so2 the left
+            let (right, left) = match control.pan {
+                Some(pan) => {
+                    let pan = pan.clamp(-1.0, 1.0);
+                    ((1.0 + pan).min(1.0), (1.0 - pan).min(1.0))
+                }
+                None => (
+                    (self.so1_masks[i] != 0) as u8 as f32,
+                    (self.so2_masks[i] != 0) as u8 as f32,
+                ),
+            };
+
+            let weight = |side: f32| (gain * side * UNITY as f32) as u32;
+            self.weights[i] = [weight(right), weight(left)];
+        }
     }
 
     pub fn set_volume(&mut self, data: u8) {
@@ -50,5 +141,113 @@ impl Mixer {
             self.so2_masks[i] = MASK_VEC[data & 0b01];
             data >>= 1;
         }
+        self.update_weights();
+    }
+}
+
+impl Savestate for Mixer {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_bytes(&self.so1_masks);
+        w.write_bytes(&self.so2_masks);
+        w.write_u8(self.so1_volume);
+        w.write_u8(self.so2_volume);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> std::io::Result<()> {
+        r.read_bytes(&mut self.so1_masks)?;
+        r.read_bytes(&mut self.so2_masks)?;
+        self.so1_volume = r.read_u8()?;
+        self.so2_volume = r.read_u8()?;
+        self.update_weights();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// one channel at 15 with volume 8
+    const FULL: u16 = (15 * 8 * LEVEL_SCALE) as u16;
+
+    /// Full volume, every channel on both sides.
+    fn mixer() -> Mixer {
+        let mut mixer = Mixer::new();
+        mixer.set_volume(0x77);
+        mixer.set_output(0xff);
+        mixer
+    }
+
+    fn control(mixer: &mut Mixer, channel: Channel, f: impl FnOnce(&mut ChannelControl)) {
+        let mut control = mixer.channel_control(channel);
+        f(&mut control);
+        mixer.set_channel_control(channel, control);
+    }
+
+    #[test]
+    fn game_mixing() {
+        let mut mixer = mixer();
+        assert_eq!(mixer.mix([15, 0, 0, 0]), (FULL, FULL));
+        assert_eq!(mixer.mix([15, 15, 15, 15]), (4 * FULL, 4 * FULL));
+
+        // square 1 on so1, the right side, at volume 1
+        mixer.set_output(0x01);
+        mixer.set_volume(0x70);
+        assert_eq!(mixer.mix([15, 15, 0, 0]), (0, FULL / 8));
+    }
+
+    #[test]
+    fn solo_overrides_the_others() {
+        let mut mixer = mixer();
+        control(&mut mixer, Channel::Wave, |c| c.solo = true);
+        assert_eq!(mixer.mix([15, 15, 15, 15]), (FULL, FULL));
+        control(&mut mixer, Channel::Noise, |c| c.solo = true);
+        assert_eq!(mixer.mix([15, 15, 15, 15]), (2 * FULL, 2 * FULL));
+
+        // a muted solo stays silent
+        control(&mut mixer, Channel::Wave, |c| c.mute = true);
+        assert_eq!(mixer.mix([15, 15, 15, 15]), (FULL, FULL));
+    }
+
+    #[test]
+    fn mute() {
+        let mut mixer = mixer();
+        control(&mut mixer, Channel::Square1, |c| c.mute = true);
+        assert_eq!(mixer.mix([15, 15, 0, 0]), (FULL, FULL));
+        control(&mut mixer, Channel::Square1, |c| c.mute = false);
+        assert_eq!(mixer.mix([15, 15, 0, 0]), (2 * FULL, 2 * FULL));
+    }
+
+    #[test]
+    fn pan_overrides_nr51() {
+        let mut mixer = mixer();
+        // right only
+        mixer.set_output(0x01);
+        control(&mut mixer, Channel::Square1, |c| c.pan = Some(-1.0));
+        assert_eq!(mixer.mix([15, 0, 0, 0]), (FULL, 0));
+        control(&mut mixer, Channel::Square1, |c| c.pan = Some(0.0));
+        assert_eq!(mixer.mix([15, 0, 0, 0]), (FULL, FULL));
+        control(&mut mixer, Channel::Square1, |c| c.pan = Some(0.5));
+        assert_eq!(mixer.mix([15, 0, 0, 0]), (FULL / 2, FULL));
+
+        // the game's panning is back without one
+        control(&mut mixer, Channel::Square1, |c| c.pan = None);
+        assert_eq!(mixer.mix([15, 0, 0, 0]), (0, FULL));
+    }
+
+    #[test]
+    fn gain_scales() {
+        let mut mixer = mixer();
+        control(&mut mixer, Channel::Square2, |c| c.gain = 0.5);
+        assert_eq!(mixer.mix([0, 15, 0, 0]), (FULL / 2, FULL / 2));
+        control(&mut mixer, Channel::Square2, |c| c.gain = 2.0);
+        assert_eq!(mixer.mix([0, 15, 0, 0]), (2 * FULL, 2 * FULL));
+
+        // but never past the loudest mix
+        for &channel in Channel::ALL.iter() {
+            control(&mut mixer, channel, |c| c.gain = 2.0);
+        }
+        let loudest = MAX_LEVEL as u16;
+        assert_eq!(mixer.mix([15, 15, 15, 15]), (loudest, loudest));
     }
 }
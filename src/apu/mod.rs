@@ -2,19 +2,27 @@
 // Note: https://www.reddit.com/r/EmuDev/comments/5gkwi5/gb_apu_sound_emulation/
 mod parts;
 
+mod filter;
 mod mixer;
 mod noise;
+mod recorder;
 mod resampler;
+mod speaker;
 mod square;
 mod wave;
 
-pub use self::mixer::Mixer;
+pub use self::filter::{HighPass, OutputFilter};
+pub use self::mixer::{Channel, ChannelControl, Mixer};
 pub use self::noise::Noise;
 pub use self::parts::*;
+pub use self::recorder::WavRecorder;
 pub use self::square::Square;
 pub use self::wave::Wave;
 
-use resampler::StereoBlipBuf;
+use crate::state::{Savestate, StateReader, StateWriter};
+use filter::Filter;
+use resampler::{MonoBlipBuf, StereoBlipBuf};
+use speaker::Speaker;
 
 pub struct Apu {
     frameseq: FrameSequencer,
@@ -23,15 +31,40 @@ pub struct Apu {
     noise: Noise,
     wave: Wave,
     mixer: Mixer,
+    /// the cart's speaker, outside the apu but heard with it
+    speaker: Speaker,
 
     regs: [u8; 0x30],
     sound_enable: bool,
 
+    sample_rate: u32,
+    /// output samples per emulated second, relative to `sample_rate`
+    rate_adjust: f64,
     resampler: StereoBlipBuf,
+    filter: Filter,
+    /// each channel resampled on its own, when asked for
+    channel_resamplers: Option<Box<[MonoBlipBuf; 4]>>,
+    /// gets a copy of the output while recording
+    recorder: Option<WavRecorder>,
 }
 
+/// resamplers hold a few frames, the output must be read more often
+fn buffer_size(sample_rate: u32) -> u32 {
+    sample_rate / 30
+}
+
+/// level of a channel in its own stream, as loud as in the mix at full
+/// volume
+const CHANNEL_SCALE: i32 = 8 * mixer::LEVEL_SCALE as i32;
+/// level of the cart's speaker, half a channel at full volume
+const SPEAKER_LEVEL: u16 = (15 * 8 * mixer::LEVEL_SCALE / 2) as u16;
+/// furthest `set_rate_adjust` moves the rate, 0.5% is too little to hear
+const MAX_RATE_ADJUST: f64 = 0.005;
+
 impl Apu {
-    pub fn new() -> Self {
+    /// An apu resampling its output to `sample_rate` samples per second,
+    /// filtered like the model's output.
+    pub fn new(cgb: bool, sample_rate: u32) -> Self {
         Apu {
             frameseq: FrameSequencer::new(),
             square1: Square::new(),
@@ -39,15 +72,21 @@ impl Apu {
             noise: Noise::new(),
             wave: Wave::new(),
             mixer: Mixer::new(),
+            speaker: Speaker::new(),
 
             regs: [0u8; 0x30],
             sound_enable: false,
 
+            sample_rate,
+            rate_adjust: 1.0,
             resampler: StereoBlipBuf::new(
-                crate::AUDIO_FREQUENCY / 30,
+                buffer_size(sample_rate),
                 crate::GB_CLOCK_SPEED,
-                crate::AUDIO_FREQUENCY,
+                sample_rate,
             ),
+            filter: Filter::new(OutputFilter::hardware(cgb), sample_rate as f64),
+            channel_resamplers: None,
+            recorder: None,
         }
     }
 
@@ -148,6 +187,20 @@ impl Apu {
             | ((self.square1.is_on() as u8) << 0)
     }
 
+    /// Powers the apu up from scratch, the output settings are kept.
+    pub fn reset(&mut self) {
+        self.frameseq = FrameSequencer::new();
+        self.square1 = Square::new();
+        self.square2 = Square::new();
+        self.noise = Noise::new();
+        self.wave = Wave::new();
+        self.mixer.set_volume(0);
+        self.mixer.set_output(0);
+
+        self.regs = [0u8; 0x30];
+        self.sound_enable = false;
+    }
+
     fn sound_off(&mut self) {
         for addr in 0xff10..0xff30 {
             if addr != 0xff26 {
@@ -158,23 +211,33 @@ impl Apu {
     }
 
     pub fn update(&mut self, clocks: u32) {
-        if self.sound_enable {
-            for _ in 0..clocks {
+        for _ in 0..clocks {
+            let (so1, so2) = if self.sound_enable {
                 self.frame_sequence();
 
-                let (so1, so2) = self.mixer.mix([
+                let chs = [
                     self.square1.next(),
                     self.square2.next(),
                     self.wave.next(),
                     self.noise.next(),
-                ]);
+                ];
+                if let Some(resamplers) = &mut self.channel_resamplers {
+                    for (resampler, &ch) in resamplers.iter_mut().zip(chs.iter()) {
+                        resampler.push(ch as i32 * CHANNEL_SCALE);
+                    }
+                }
+                self.mixer.mix(chs)
+            } else {
+                if let Some(resamplers) = &mut self.channel_resamplers {
+                    resamplers.iter_mut().for_each(|r| r.push(0));
+                }
+                (0, 0)
+            };
 
-                self.resampler.push((so1, so2));
-            }
-        } else {
-            for _ in 0..clocks {
-                self.resampler.push((0, 0));
-            }
+            // the speaker plays on both sides, whatever the sound registers
+            let speaker = self.speaker.next() as u16 * SPEAKER_LEVEL;
+            self.resampler
+                .push((so1.saturating_add(speaker), so2.saturating_add(speaker)));
         }
     }
 
@@ -197,8 +260,167 @@ impl Apu {
         }
     }
 
-    pub fn output(&mut self, cb: impl FnMut(&[i16])) {
-        self.resampler.output(cb);
+    pub fn output(&mut self, mut cb: impl FnMut(&[i16])) {
+        let filter = &mut self.filter;
+        let recorder = &mut self.recorder;
+        self.resampler.output(|buf| {
+            filter.apply(buf);
+            if let Some(recorder) = recorder {
+                recorder.write(buf);
+            }
+            cb(buf);
+        });
+    }
+
+    /// Plays a tone of the cartridge's speaker, `None` when it's silent.
+    pub fn set_speaker_tone(&mut self, tone: Option<u8>) {
+        self.speaker.set_tone(tone);
+    }
+
+    /// Samples per second of `output`.
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Changes the output rate, dropping the samples not read yet. A
+    /// recording in progress is finished, its header holds the old rate.
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.recorder = None;
+        self.sample_rate = sample_rate;
+        self.resampler =
+            StereoBlipBuf::new(buffer_size(sample_rate), crate::GB_CLOCK_SPEED, sample_rate);
+        if self.channel_resamplers.is_some() {
+            self.set_channel_output(true);
+        }
+        self.set_rate_adjust(self.rate_adjust);
+    }
+
+    /// Makes slightly more or fewer samples than `sample_rate` per emulated
+    /// second, so a player can keep its queue from running dry or growing
+    /// when the host's audio clock drifts from the emulation's. A ratio of
+    /// 1.0 is the exact rate, it's kept within 0.5% of it.
+    pub fn set_rate_adjust(&mut self, ratio: f64) {
+        let ratio = ratio.clamp(1.0 - MAX_RATE_ADJUST, 1.0 + MAX_RATE_ADJUST);
+        self.rate_adjust = ratio;
+        let sample_rate = self.sample_rate as f64 * ratio;
+        self.resampler.set_rates(crate::GB_CLOCK_SPEED, sample_rate);
+        self.filter.set_rate(sample_rate);
+        if let Some(recorder) = &mut self.recorder {
+            recorder.set_sample_rate(sample_rate);
+        }
+        if let Some(resamplers) = &mut self.channel_resamplers {
+            for resampler in resamplers.iter_mut() {
+                resampler.set_rates(crate::GB_CLOCK_SPEED, sample_rate);
+            }
+        }
+    }
+
+    pub fn rate_adjust(&self) -> f64 {
+        self.rate_adjust
+    }
+
+    pub fn filter(&self) -> OutputFilter {
+        self.filter.config()
+    }
+
+    /// Changes the analog filters on the output.
+    pub fn set_filter(&mut self, filter: OutputFilter) {
+        let sample_rate = self.sample_rate as f64 * self.rate_adjust;
+        self.filter.set_config(filter, sample_rate);
+    }
+
+    /// Records everything `output` returns to a wav file, until
+    /// `stop_recording`.
+    pub fn start_recording<P: AsRef<std::path::Path>>(&mut self, path: P) -> std::io::Result<()> {
+        self.stop_recording()?;
+        let sample_rate = self.sample_rate as f64 * self.rate_adjust;
+        self.recorder = Some(WavRecorder::create(path, sample_rate)?);
+        Ok(())
+    }
+
+    /// Finishes the recording, returning its first error.
+    pub fn stop_recording(&mut self) -> std::io::Result<()> {
+        match self.recorder.take() {
+            Some(recorder) => recorder.finish(),
+            None => Ok(()),
+        }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recorder.is_some()
+    }
+
+    /// Resamples each channel into a stream of its own too, before panning,
+    /// volume and the host side controls. The streams must then be drained
+    /// by `channel_output` as often as `output`.
+    pub fn set_channel_output(&mut self, enable: bool) {
+        self.channel_resamplers = if enable {
+            let sample_rate = self.sample_rate as f64 * self.rate_adjust;
+            let resampler = || {
+                let mut resampler = MonoBlipBuf::new(
+                    buffer_size(self.sample_rate),
+                    crate::GB_CLOCK_SPEED,
+                    self.sample_rate,
+                );
+                resampler.set_rates(crate::GB_CLOCK_SPEED, sample_rate);
+                resampler
+            };
+            Some(Box::new([
+                resampler(),
+                resampler(),
+                resampler(),
+                resampler(),
+            ]))
+        } else {
+            None
+        };
+    }
+
+    /// Mono samples of each channel since the last call, when enabled by
+    /// `set_channel_output`.
+    pub fn channel_output(&mut self, mut cb: impl FnMut(Channel, &[i16])) {
+        if let Some(resamplers) = &mut self.channel_resamplers {
+            for (resampler, &channel) in resamplers.iter_mut().zip(Channel::ALL.iter()) {
+                resampler.output(|buf| cb(channel, buf));
+            }
+        }
+    }
+
+    pub fn channel_control(&self, channel: Channel) -> ChannelControl {
+        self.mixer.channel_control(channel)
+    }
+
+    /// Mutes, solos, amplifies or pans a channel on the host side, the
+    /// game sees no difference.
+    pub fn set_channel_control(&mut self, channel: Channel, control: ChannelControl) {
+        self.mixer.set_channel_control(channel, control);
+    }
+}
+
+impl Savestate for Apu {
+    fn save_state(&self, w: &mut StateWriter) {
+        self.frameseq.save_state(w);
+        self.square1.save_state(w);
+        self.square2.save_state(w);
+        self.noise.save_state(w);
+        self.wave.save_state(w);
+        self.mixer.save_state(w);
+
+        w.write_bytes(&self.regs);
+        w.write_bool(self.sound_enable);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> std::io::Result<()> {
+        self.frameseq.load_state(r)?;
+        self.square1.load_state(r)?;
+        self.square2.load_state(r)?;
+        self.noise.load_state(r)?;
+        self.wave.load_state(r)?;
+        self.mixer.load_state(r)?;
+
+        r.read_bytes(&mut self.regs)?;
+        self.sound_enable = r.read_bool()?;
+        Ok(())
     }
 }
 
@@ -208,12 +430,32 @@ pub enum ChannelMode {
     Off,
 }
 
+impl ChannelMode {
+    fn from_bool(on: bool) -> Self {
+        if on {
+            ChannelMode::On
+        } else {
+            ChannelMode::Off
+        }
+    }
+}
+
 #[derive(Eq, PartialEq)]
 pub enum DacMode {
     On,
     Off,
 }
 
+impl DacMode {
+    fn from_bool(on: bool) -> Self {
+        if on {
+            DacMode::On
+        } else {
+            DacMode::Off
+        }
+    }
+}
+
 const TRIGGER_MASK: u8 = 0b1000_0000;
 const DUTY_MASK: u8 = 0b1100_0000;
 
@@ -228,3 +470,96 @@ fn freq_high(freq: u32, data: u8) -> u32 {
 fn freq_low(freq: u32, data: u8) -> u32 {
     (freq & 0xff00) | data as u32
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// stereo samples `output` returns over a second of emulation
+    fn samples_per_second(apu: &mut Apu) -> u32 {
+        let mut samples = 0;
+        for _ in 0..60 {
+            apu.update(crate::GB_CLOCK_SPEED / 60);
+            apu.output(|buf| samples += buf.len() as u32 / 2);
+        }
+        samples
+    }
+
+    fn assert_near(samples: u32, rate: f64) {
+        // blip_buf holds back a few samples until the next frame
+        let diff = (samples as f64 - rate).abs();
+        assert!(diff < 32.0, "{} samples for {}", samples, rate);
+    }
+
+    #[test]
+    fn samples_follow_the_sample_rate() {
+        for &rate in [22050, 44100, 48000].iter() {
+            let mut apu = Apu::new(false, rate);
+            assert_near(samples_per_second(&mut apu), rate as f64);
+        }
+
+        let mut apu = Apu::new(false, 48000);
+        apu.set_sample_rate(32000);
+        assert_eq!(apu.sample_rate(), 32000);
+        assert_near(samples_per_second(&mut apu), 32000.0);
+    }
+
+    #[test]
+    fn rate_adjust_is_clamped() {
+        let mut apu = Apu::new(false, 48000);
+        apu.set_rate_adjust(1.002);
+        assert_eq!(apu.rate_adjust(), 1.002);
+        assert_near(samples_per_second(&mut apu), 48096.0);
+
+        apu.set_rate_adjust(1.5);
+        assert_eq!(apu.rate_adjust(), 1.005);
+        assert_near(samples_per_second(&mut apu), 48240.0);
+        apu.set_rate_adjust(0.5);
+        assert_eq!(apu.rate_adjust(), 0.995);
+        assert_near(samples_per_second(&mut apu), 47760.0);
+
+        // kept when the rate changes
+        apu.set_sample_rate(44100);
+        assert_eq!(apu.rate_adjust(), 0.995);
+        assert_near(samples_per_second(&mut apu), 43879.5);
+    }
+
+    #[test]
+    fn channel_streams_match_the_stereo_stream() {
+        let mut apu = Apu::new(false, 44100);
+        apu.set_channel_output(true);
+        for frame in 0..60 {
+            // the rate moving between frames too
+            apu.set_rate_adjust(if frame % 2 == 0 { 1.005 } else { 0.995 });
+            apu.update(crate::GB_CLOCK_SPEED / 60);
+
+            let mut stereo = 0;
+            apu.output(|buf| stereo += buf.len() / 2);
+            let mut channels = [0; 4];
+            apu.channel_output(|channel, buf| channels[channel as usize] += buf.len());
+            assert_eq!(channels, [stereo; 4], "frame {}", frame);
+        }
+    }
+
+    #[test]
+    fn loud_mix_is_not_clipped_before_the_high_pass() {
+        // the loudest mix with the speaker on top
+        let loudest = (15 * 4 * 8 * mixer::LEVEL_SCALE) as u16 + SPEAKER_LEVEL;
+        let mut apu = Apu::new(false, 48000);
+        apu.set_filter(OutputFilter::hardware(false));
+
+        // a second of it, read every frame
+        let mut left: Vec<i16> = Vec::new();
+        for _ in 0..60 {
+            for _ in 0..crate::GB_CLOCK_SPEED / 60 {
+                apu.resampler.push((loudest, loudest));
+            }
+            apu.output(|buf| left.extend(buf.iter().step_by(2)));
+        }
+
+        // saturated while the capacitor charges from the whole level, it
+        // would be falling already if the resampler had clamped it
+        assert!(left[20..50].iter().all(|&s| s == i16::MAX));
+        assert!(left[left.len() - 1].abs() < 100);
+    }
+}
@@ -8,6 +8,7 @@
 // Noise:    Timer -> LFSR -> Length Counter -> Envelope -> Mixer
 
 use super::*;
+use crate::state::{Savestate, StateReader, StateWriter};
 
 pub struct Noise {
     rand: LFSR,
@@ -96,3 +97,22 @@ impl Noise {
         self.dac == DacMode::On
     }
 }
+
+impl Savestate for Noise {
+    fn save_state(&self, w: &mut StateWriter) {
+        self.rand.save_state(w);
+        self.envelope.save_state(w);
+        self.counter.save_state(w);
+        w.write_bool(self.is_on());
+        w.write_bool(self.is_dac_on());
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> std::io::Result<()> {
+        self.rand.load_state(r)?;
+        self.envelope.load_state(r)?;
+        self.counter.load_state(r)?;
+        self.mode = ChannelMode::from_bool(r.read_bool()?);
+        self.dac = DacMode::from_bool(r.read_bool()?);
+        Ok(())
+    }
+}
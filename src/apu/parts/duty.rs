@@ -1,4 +1,5 @@
 use super::Timer;
+use crate::state::{Savestate, StateReader, StateWriter};
 
 const SQUARE_WAVE: [[u8; 8]; 4] = [
     [0, 0, 0, 0, 0, 0, 0, 1],
@@ -51,3 +52,19 @@ impl Duty {
         self.freq
     }
 }
+
+impl Savestate for Duty {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_usize(self.duty);
+        w.write_usize(self.step);
+        w.write_u32(self.freq);
+        self.timer.save_state(w);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> std::io::Result<()> {
+        self.duty = r.read_usize()? % SQUARE_WAVE.len();
+        self.step = r.read_usize()? % 8;
+        self.freq = r.read_u32()?;
+        self.timer.load_state(r)
+    }
+}
@@ -1,3 +1,5 @@
+use crate::state::{Savestate, StateReader, StateWriter};
+
 pub enum EnvelopeMode {
     Inc,
     Dec,
@@ -73,3 +75,25 @@ impl Envelope {
         };
     }
 }
+
+impl Savestate for Envelope {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u8(self.period);
+        w.write_u8(self.counter);
+        w.write_u8(self.volume);
+        w.write_u8(self.start_volume);
+        w.write_bool(match self.mode {
+            EnvelopeMode::Inc => true,
+            EnvelopeMode::Dec => false,
+        });
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> std::io::Result<()> {
+        self.period = r.read_u8()?;
+        self.counter = r.read_u8()?;
+        self.volume = r.read_u8()?;
+        self.start_volume = r.read_u8()?;
+        self.set_increment(r.read_bool()?);
+        Ok(())
+    }
+}
@@ -1,5 +1,6 @@
 use super::freq_to_period;
 use super::Timer;
+use crate::state::{Savestate, StateReader, StateWriter};
 
 const FRAME_SEQUENCER_FREQUENCY: u32 = 512;
 
@@ -29,3 +30,16 @@ impl FrameSequencer {
         }
     }
 }
+
+impl Savestate for FrameSequencer {
+    fn save_state(&self, w: &mut StateWriter) {
+        self.timer.save_state(w);
+        w.write_u8(self.step);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> std::io::Result<()> {
+        self.timer.load_state(r)?;
+        self.step = r.read_u8()? % 8;
+        Ok(())
+    }
+}
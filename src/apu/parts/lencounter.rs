@@ -1,3 +1,5 @@
+use crate::state::{Savestate, StateReader, StateWriter};
+
 #[derive(Eq, PartialEq)]
 pub enum CounterMode {
     Counter,
@@ -56,3 +58,16 @@ impl LengthCounter {
         self.mode == CounterMode::Counter
     }
 }
+
+impl Savestate for LengthCounter {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u16(self.counter);
+        w.write_bool(self.is_on());
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> std::io::Result<()> {
+        self.counter = r.read_u16()?;
+        self.set_mode_on(r.read_bool()?);
+        Ok(())
+    }
+}
@@ -1,4 +1,5 @@
 use super::Timer;
+use crate::state::{Savestate, StateReader, StateWriter};
 
 const DIVISOR: [u32; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
 
@@ -59,3 +60,22 @@ impl LFSR {
         self.shift_reg = 0x7fff;
     }
 }
+
+impl Savestate for LFSR {
+    fn save_state(&self, w: &mut StateWriter) {
+        self.timer.save_state(w);
+        w.write_u16(self.shift_reg);
+        w.write_bool(self.mode == WidthMode::Low);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> std::io::Result<()> {
+        self.timer.load_state(r)?;
+        self.shift_reg = r.read_u16()?;
+        self.mode = if r.read_bool()? {
+            WidthMode::Low
+        } else {
+            WidthMode::High
+        };
+        Ok(())
+    }
+}
@@ -1,4 +1,5 @@
 use super::Duty;
+use crate::state::{Savestate, StateReader, StateWriter};
 
 pub struct Sweep {
     pub(crate) period: u8,
@@ -82,3 +83,22 @@ impl Sweep {
 fn wave_timer_period(frequency: u32) -> u32 {
     (2048 - frequency) * 4
 }
+
+impl Savestate for Sweep {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u8(self.period);
+        w.write_u8(self.counter);
+        w.write_bool(self.negate);
+        w.write_u8(self.shift);
+        w.write_u32(self.shadow_freq);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> std::io::Result<()> {
+        self.period = r.read_u8()?;
+        self.counter = r.read_u8()?;
+        self.negate = r.read_bool()?;
+        self.shift = r.read_u8()?;
+        self.shadow_freq = r.read_u32()?;
+        Ok(())
+    }
+}
@@ -1,3 +1,5 @@
+use crate::state::{Savestate, StateReader, StateWriter};
+
 pub struct Timer {
     period: u32,
     counter: u32,
@@ -29,3 +31,16 @@ impl Timer {
         self.counter = self.period;
     }
 }
+
+impl Savestate for Timer {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u32(self.period);
+        w.write_u32(self.counter);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> std::io::Result<()> {
+        self.period = r.read_u32()?;
+        self.counter = r.read_u32()?;
+        Ok(())
+    }
+}
@@ -1,4 +1,5 @@
 use super::Timer;
+use crate::state::{Savestate, StateReader, StateWriter};
 
 const INIT_TABLE: [u8; 32] = [
     0x08, 0x04, 0x04, 0x00, 0x04, 0x03, 0x0A, 0x0A, 0x02, 0x0D, 0x07, 0x08, 0x09, 0x02, 0x03, 0x0C,
@@ -65,3 +66,22 @@ impl WaveTable {
         self.timer.reset();
     }
 }
+
+impl Savestate for WaveTable {
+    fn save_state(&self, w: &mut StateWriter) {
+        self.timer.save_state(w);
+        w.write_u32(self.freq);
+        w.write_usize(self.index);
+        w.write_bytes(&self.wave_table);
+        w.write_u8(self.sample_buffer);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> std::io::Result<()> {
+        self.timer.load_state(r)?;
+        self.freq = r.read_u32()?;
+        self.index = r.read_usize()? % 32;
+        r.read_bytes(&mut self.wave_table)?;
+        self.sample_buffer = r.read_u8()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,200 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// size of the riff and wave headers before the samples
+const HEADER_SIZE: u32 = 44;
+const CHANNELS: u16 = 2;
+const BYTES_PER_SAMPLE: u16 = 2;
+
+/// Writes interleaved 16-bit stereo samples to a wav file.
+///
+/// The sizes in the header are filled in by `finish`, or when dropped. So is
+/// the rate, as the average of the rates the samples were written at.
+pub struct WavRecorder {
+    writer: Option<BufWriter<File>>,
+    /// rate of the samples written now
+    sample_rate: f64,
+    /// emulated seconds of the samples written
+    seconds: f64,
+    /// bytes of samples written
+    data_size: u32,
+    /// first write error, returned by `finish`
+    error: Option<io::Error>,
+}
+
+impl WavRecorder {
+    pub fn create<P: AsRef<Path>>(path: P, sample_rate: f64) -> io::Result<Self> {
+        let mut recorder = WavRecorder {
+            writer: Some(BufWriter::new(File::create(path)?)),
+            sample_rate,
+            seconds: 0.0,
+            data_size: 0,
+            error: None,
+        };
+        recorder.write_header()?;
+        Ok(recorder)
+    }
+
+    /// Changes the rate of the samples written next, when the output rate
+    /// is adjusted.
+    pub fn set_sample_rate(&mut self, sample_rate: f64) {
+        self.sample_rate = sample_rate;
+    }
+
+    /// Average rate of the samples written, or the current one before any.
+    fn header_rate(&self) -> u32 {
+        let frames = self.data_size / (CHANNELS * BYTES_PER_SAMPLE) as u32;
+        if self.seconds > 0.0 {
+            (frames as f64 / self.seconds).round() as u32
+        } else {
+            self.sample_rate.round() as u32
+        }
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        let sample_rate = self.header_rate();
+        let w = self.writer.as_mut().unwrap();
+        let block_align = CHANNELS * BYTES_PER_SAMPLE;
+
+        w.write_all(b"RIFF")?;
+        w.write_all(&(HEADER_SIZE - 8 + self.data_size).to_le_bytes())?;
+        w.write_all(b"WAVE")?;
+
+        w.write_all(b"fmt ")?;
+        w.write_all(&16u32.to_le_bytes())?;
+        // pcm
+        w.write_all(&1u16.to_le_bytes())?;
+        w.write_all(&CHANNELS.to_le_bytes())?;
+        w.write_all(&sample_rate.to_le_bytes())?;
+        w.write_all(&(sample_rate * block_align as u32).to_le_bytes())?;
+        w.write_all(&block_align.to_le_bytes())?;
+        w.write_all(&(BYTES_PER_SAMPLE * 8).to_le_bytes())?;
+
+        w.write_all(b"data")?;
+        w.write_all(&self.data_size.to_le_bytes())
+    }
+
+    /// Appends interleaved left and right samples. Errors are kept for
+    /// `finish`, nothing more is written after one.
+    // io::Error::other is newer than the rust this builds with
+    #[allow(clippy::io_other_error)]
+    pub fn write(&mut self, samples: &[i16]) {
+        if self.error.is_some() {
+            return;
+        }
+        let w = self.writer.as_mut().unwrap();
+
+        // the sizes in the header are 32 bits
+        let size = samples.len() as u32 * BYTES_PER_SAMPLE as u32;
+        if self.data_size.checked_add(size + HEADER_SIZE).is_none() {
+            self.error = Some(io::Error::new(
+                io::ErrorKind::Other,
+                "wav recording too long",
+            ));
+            return;
+        }
+
+        let result = samples
+            .iter()
+            .try_for_each(|sample| w.write_all(&sample.to_le_bytes()));
+        match result {
+            Ok(()) => {
+                self.data_size += size;
+                self.seconds += samples.len() as f64 / CHANNELS as f64 / self.sample_rate;
+            }
+            Err(e) => self.error = Some(e),
+        }
+    }
+
+    /// Seconds recorded so far.
+    pub fn duration(&self) -> f64 {
+        self.seconds
+    }
+
+    /// Fills in the header and closes the file.
+    pub fn finish(mut self) -> io::Result<()> {
+        self.close()
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        if let Some(e) = self.error.take() {
+            self.writer = None;
+            return Err(e);
+        }
+        if self.writer.is_none() {
+            return Ok(());
+        }
+
+        self.writer.as_mut().unwrap().seek(SeekFrom::Start(0))?;
+        self.write_header()?;
+        let file = self.writer.take().unwrap().into_inner()?;
+        file.sync_all()
+    }
+}
+
+impl Drop for WavRecorder {
+    fn drop(&mut self) {
+        let _ = self.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u32_at(data: &[u8], i: usize) -> u32 {
+        u32::from_le_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]])
+    }
+
+    fn record(name: &str, write: impl FnOnce(&mut WavRecorder)) -> Vec<u8> {
+        let path = std::env::temp_dir().join(format!("joy-{}-{}.wav", name, std::process::id()));
+        let mut recorder = WavRecorder::create(&path, 48000.0).unwrap();
+        write(&mut recorder);
+        recorder.finish().unwrap();
+        let data = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        data
+    }
+
+    #[test]
+    fn header_sizes() {
+        let data = record("sizes", |recorder| {
+            recorder.write(&[1, -1, 2, -2]);
+            recorder.write(&[0x1234, 0x5678]);
+            assert_eq!(recorder.duration(), 3.0 / 48000.0);
+        });
+        assert_eq!(data.len(), HEADER_SIZE as usize + 12);
+
+        assert_eq!(&data[0..4], b"RIFF");
+        assert_eq!(u32_at(&data, 4), data.len() as u32 - 8);
+        assert_eq!(&data[8..16], b"WAVEfmt ");
+        assert_eq!(u32_at(&data, 24), 48000);
+        // bytes per second
+        assert_eq!(u32_at(&data, 28), 48000 * 4);
+        assert_eq!(&data[36..40], b"data");
+        assert_eq!(u32_at(&data, 40), 12);
+        assert_eq!(&data[44..48], &[0x01, 0x00, 0xff, 0xff]);
+    }
+
+    #[test]
+    fn empty_recording() {
+        let data = record("empty", |_| {});
+        assert_eq!(data.len(), HEADER_SIZE as usize);
+        assert_eq!(u32_at(&data, 4), HEADER_SIZE - 8);
+        assert_eq!(u32_at(&data, 40), 0);
+    }
+
+    #[test]
+    fn adjusted_rate() {
+        let data = record("adjusted", |recorder| {
+            // a tenth of a second at each rate
+            recorder.write(&[0; 2 * 4800]);
+            recorder.set_sample_rate(48240.0);
+            recorder.write(&[0; 2 * 4824]);
+            assert!((recorder.duration() - 0.2).abs() < 1e-9);
+        });
+        assert_eq!(u32_at(&data, 24), 48120);
+        assert_eq!(u32_at(&data, 28), 48120 * 4);
+    }
+}
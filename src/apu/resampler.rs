@@ -1,5 +1,9 @@
 use blip_buf::BlipBuf;
 
+/// the mix is resampled at this fraction of its level, it goes up to 57600
+/// and blip_buf clamps to i16 before the filters take out its dc offset
+pub const HEADROOM: i32 = 2;
+
 pub struct StereoBlipBuf {
     left_buf: BlipBuf,
     right_buf: BlipBuf,
@@ -24,30 +28,89 @@ impl StereoBlipBuf {
         }
     }
 
+    pub fn set_rates(&mut self, clock_rate: u32, sample_rate: f64) {
+        self.left_buf.set_rates(clock_rate as f64, sample_rate);
+        self.right_buf.set_rates(clock_rate as f64, sample_rate);
+    }
+
     pub fn push(&mut self, sample: (u16, u16)) {
+        let left = sample.0 as i32 / HEADROOM;
+        let right = sample.1 as i32 / HEADROOM;
         self.clocks += 1;
         self.left_buf
-            .add_delta(self.clocks, sample.0 as i32 - self.left_sample);
+            .add_delta(self.clocks, left - self.left_sample);
         self.right_buf
-            .add_delta(self.clocks, sample.1 as i32 - self.right_sample);
-        self.left_sample = sample.0 as i32;
-        self.right_sample = sample.1 as i32;
+            .add_delta(self.clocks, right - self.right_sample);
+        self.left_sample = left;
+        self.right_sample = right;
     }
 
-    pub fn output(&mut self, mut cb: impl FnMut(&[i16])) {
+    pub fn output(&mut self, mut cb: impl FnMut(&mut [i16])) {
         self.left_buf.end_frame(self.clocks);
         self.right_buf.end_frame(self.clocks);
 
         self.clocks = 0;
 
-        let buf = &mut [0i16; 2049];
+        // read each side on its own, from 0.1.6 blip_buf writes stereo reads
+        // past the end of the buffer, and Cargo.toml allows 0.1.6
+        let left = &mut [0i16; 1024];
+        let right = &mut [0i16; 1024];
+        let buf = &mut [0i16; 2048];
         while self.left_buf.samples_avail() > 0 {
-            let count1 = self.left_buf.read_samples(buf, true);
-            let count2 = self.right_buf.read_samples(&mut buf[1..], true);
+            let count1 = self.left_buf.read_samples(left, false);
+            let count2 = self.right_buf.read_samples(right, false);
 
             assert!(count1 == count2);
 
-            cb(&buf[..(count1 + count2)]);
+            for (frame, (l, r)) in buf.chunks_mut(2).zip(left.iter().zip(right.iter())) {
+                frame[0] = *l;
+                frame[1] = *r;
+            }
+
+            cb(&mut buf[..(count1 + count2)]);
+        }
+    }
+}
+
+/// One channel on its own, for oscilloscopes and stems.
+pub struct MonoBlipBuf {
+    buf: BlipBuf,
+    sample: i32,
+    clocks: u32,
+}
+
+impl MonoBlipBuf {
+    pub fn new(sample_count: u32, clock_rate: u32, sample_rate: u32) -> Self {
+        let mut buf = BlipBuf::new(sample_count);
+        buf.set_rates(clock_rate as f64, sample_rate as f64);
+
+        Self {
+            buf,
+            sample: 0,
+            clocks: 0,
+        }
+    }
+
+    pub fn set_rates(&mut self, clock_rate: u32, sample_rate: f64) {
+        self.buf.set_rates(clock_rate as f64, sample_rate);
+    }
+
+    pub fn push(&mut self, sample: i32) {
+        self.clocks += 1;
+        if sample != self.sample {
+            self.buf.add_delta(self.clocks, sample - self.sample);
+            self.sample = sample;
+        }
+    }
+
+    pub fn output(&mut self, mut cb: impl FnMut(&[i16])) {
+        self.buf.end_frame(self.clocks);
+        self.clocks = 0;
+
+        let buf = &mut [0i16; 1024];
+        while self.buf.samples_avail() > 0 {
+            let count = self.buf.read_samples(buf, false);
+            cb(&buf[..count]);
         }
     }
 }
@@ -0,0 +1,89 @@
+use crate::GB_CLOCK_SPEED;
+
+/// pitch of tone 0, in Hz
+const BASE_FREQ: f64 = 1000.0;
+
+/// The piezo speaker of carts like the HuC3, playing a square wave.
+///
+/// Which pitch each of the 16 tones has isn't documented, they are played
+/// as semitones up from `BASE_FREQ`.
+pub struct Speaker {
+    tone: Option<u8>,
+    /// clocks per half wave of the tone
+    half_period: u32,
+    clocks: u32,
+    high: bool,
+}
+
+impl Speaker {
+    pub fn new() -> Self {
+        Speaker {
+            tone: None,
+            half_period: 0,
+            clocks: 0,
+            high: false,
+        }
+    }
+
+    /// Plays a tone from 0 to 15, `None` silences the speaker.
+    pub fn set_tone(&mut self, tone: Option<u8>) {
+        if tone == self.tone {
+            return;
+        }
+        self.tone = tone;
+        self.clocks = 0;
+        self.high = false;
+
+        if let Some(tone) = tone {
+            let freq = BASE_FREQ * 2f64.powf((tone & 0x0f) as f64 / 12.0);
+            self.half_period = (GB_CLOCK_SPEED as f64 / freq / 2.0) as u32;
+        }
+    }
+
+    /// 1 while the wave is high, 0 while low or silent.
+    pub fn next(&mut self) -> u8 {
+        if self.tone.is_none() {
+            return 0;
+        }
+
+        self.clocks += 1;
+        if self.clocks >= self.half_period {
+            self.clocks = 0;
+            self.high = !self.high;
+        }
+        self.high as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// rising edges in a second of clocks
+    fn cycles_per_second(speaker: &mut Speaker) -> u32 {
+        let mut last = 0;
+        let mut edges = 0;
+        for _ in 0..GB_CLOCK_SPEED {
+            let level = speaker.next();
+            edges += (level > last) as u32;
+            last = level;
+        }
+        edges
+    }
+
+    #[test]
+    fn tones_are_semitones() {
+        let mut speaker = Speaker::new();
+        assert_eq!(cycles_per_second(&mut speaker), 0);
+
+        speaker.set_tone(Some(0));
+        assert_eq!(cycles_per_second(&mut speaker), 1000);
+        speaker.set_tone(Some(12));
+        // an octave up, within rounding of the period
+        let freq = cycles_per_second(&mut speaker);
+        assert!((1998..=2002).contains(&freq), "{}", freq);
+
+        speaker.set_tone(None);
+        assert_eq!(cycles_per_second(&mut speaker), 0);
+    }
+}
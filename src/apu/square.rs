@@ -9,6 +9,7 @@
 // Square 2:          Timer -> Duty -> Length Counter -> Envelope -> Mixer
 
 use super::*;
+use crate::state::{Savestate, StateReader, StateWriter};
 
 pub struct Square {
     duty: Duty,
@@ -122,3 +123,24 @@ impl Square {
 fn wave_timer_period(frequency: u32) -> u32 {
     (2048 - frequency) * 4
 }
+
+impl Savestate for Square {
+    fn save_state(&self, w: &mut StateWriter) {
+        self.duty.save_state(w);
+        self.counter.save_state(w);
+        self.envelope.save_state(w);
+        self.sweep.save_state(w);
+        w.write_bool(self.is_on());
+        w.write_bool(self.is_dac_on());
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> std::io::Result<()> {
+        self.duty.load_state(r)?;
+        self.counter.load_state(r)?;
+        self.envelope.load_state(r)?;
+        self.sweep.load_state(r)?;
+        self.mode = ChannelMode::from_bool(r.read_bool()?);
+        self.dac = DacMode::from_bool(r.read_bool()?);
+        Ok(())
+    }
+}
@@ -13,6 +13,7 @@
 // FF3F 0000 1111	Samples 30 and 31
 
 use super::*;
+use crate::state::{Savestate, StateReader, StateWriter};
 
 const VOLUME_SHIFT: [u8; 4] = [4, 0, 1, 2];
 
@@ -121,3 +122,22 @@ impl Wave {
 fn wave_timer_period(frequency: u32) -> u32 {
     (2048 - frequency) * 2
 }
+
+impl Savestate for Wave {
+    fn save_state(&self, w: &mut StateWriter) {
+        self.wave_table.save_state(w);
+        self.counter.save_state(w);
+        w.write_u8(self.volume_shift);
+        w.write_bool(self.is_on());
+        w.write_bool(self.is_dac_on());
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> std::io::Result<()> {
+        self.wave_table.load_state(r)?;
+        self.counter.load_state(r)?;
+        self.volume_shift = r.read_u8()?;
+        self.mode = ChannelMode::from_bool(r.read_bool()?);
+        self.dac = DacMode::from_bool(r.read_bool()?);
+        Ok(())
+    }
+}
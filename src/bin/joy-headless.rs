@@ -0,0 +1,94 @@
+use joy::runner::{ExitCondition, Outcome};
+use joy::*;
+
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "Joy headless",
+    about = "Runs a gameboy test rom without display.\n\nExits with 0 on pass, 1 on fail, 2 on timeout and 3 on errors."
+)]
+struct Args {
+    /// Gameboy cartridge.
+    #[structopt(name = "FILE")]
+    file: String,
+
+    /// Maximum frames to run.
+    #[structopt(short = "f", long = "frames", default_value = "3600")]
+    frames: u32,
+
+    /// Pass once the serial output contains the text.
+    #[structopt(long = "serial-pass")]
+    serial_pass: Option<String>,
+
+    /// Fail once the serial output contains the text.
+    #[structopt(long = "serial-fail")]
+    serial_fail: Option<String>,
+
+    /// Stop at `LD B,B`, mooneye's fibonacci registers pass.
+    #[structopt(short = "b", long = "breakpoint")]
+    breakpoint: bool,
+
+    /// Check blargg's result signature at 0xa000.
+    #[structopt(long = "signature")]
+    signature: bool,
+
+    /// Print the serial output when done.
+    #[structopt(short = "p", long = "print-serial")]
+    print_serial: bool,
+}
+
+fn main() {
+    let args = Args::from_args();
+
+    let mut runner = match Runner::load(&args.file) {
+        Ok(runner) => runner,
+        Err(e) => {
+            eprintln!("load cartridge failed: {}", e);
+            std::process::exit(3);
+        }
+    };
+
+    let mut conditions = Vec::new();
+    if let Some(text) = args.serial_pass {
+        conditions.push(ExitCondition::SerialPass(text));
+    }
+    if let Some(text) = args.serial_fail {
+        conditions.push(ExitCondition::SerialFail(text));
+    }
+    if args.breakpoint {
+        conditions.push(ExitCondition::Breakpoint);
+    }
+    if args.signature {
+        conditions.push(ExitCondition::Signature);
+    }
+
+    let outcome = runner.run(args.frames, &conditions);
+
+    if args.print_serial {
+        println!("{}", runner.serial_output());
+    }
+
+    let code = match outcome {
+        Outcome::Passed => 0,
+        Outcome::Failed(reason) => {
+            println!("failed: {}", reason);
+            1
+        }
+        Outcome::Breakpoint => {
+            println!("breakpoint: {:?}", runner.gameboy().registers());
+            1
+        }
+        // with nothing to wait for, running all frames is a success
+        Outcome::Timeout if conditions.is_empty() => 0,
+        Outcome::Timeout => {
+            println!("timeout after {} frames", args.frames);
+            2
+        }
+    };
+
+    if code == 0 {
+        println!("passed");
+    }
+    std::process::exit(code);
+}
@@ -0,0 +1,64 @@
+use joy::Gameboy;
+
+use std::time::{Duration, Instant};
+
+/// battery saves are written once the game stops writing for this long
+const DELAY: Duration = Duration::from_secs(5);
+/// but no later than this after the first write, for games that never stop,
+/// like the camera's viewfinder
+const MAX_DELAY: Duration = Duration::from_secs(60);
+
+/// Writes the battery save of a gameboy some time after the game wrote it.
+pub struct Autosave {
+    /// battery writes seen last, and when they changed
+    writes: u64,
+    changed: Instant,
+    /// when the battery memory first differed from the save file
+    dirty_since: Option<Instant>,
+    /// writes when a save last failed, it's only retried after new ones
+    failed: Option<u64>,
+}
+
+impl Autosave {
+    pub fn new() -> Self {
+        Autosave {
+            writes: 0,
+            changed: Instant::now(),
+            dirty_since: None,
+            failed: None,
+        }
+    }
+
+    /// Saves if it's time, reporting only the first of failing saves.
+    pub fn update(&mut self, gameboy: &mut Gameboy) {
+        if !gameboy.save_dirty() {
+            self.dirty_since = None;
+            return;
+        }
+
+        let now = Instant::now();
+        let writes = gameboy.save_writes();
+        if writes != self.writes {
+            self.writes = writes;
+            self.changed = now;
+        }
+        let dirty_since = *self.dirty_since.get_or_insert(now);
+        if now - self.changed < DELAY && now - dirty_since < MAX_DELAY {
+            return;
+        }
+        if self.failed == Some(writes) {
+            return;
+        }
+
+        match gameboy.save_game() {
+            Ok(()) => self.failed = None,
+            Err(e) => {
+                if self.failed.is_none() {
+                    eprintln!("autosave failed, retrying after the next write: {}", e);
+                }
+                self.failed = Some(writes);
+                self.dirty_since = Some(now);
+            }
+        }
+    }
+}
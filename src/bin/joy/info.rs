@@ -0,0 +1,55 @@
+use joy::cart::CartridgeHeader;
+
+/// Prints the cartridge header of a rom file.
+pub fn run(file: &str) -> Result<(), String> {
+    let rom = std::fs::read(file).map_err(|e| format!("read {} failed: {}", file, e))?;
+    let header = CartridgeHeader::parse(&rom).map_err(|e| e.to_string())?;
+
+    let check = |valid| if valid { "ok" } else { "bad" };
+    let manufacturer = &header.manufacturer_code;
+
+    println!("title           : {}", header.title);
+    if manufacturer
+        .iter()
+        .all(|b| b.is_ascii_uppercase() || b.is_ascii_digit())
+    {
+        println!(
+            "manufacturer    : {}",
+            String::from_utf8_lossy(manufacturer)
+        );
+    }
+    println!("licensee        : {}", header.licensee());
+    println!(
+        "cart type       : 0x{:02x} - {}",
+        header.cart_type,
+        header.cart_type_name()
+    );
+    println!("rom size        : 0x{:06x}", header.rom_size);
+    println!("ram size        : 0x{:06x}", header.ram_size);
+    println!("cgb flag        : 0x{:02x}", header.cgb_flag);
+    println!("sgb flag        : 0x{:02x}", header.sgb_flag);
+    println!(
+        "destination     : {}",
+        match header.destination {
+            0x00 => "japan",
+            _ => "overseas",
+        }
+    );
+    println!("version         : 0x{:02x}", header.version);
+    println!("logo            : {}", check(header.logo_valid()));
+    println!(
+        "header checksum : 0x{:02x} {}",
+        header.header_checksum,
+        check(header.header_checksum_valid())
+    );
+    println!(
+        "global checksum : 0x{:04x} {}",
+        header.global_checksum,
+        check(header.global_checksum_valid())
+    );
+    if header.rom_size != rom.len() {
+        println!("file size       : 0x{:06x} mismatch", rom.len());
+    }
+
+    Ok(())
+}
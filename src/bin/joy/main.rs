@@ -0,0 +1,653 @@
+use joy::debug::Stop;
+use joy::*;
+
+use sdl2::audio::{AudioQueue, AudioSpecDesired};
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::keyboard::{KeyboardState, Scancode};
+use sdl2::messagebox::{show_simple_message_box, MessageBoxFlag};
+use sdl2::pixels::PixelFormatEnum;
+use structopt::clap::{Error, ErrorKind};
+use structopt::StructOpt;
+
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+mod autosave;
+mod info;
+mod repl;
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "Joy", about = "A gameboy emulator.")]
+struct Args {
+    #[structopt(subcommand)]
+    command: Option<Command>,
+
+    /// Gameboy cartridge.
+    #[structopt(name = "FILE")]
+    file: Option<String>,
+
+    /// Second cartridge, linked to the first one by cable.
+    #[structopt(short = "l", long = "link")]
+    link: Option<String>,
+
+    /// Window scaling.
+    #[structopt(short = "s", long = "scale", default_value = "2")]
+    scale: u32,
+
+    /// Link port device: null, stdout.
+    #[structopt(long = "serial", default_value = "null")]
+    serial: String,
+
+    /// Boot rom to start from, a cgb one colorizes dmg carts.
+    #[structopt(short = "b", long = "boot-rom")]
+    boot_rom: Option<String>,
+
+    /// Colors of dmg carts: black-white, classic, bgb, kirokaze, mist or a
+    /// palette file.
+    #[structopt(
+        short = "p",
+        long = "palette",
+        conflicts_with_all = &["colorize", "boot-rom"]
+    )]
+    palette: Option<String>,
+
+    /// Color dmg carts like a cgb does, by title or by held buttons such as
+    /// up+a.
+    #[structopt(long = "colorize", conflicts_with = "boot-rom")]
+    colorize: Option<Option<String>>,
+
+    /// Picture the Pocket Camera sees, a binary pgm image, a test pattern
+    /// without one.
+    #[structopt(long = "camera")]
+    camera: Option<String>,
+
+    /// Directory for battery saves, instead of next to the rom.
+    #[structopt(long = "save-dir")]
+    save_dir: Option<String>,
+
+    /// Battery save to start from instead, from another emulator or a flash
+    /// cart.
+    #[structopt(long = "import-save")]
+    import_save: Option<String>,
+
+    /// Audio output rate, the sound card may pick another.
+    #[structopt(long = "sample-rate")]
+    sample_rate: Option<u32>,
+
+    /// Audio kept queued in milliseconds, the output rate follows it closely.
+    #[structopt(long = "audio-latency", default_value = "50")]
+    audio_latency: u32,
+
+    /// Capacitor on the audio output: off, dmg or cgb, the model's by
+    /// default.
+    #[structopt(long = "high-pass")]
+    high_pass: Option<String>,
+
+    /// Low pass cutoff in Hz, muffling the sound like the built in speaker.
+    #[structopt(long = "low-pass")]
+    low_pass: Option<f32>,
+
+    /// Start in the debugger, F12 breaks into it while running.
+    #[structopt(short = "d", long = "debug")]
+    debug: bool,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Print the cartridge header.
+    Info {
+        #[structopt(name = "FILE")]
+        file: String,
+    },
+    /// Write the battery save of a rom for another emulator or a flash cart.
+    ExportSave {
+        #[structopt(name = "FILE")]
+        file: String,
+        #[structopt(name = "OUTPUT")]
+        output: String,
+        /// Leave out the clock after the ram.
+        #[structopt(long = "raw")]
+        raw: bool,
+    },
+}
+
+enum Session {
+    Single(Box<Gameboy>),
+    Linked(Box<LinkedPair>),
+}
+
+impl Session {
+    fn gameboys(&self) -> &[Gameboy] {
+        match self {
+            Session::Single(gameboy) => std::slice::from_ref(gameboy.as_ref()),
+            Session::Linked(pair) => pair.gameboys(),
+        }
+    }
+
+    fn gameboys_mut(&mut self) -> &mut [Gameboy] {
+        match self {
+            Session::Single(gameboy) => std::slice::from_mut(gameboy.as_mut()),
+            Session::Linked(pair) => pair.gameboys_mut(),
+        }
+    }
+
+    /// The gameboy to debug, linked gameboys can't be debugged.
+    fn single_mut(&mut self) -> Option<&mut Gameboy> {
+        match self {
+            Session::Single(gameboy) => Some(gameboy),
+            Session::Linked(_) => None,
+        }
+    }
+
+    fn emulate(&mut self, cycles: u32, keyboard: &KeyboardState) {
+        match self {
+            Session::Single(gameboy) => {
+                gameboy.set_tilt(tilt_input(keyboard));
+                gameboy.emulate(cycles, player1_input(keyboard))
+            }
+            Session::Linked(pair) => {
+                pair.emulate(cycles, [player1_input(keyboard), player2_input(keyboard)])
+            }
+        }
+    }
+}
+
+fn player1_input(keyboard: &KeyboardState) -> JoypadState {
+    JoypadState {
+        left: keyboard.is_scancode_pressed(Scancode::Left),
+        right: keyboard.is_scancode_pressed(Scancode::Right),
+        up: keyboard.is_scancode_pressed(Scancode::Up),
+        down: keyboard.is_scancode_pressed(Scancode::Down),
+        start: keyboard.is_scancode_pressed(Scancode::C),
+        select: keyboard.is_scancode_pressed(Scancode::V),
+        button_a: keyboard.is_scancode_pressed(Scancode::Z),
+        button_b: keyboard.is_scancode_pressed(Scancode::X),
+    }
+}
+
+/// Tilt sensor carts use the second player's keys, a full g each way.
+fn tilt_input(keyboard: &KeyboardState) -> TiltState {
+    let axis = |negative, positive| {
+        keyboard.is_scancode_pressed(positive) as i32 as f32
+            - keyboard.is_scancode_pressed(negative) as i32 as f32
+    };
+    TiltState {
+        x: axis(Scancode::J, Scancode::L),
+        y: axis(Scancode::I, Scancode::K),
+    }
+}
+
+fn player2_input(keyboard: &KeyboardState) -> JoypadState {
+    JoypadState {
+        left: keyboard.is_scancode_pressed(Scancode::J),
+        right: keyboard.is_scancode_pressed(Scancode::L),
+        up: keyboard.is_scancode_pressed(Scancode::I),
+        down: keyboard.is_scancode_pressed(Scancode::K),
+        start: keyboard.is_scancode_pressed(Scancode::Return),
+        select: keyboard.is_scancode_pressed(Scancode::RShift),
+        button_a: keyboard.is_scancode_pressed(Scancode::N),
+        button_b: keyboard.is_scancode_pressed(Scancode::M),
+    }
+}
+
+/// Loads a cartridge, telling the user in a message box when it fails.
+fn load(file: &str, save_dir: Option<&Path>) -> Result<Cartridge, String> {
+    load_cartridge_with_saves(file, save_dir, ClockSource::Host).map_err(|e| {
+        let msg = format!("load {} failed: {}", file, e);
+        let _ = show_simple_message_box(MessageBoxFlag::ERROR, "Joy", &msg, None);
+        msg
+    })
+}
+
+/// Channel toggled by 1-4 and F1-F4.
+fn channel_key(key: Keycode) -> apu::Channel {
+    match key {
+        Keycode::Num1 | Keycode::F1 => apu::Channel::Square1,
+        Keycode::Num2 | Keycode::F2 => apu::Channel::Square2,
+        Keycode::Num3 | Keycode::F3 => apu::Channel::Wave,
+        _ => apu::Channel::Noise,
+    }
+}
+
+/// Changes the channel controls of every gameboy, then prints them.
+fn update_channels(
+    session: &mut Session,
+    mut update: impl FnMut(apu::Channel, &mut apu::ChannelControl),
+) {
+    for gameboy in session.gameboys_mut() {
+        for &channel in apu::Channel::ALL.iter() {
+            let mut control = gameboy.channel_control(channel);
+            update(channel, &mut control);
+            gameboy.set_channel_control(channel, control);
+        }
+    }
+
+    let gameboy = &session.gameboys()[0];
+    let status = apu::Channel::ALL
+        .iter()
+        .map(|&channel| {
+            let control = gameboy.channel_control(channel);
+            let state = match (control.mute, control.solo) {
+                (true, _) => "muted",
+                (false, true) => "solo",
+                (false, false) => "on",
+            };
+            format!("{:?} {}", channel, state)
+        })
+        .collect::<Vec<_>>();
+    println!("channels: {}", status.join(", "));
+}
+
+/// Starts or stops recording the audio to a wav file.
+fn toggle_recording(gameboy: &mut Gameboy, file: &str) {
+    if gameboy.is_recording() {
+        match gameboy.stop_recording() {
+            Ok(_) => println!("recording saved: {}", file),
+            Err(e) => eprintln!("recording failed: {}", e),
+        }
+    } else {
+        match gameboy.start_recording(file) {
+            Ok(_) => println!("recording: {}", file),
+            Err(e) => eprintln!("recording failed: {}", e),
+        }
+    }
+}
+
+/// Output rate ratio that brings `queued` samples back to `target`, the
+/// host's audio clock never runs exactly as fast as the emulation.
+fn rate_adjust(queued: u32, target: u32) -> f64 {
+    // 0.5%, too little to hear the pitch change
+    const MAX_ADJUST: f64 = 0.005;
+    let error = 1.0 - queued as f64 / target as f64;
+    1.0 + (error * MAX_ADJUST).clamp(-MAX_ADJUST, MAX_ADJUST)
+}
+
+/// Writes the battery save, telling the user when it fails.
+fn save_game(gameboy: &mut Gameboy) -> bool {
+    match gameboy.save_game() {
+        Ok(_) => true,
+        Err(e) => {
+            eprintln!("save game failed: {}", e);
+            false
+        }
+    }
+}
+
+/// Parses the `--high-pass` setting.
+fn parse_high_pass(name: &str) -> Result<apu::HighPass, String> {
+    match name.to_lowercase().as_str() {
+        "off" => Ok(apu::HighPass::Off),
+        "dmg" => Ok(apu::HighPass::Dmg),
+        "cgb" => Ok(apu::HighPass::Cgb),
+        other => Err(format!("unknown high pass: {}", other)),
+    }
+}
+
+/// Parses buttons held at boot, like `left+b`.
+fn parse_keys(keys: &str) -> Result<JoypadState, String> {
+    let mut state = JoypadState::default();
+    for key in keys.split('+') {
+        match key.trim().to_lowercase().as_str() {
+            "left" => state.left = true,
+            "right" => state.right = true,
+            "up" => state.up = true,
+            "down" => state.down = true,
+            "a" => state.button_a = true,
+            "b" => state.button_b = true,
+            other => return Err(format!("unknown button: {}", other)),
+        }
+    }
+    Ok(state)
+}
+
+fn export_save(file: &str, output: &str, raw: bool, save_dir: Option<&Path>) -> Result<(), String> {
+    let cart =
+        load_cartridge_with_saves(file, save_dir, ClockSource::Host).map_err(|e| e.to_string())?;
+    let format = if raw {
+        cart::SaveFormat::Raw
+    } else {
+        cart::SaveFormat::RtcFooter
+    };
+    cart.export_save(output, format)
+        .map_err(|e| format!("export {} failed: {}", output, e))
+}
+
+fn main() -> Result<(), String> {
+    let args = Args::from_args();
+    let save_dir = args.save_dir.as_ref().map(Path::new);
+    let file = match (&args.command, &args.file) {
+        (Some(Command::Info { file }), _) => return info::run(file),
+        (Some(Command::ExportSave { file, output, raw }), _) => {
+            return export_save(file, output, *raw, save_dir)
+        }
+        (None, Some(file)) => file,
+        (None, None) => Error::with_description(
+            "The following required arguments were not provided:\n    <FILE>",
+            ErrorKind::MissingRequiredArgument,
+        )
+        .exit(),
+    };
+
+    let boot_rom = match &args.boot_rom {
+        Some(file) => {
+            Some(bus::BootRom::load(file).map_err(|e| format!("load boot rom failed: {}", e))?)
+        }
+        None => None,
+    };
+    let palette = match &args.palette {
+        Some(name) => Some(match ppu::DmgPalette::preset(name) {
+            Some(palette) => palette,
+            None => ppu::DmgPalette::load(name)
+                .map_err(|e| format!("load palette {} failed: {}", name, e))?,
+        }),
+        None => None,
+    };
+    let colorize = match &args.colorize {
+        Some(Some(keys)) => Some(parse_keys(keys)?),
+        Some(None) => Some(JoypadState::default()),
+        None => None,
+    };
+    let high_pass = match &args.high_pass {
+        Some(name) => Some(parse_high_pass(name)?),
+        None => None,
+    };
+    let low_pass = args.low_pass;
+    let camera = match &args.camera {
+        Some(file) => Some(
+            cart::StillImage::load(file)
+                .map_err(|e| format!("load camera image {} failed: {}", file, e))?,
+        ),
+        None => None,
+    };
+    let new_gameboy = |cart| {
+        let mut gameboy = match &boot_rom {
+            Some(boot_rom) => Gameboy::with_boot_rom(cart, boot_rom.clone()),
+            None => Gameboy::new(cart),
+        };
+        if let Some(palette) = &palette {
+            gameboy.set_dmg_palette(palette);
+        }
+        if let Some(keys) = &colorize {
+            gameboy.colorize(keys);
+        }
+        if let Some(image) = &camera {
+            gameboy.set_image_source(Box::new(image.clone()));
+        }
+        let mut filter = gameboy.audio_filter();
+        filter.high_pass = high_pass.unwrap_or(filter.high_pass);
+        filter.low_pass = low_pass;
+        gameboy.set_audio_filter(filter);
+        gameboy
+    };
+
+    let mut cart = load(file, save_dir)?;
+    if let Some(save) = &args.import_save {
+        cart.import_save(save)
+            .map_err(|e| format!("import save {} failed: {}", save, e))?;
+    }
+    let mut titles = vec![cart.title()];
+
+    let mut gameboy = new_gameboy(cart);
+    match args.serial.as_str() {
+        "null" => gameboy.set_serial_link(Box::new(bus::NullLink)),
+        "stdout" => gameboy.set_serial_link(Box::new(bus::PrintLink)),
+        other => return Err(format!("unknown serial device: {}", other)),
+    }
+
+    let mut session = if let Some(file) = args.link {
+        let cart = load(&file, save_dir)?;
+        titles.push(cart.title());
+        Session::Linked(Box::new(LinkedPair::new(gameboy, new_gameboy(cart))))
+    } else {
+        Session::Single(Box::new(gameboy))
+    };
+    let screens = titles.len() as u32;
+
+    if args.debug && screens > 1 {
+        return Err("linked gameboys can't be debugged".to_owned());
+    }
+    let mut debugger = if args.debug {
+        Some(Debugger::new())
+    } else {
+        None
+    };
+    let mut break_in = args.debug;
+
+    let sdl_context = sdl2::init()?;
+
+    // window
+    let video_system = sdl_context.video()?;
+    let window = video_system
+        .window(
+            format!("Joy - {}", titles.join(" <-> ")).as_str(),
+            GB_LCD_WIDTH as u32 * args.scale * screens,
+            GB_LCD_HEIGHT as u32 * args.scale,
+        )
+        .resizable()
+        .position_centered()
+        .build()
+        .map_err(|e| e.to_string())?;
+
+    let mut canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
+    let texture_creator = canvas.texture_creator();
+    let mut texture = texture_creator
+        .create_texture_streaming(
+            PixelFormatEnum::RGB24,
+            GB_LCD_WIDTH as u32 * screens,
+            GB_LCD_HEIGHT as u32,
+        )
+        .map_err(|e| e.to_string())?;
+
+    // audio
+    let audio_system = sdl_context.audio()?;
+    let audio_spec = AudioSpecDesired {
+        freq: Some(args.sample_rate.unwrap_or(AUDIO_FREQUENCY) as i32),
+        channels: Some(2),
+        samples: None,
+    };
+    let audio_device: AudioQueue<i16> = audio_system.open_queue(None, &audio_spec)?;
+    {
+        let spec = audio_device.spec();
+        println!("audio spec: ");
+        println!("    driver: {}", audio_system.current_audio_driver());
+        println!("    channels: {}", spec.channels);
+        println!("    frequency: {}", spec.freq);
+        println!("    buffer size: {} * {}", spec.samples, spec.channels);
+    }
+    let sample_rate = audio_device.spec().freq as u32;
+    for gameboy in session.gameboys_mut() {
+        gameboy.set_sample_rate(sample_rate);
+    }
+    // in stereo samples of two i16s, as the queue's size is in bytes
+    let target_queued = sample_rate * args.audio_latency.max(1) / 1000;
+    audio_device.resume();
+
+    let mut event_pump = sdl_context.event_pump()?;
+    let mut paused = false;
+
+    let state_files = titles
+        .iter()
+        .enumerate()
+        .map(|(i, title)| match screens {
+            1 => title.to_lowercase() + ".state",
+            _ => format!("{}.{}.state", title.to_lowercase(), i),
+        })
+        .collect::<Vec<_>>();
+
+    // only the first gameboy is audible, so only it is recorded
+    let wav_file = titles[0].to_lowercase() + ".wav";
+
+    const INTERVAL: Duration = Duration::from_nanos(16666667);
+    let mut time = Instant::now() - INTERVAL;
+    let mut autosaves = session
+        .gameboys()
+        .iter()
+        .map(|_| autosave::Autosave::new())
+        .collect::<Vec<_>>();
+
+    // main loop
+    'running: loop {
+        let mut cycles = (time.elapsed().as_secs_f32() * GB_CLOCK_SPEED as f32) as u32;
+        time = Instant::now();
+
+        // events
+        {
+            for event in event_pump.poll_iter() {
+                match event {
+                    Event::Quit { .. }
+                    | Event::KeyDown {
+                        keycode: Some(Keycode::Escape),
+                        ..
+                    } => break 'running,
+                    Event::KeyDown {
+                        keycode: Some(key), ..
+                    } => match key {
+                        Keycode::LShift => paused = !paused,
+                        Keycode::S => {
+                            for gameboy in session.gameboys_mut() {
+                                if save_game(gameboy) {
+                                    println!("game saved");
+                                }
+                            }
+                        }
+                        Keycode::Tab => cycles *= 2,
+                        Keycode::R => toggle_recording(&mut session.gameboys_mut()[0], &wav_file),
+                        Keycode::Num1 | Keycode::Num2 | Keycode::Num3 | Keycode::Num4 => {
+                            let channel = channel_key(key);
+                            update_channels(&mut session, |c, control| {
+                                if c == channel {
+                                    control.mute = !control.mute;
+                                }
+                            });
+                        }
+                        Keycode::F1 | Keycode::F2 | Keycode::F3 | Keycode::F4 => {
+                            let channel = channel_key(key);
+                            update_channels(&mut session, |c, control| {
+                                if c == channel {
+                                    control.solo = !control.solo;
+                                }
+                            });
+                        }
+                        Keycode::Num0 => update_channels(&mut session, |_, control| {
+                            *control = apu::ChannelControl::default()
+                        }),
+                        Keycode::F12 if screens == 1 => break_in = true,
+                        Keycode::F5 => {
+                            for (gameboy, file) in session.gameboys().iter().zip(&state_files) {
+                                match std::fs::write(file, gameboy.save_state()) {
+                                    Ok(_) => println!("state saved: {}", file),
+                                    Err(e) => eprintln!("save state failed: {}", e),
+                                }
+                            }
+                        }
+                        Keycode::F8 => {
+                            for (gameboy, file) in
+                                session.gameboys_mut().iter_mut().zip(&state_files)
+                            {
+                                match std::fs::read(file) {
+                                    Ok(state) => {
+                                        if let Err(e) = gameboy.load_state(&state) {
+                                            eprintln!("load state failed: {}", e);
+                                            gameboy.reset();
+                                        } else {
+                                            println!("state loaded: {}", file);
+                                        }
+                                    }
+                                    Err(e) => eprintln!("read state failed: {}", e),
+                                }
+                            }
+                        }
+                        _ => {}
+                    },
+                    _ => {}
+                }
+            }
+        }
+
+        // debugger, blocks on stdin
+        if break_in {
+            break_in = false;
+
+            let gameboy = session.single_mut().unwrap();
+            let debugger = debugger.get_or_insert_with(Debugger::new);
+            if let repl::Action::Quit = repl::run(debugger, gameboy) {
+                break 'running;
+            }
+
+            time = Instant::now();
+            continue 'running;
+        }
+
+        if !paused {
+            // emulate
+            {
+                let keyboard = event_pump.keyboard_state();
+                match (session.single_mut(), debugger.as_mut()) {
+                    (Some(gameboy), Some(debugger)) => {
+                        gameboy.set_input(player1_input(&keyboard));
+                        let stop = debugger.run(gameboy, cycles);
+                        if stop != Stop::Timeout {
+                            repl::report(&stop);
+                            break_in = true;
+                        }
+                    }
+                    _ => session.emulate(cycles, &keyboard),
+                }
+            }
+
+            // audio, only the first gameboy is audible
+            {
+                let queued = audio_device.size() / 4;
+                // far behind after fast forward, start over rather than lag
+                if queued > target_queued * 4 {
+                    audio_device.clear();
+                }
+                let ratio = rate_adjust(queued, target_queued);
+                for (i, gameboy) in session.gameboys_mut().iter_mut().enumerate() {
+                    gameboy.set_rate_adjust(ratio);
+                    gameboy.apu_output(|buf| {
+                        if i == 0 {
+                            audio_device.queue(buf);
+                        }
+                    });
+                }
+            }
+
+            // graphics
+            {
+                texture.with_lock(None, |buffer: &mut [u8], pitch: usize| {
+                    const LINE: usize = GB_LCD_WIDTH * 3;
+
+                    for (i, gameboy) in session.gameboys().iter().enumerate() {
+                        let frame = gameboy.get_frame_buffer();
+                        for (y, line) in frame.chunks(LINE).enumerate() {
+                            buffer[y * pitch + i * LINE..][..LINE].copy_from_slice(line);
+                        }
+                    }
+                })?;
+                canvas.copy(&texture, None, None)?;
+                canvas.present();
+            }
+        }
+
+        // autosave
+        for (gameboy, autosave) in session.gameboys_mut().iter_mut().zip(&mut autosaves) {
+            autosave.update(gameboy);
+        }
+
+        std::thread::sleep(INTERVAL.checked_sub(time.elapsed()).unwrap_or_default());
+    }
+
+    if session.gameboys()[0].is_recording() {
+        toggle_recording(&mut session.gameboys_mut()[0], &wav_file);
+    }
+
+    for gameboy in session.gameboys_mut() {
+        if gameboy.save_dirty() {
+            save_game(gameboy);
+        }
+    }
+
+    Ok(())
+}
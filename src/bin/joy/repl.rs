@@ -0,0 +1,205 @@
+use joy::debug::{Breakpoint, Debugger, Stop};
+use joy::{disasm, Gameboy, GB_CLOCK_SPEED};
+
+use std::io::{BufRead, Write};
+use std::ops::RangeInclusive;
+
+/// clocks `next` and `finish` may run before giving up, 10 seconds
+const RUN_LIMIT: u32 = GB_CLOCK_SPEED * 10;
+
+const HELP: &str = "\
+commands:
+    c, continue          resume emulation
+    s, step [n]          execute n instructions
+    n, next              step over calls
+    f, finish            run until the current function returns
+    b, break ADDR        break at pc
+    o, opcode OP         break before opcode, cbxx for prefixed ones
+    w, watch ADDR[-END]  break after a write
+    rw, rwatch ADDR[-END]
+                         break after a read
+    d, delete N          delete breakpoint
+    i, info              list breakpoints
+    r, regs              show registers
+    x ADDR [LEN]         dump memory
+    l, list [ADDR] [N]   disassemble, from pc by default
+    q, quit              exit
+    (empty)              repeat the last command
+addresses, opcodes and lengths are hex";
+
+pub enum Action {
+    Continue,
+    Quit,
+}
+
+/// Reads debugger commands from stdin until told to continue or quit.
+pub fn run(debugger: &mut Debugger, gameboy: &mut Gameboy) -> Action {
+    gameboy.debug_output();
+
+    let stdin = std::io::stdin();
+    let mut lines = stdin.lock().lines();
+    let mut last = String::new();
+
+    loop {
+        print!("(joy) ");
+        let _ = std::io::stdout().flush();
+
+        let line = match lines.next() {
+            Some(Ok(line)) => line,
+            _ => return Action::Quit,
+        };
+        let line = if line.trim().is_empty() {
+            last.clone()
+        } else {
+            line
+        };
+
+        let args = line.split_whitespace().collect::<Vec<_>>();
+        let (command, args) = match args.split_first() {
+            Some((command, args)) => (*command, args),
+            None => continue,
+        };
+
+        match execute(debugger, gameboy, command, args) {
+            Ok(Some(action)) => return action,
+            Ok(None) => {}
+            Err(e) => println!("{}", e),
+        }
+        last = line;
+    }
+}
+
+fn execute(
+    debugger: &mut Debugger,
+    gameboy: &mut Gameboy,
+    command: &str,
+    args: &[&str],
+) -> Result<Option<Action>, String> {
+    match command {
+        "c" | "continue" => return Ok(Some(Action::Continue)),
+        "q" | "quit" => return Ok(Some(Action::Quit)),
+        "h" | "help" => println!("{}", HELP),
+
+        "s" | "step" => {
+            let count = match args.first() {
+                Some(n) => n.parse::<u32>().map_err(|e| e.to_string())?,
+                None => 1,
+            };
+            for _ in 0..count {
+                let stop = debugger.step(gameboy);
+                if stop != Stop::Done {
+                    report(&stop);
+                    break;
+                }
+            }
+            gameboy.debug_output();
+        }
+        "n" | "next" => {
+            report(&debugger.step_over(gameboy, RUN_LIMIT));
+            gameboy.debug_output();
+        }
+        "f" | "finish" => {
+            report(&debugger.run_to_return(gameboy, RUN_LIMIT));
+            gameboy.debug_output();
+        }
+
+        "b" | "break" => {
+            let addr = parse_hex(arg(args, 0)?)?;
+            debugger.add_breakpoint(Breakpoint::Pc(addr));
+        }
+        "o" | "opcode" => {
+            let op = parse_hex(arg(args, 0)?)?;
+            debugger.add_breakpoint(Breakpoint::Opcode(op));
+        }
+        "w" | "watch" => {
+            let range = parse_range(arg(args, 0)?)?;
+            debugger.add_breakpoint(Breakpoint::Write(range));
+        }
+        "rw" | "rwatch" => {
+            let range = parse_range(arg(args, 0)?)?;
+            debugger.add_breakpoint(Breakpoint::Read(range));
+        }
+        "d" | "delete" => {
+            let index = arg(args, 0)?.parse::<usize>().map_err(|e| e.to_string())?;
+            if debugger.remove_breakpoint(index).is_none() {
+                return Err(format!("no breakpoint {}", index));
+            }
+        }
+        "i" | "info" => {
+            for (i, breakpoint) in debugger.breakpoints().iter().enumerate() {
+                println!("{:>3}: {:x?}", i, breakpoint);
+            }
+        }
+
+        "r" | "regs" => gameboy.debug_output(),
+        "x" => {
+            let addr = parse_hex(arg(args, 0)?)?;
+            let len = match args.get(1) {
+                Some(len) => parse_hex(len)?,
+                None => 0x40,
+            };
+            dump(gameboy, addr, len);
+        }
+        "l" | "list" => {
+            let addr = match args.first() {
+                Some(addr) => parse_hex(addr)?,
+                None => gameboy.registers().pc,
+            };
+            let count = match args.get(1) {
+                Some(count) => parse_hex(count)?,
+                None => 0x10,
+            };
+            for line in disasm::disassemble(gameboy, addr, count as usize) {
+                println!("{}", line);
+            }
+        }
+
+        _ => return Err(format!("unknown command: {}, try help", command)),
+    }
+
+    Ok(None)
+}
+
+/// Prints why the debugger stopped, if it wasn't simply done.
+pub fn report(stop: &Stop) {
+    match stop {
+        Stop::Done => {}
+        Stop::Timeout => println!("gave up after {} clocks", RUN_LIMIT),
+        Stop::Breakpoint(breakpoint) => println!("hit {:x?}", breakpoint),
+        Stop::Watch(breakpoint, access) => println!(
+            "hit {:x?}: {:?} {:02x} at {:04x}",
+            breakpoint, access.access, access.data, access.addr
+        ),
+    }
+}
+
+fn dump(gameboy: &Gameboy, addr: u16, len: u16) {
+    let end = addr as u32 + len as u32;
+    for line in (addr as u32..end).step_by(16) {
+        let bytes = (line..end.min(line + 16))
+            .map(|addr| format!("{:02x}", gameboy.read_memory(addr as u16)))
+            .collect::<Vec<_>>();
+        println!("{:04x}: {}", line, bytes.join(" "));
+    }
+}
+
+fn arg<'a>(args: &[&'a str], index: usize) -> Result<&'a str, String> {
+    args.get(index)
+        .copied()
+        .ok_or_else(|| "missing argument".to_owned())
+}
+
+fn parse_hex(s: &str) -> Result<u16, String> {
+    let digits = s.trim_start_matches("0x").trim_start_matches('$');
+    u16::from_str_radix(digits, 16).map_err(|_| format!("bad hex number: {}", s))
+}
+
+fn parse_range(s: &str) -> Result<RangeInclusive<u16>, String> {
+    match s.find('-') {
+        Some(pos) => Ok(parse_hex(&s[..pos])?..=parse_hex(&s[pos + 1..])?),
+        None => {
+            let addr = parse_hex(s)?;
+            Ok(addr..=addr)
+        }
+    }
+}
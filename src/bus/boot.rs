@@ -0,0 +1,42 @@
+use std::io::{Error, ErrorKind};
+
+/// dmg boot rom, mapped at 0x0000-0x00ff
+const DMG_BOOT_SIZE: usize = 0x100;
+/// cgb boot rom, mapped at 0x0000-0x00ff and 0x0200-0x08ff
+const CGB_BOOT_SIZE: usize = 0x900;
+
+/// A boot rom dump, or an open source replacement of the same size.
+#[derive(Clone)]
+pub struct BootRom {
+    data: Vec<u8>,
+}
+
+impl BootRom {
+    pub fn new(data: Vec<u8>) -> std::io::Result<Self> {
+        match data.len() {
+            DMG_BOOT_SIZE | CGB_BOOT_SIZE => Ok(BootRom { data }),
+            len => Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("boot rom should be 256 or 2304 bytes, got {}", len),
+            )),
+        }
+    }
+
+    pub fn load<P: AsRef<std::path::Path>>(path: P) -> std::io::Result<Self> {
+        Self::new(std::fs::read(path)?)
+    }
+
+    /// Whether this is a cgb boot rom, which makes the machine a cgb.
+    pub fn cgb(&self) -> bool {
+        self.data.len() == CGB_BOOT_SIZE
+    }
+
+    /// Reads the overlay, `None` where the cartridge shows through.
+    pub fn read(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x0000..=0x00ff => Some(self.data[addr as usize]),
+            0x0200..=0x08ff if self.cgb() => Some(self.data[addr as usize]),
+            _ => None,
+        }
+    }
+}
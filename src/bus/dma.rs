@@ -1,3 +1,5 @@
+use crate::state::{Savestate, StateReader, StateWriter};
+
 const DMA_LEN: u16 = 160;
 
 pub struct Dma {
@@ -43,3 +45,20 @@ impl Dma {
         self.delay = true;
     }
 }
+
+impl Savestate for Dma {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u16(self.src);
+        w.write_u16(self.offset);
+        w.write_bool(self.active);
+        w.write_bool(self.delay);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> std::io::Result<()> {
+        self.src = r.read_u16()?;
+        self.offset = r.read_u16()?;
+        self.active = r.read_bool()?;
+        self.delay = r.read_bool()?;
+        Ok(())
+    }
+}
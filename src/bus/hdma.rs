@@ -1,3 +1,5 @@
+use crate::state::{invalid_state, Savestate, StateReader, StateWriter};
+
 #[derive(PartialEq, Eq)]
 enum HdmaMode {
     GDMA,
@@ -86,3 +88,29 @@ impl Hdma {
         }
     }
 }
+
+impl Savestate for Hdma {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u16(self.hdma_src);
+        w.write_u16(self.hdma_dst);
+        w.write_u16(self.hdma_len);
+        w.write_u8(match self.mode {
+            None => 0,
+            Some(HdmaMode::GDMA) => 1,
+            Some(HdmaMode::HDMA) => 2,
+        });
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> std::io::Result<()> {
+        self.hdma_src = r.read_u16()?;
+        self.hdma_dst = r.read_u16()?;
+        self.hdma_len = r.read_u16()?;
+        self.mode = match r.read_u8()? {
+            0 => None,
+            1 => Some(HdmaMode::GDMA),
+            2 => Some(HdmaMode::HDMA),
+            _ => return Err(invalid_state("bad hdma mode")),
+        };
+        Ok(())
+    }
+}
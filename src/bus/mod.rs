@@ -1,11 +1,22 @@
+use crate::debug::{Access, Watch};
+use crate::state::{invalid_state, Savestate, StateReader, StateWriter};
 use crate::{Apu, Cartridge, Ppu};
 use crate::{InterruptHandler, Timer};
 use crate::{Joypad, JoypadState};
 use dma::Dma;
 use hdma::Hdma;
+use serial::Serial;
 
+pub use self::boot::BootRom;
+pub use self::serial::{CaptureLink, LinkPort, NullLink, PrintLink, SerialLink};
+
+mod boot;
 mod dma;
 mod hdma;
+mod serial;
+
+/// KEY0 bit of dmg compatibility mode
+const KEY0_DMG_MODE: u8 = 0b0100;
 
 #[derive(Copy, Clone, Eq, PartialEq)]
 #[repr(u8)]
@@ -28,10 +39,17 @@ pub struct Bus {
     hdma: Hdma,
     timer: Timer,
     joypad: Joypad,
+    serial: Serial,
     pub(crate) cart: Cartridge,
     pub(crate) ppu: Ppu,
     pub(crate) apu: Apu,
     pub(crate) interrupt_handler: InterruptHandler,
+    pub(crate) watch: Watch,
+
+    boot_rom: Option<BootRom>,
+    boot_rom_mapped: bool,
+    // cleared in dmg compatibility mode
+    cgb: bool,
 
     prepare_speed_switch: bool,
     speed_mode: SpeedMode,
@@ -41,7 +59,20 @@ pub struct Bus {
 
 impl Bus {
     pub fn new(cart: Cartridge) -> Self {
-        let cgb = cart.cgb();
+        Self::build(cart, None)
+    }
+
+    /// Starts from the boot rom instead of the post-boot state, a cgb boot rom
+    /// makes this a cgb, running dmg carts in compatibility mode.
+    pub fn with_boot_rom(cart: Cartridge, boot_rom: BootRom) -> Self {
+        Self::build(cart, Some(boot_rom))
+    }
+
+    fn build(cart: Cartridge, boot_rom: Option<BootRom>) -> Self {
+        let cgb = match &boot_rom {
+            Some(boot_rom) => boot_rom.cgb(),
+            None => cart.cgb(),
+        };
         Self {
             work_ram0: Box::new([0u8; 0x1000]),
             work_ram1: Box::new([[0u8; 0x1000]; 7]),
@@ -54,10 +85,16 @@ impl Bus {
             hdma: Hdma::new(),
             timer: Timer::new(),
             joypad: Joypad::new(),
+            serial: Serial::new(cgb),
             cart,
             ppu: Ppu::new(cgb),
-            apu: Apu::new(),
+            apu: Apu::new(cgb, crate::AUDIO_FREQUENCY),
             interrupt_handler: InterruptHandler::new(),
+            watch: Watch::default(),
+
+            boot_rom,
+            boot_rom_mapped: false,
+            cgb,
 
             prepare_speed_switch: false,
             speed_mode: SpeedMode::Normal,
@@ -70,8 +107,10 @@ impl Bus {
         let scaled_tcycles = 4 >> (self.speed_mode as u8);
 
         self.timer.update(4, &mut self.interrupt_handler);
+        self.serial.update(4, &mut self.interrupt_handler);
         self.ppu.update(scaled_tcycles, &mut self.interrupt_handler);
         self.apu.update(scaled_tcycles);
+        self.cart.tick(scaled_tcycles);
 
         self.do_dma();
         self.do_hdma();
@@ -99,9 +138,49 @@ impl Bus {
         self.mcycles
     }
 
+    pub fn cgb(&self) -> bool {
+        self.cgb
+    }
+
+    /// Whether the boot rom is still running.
+    pub fn boot_rom_mapped(&self) -> bool {
+        self.boot_rom_mapped
+    }
+
+    /// Power cycles the console, the cartridge keeps its banks, memory and
+    /// clock.
     pub fn reset(&mut self) {
-        for &(addr, data) in INIT_PORTS.iter() {
-            self.write(addr, data);
+        self.cgb = match &self.boot_rom {
+            Some(boot_rom) => boot_rom.cgb(),
+            None => self.cart.cgb(),
+        };
+
+        *self.work_ram0 = [0u8; 0x1000];
+        *self.work_ram1 = [[0u8; 0x1000]; 7];
+        self.wram_bank = 0;
+        self.io_ports = [0u8; 0x80];
+        self.high_ram = [0u8; 0x80];
+
+        self.dma = Dma::new();
+        self.hdma = Hdma::new();
+        self.timer = Timer::new();
+        self.apu.reset();
+        self.interrupt_handler = InterruptHandler::new();
+        let palette = self.ppu.dmg_palette();
+        self.ppu = Ppu::new(self.cgb);
+        self.ppu.set_dmg_palette(&palette);
+        self.serial.reset(self.cgb);
+
+        self.prepare_speed_switch = false;
+        self.speed_mode = SpeedMode::Normal;
+
+        if self.boot_rom.is_some() {
+            // power on, the boot rom sets everything up
+            self.boot_rom_mapped = true;
+        } else {
+            for &(addr, data) in INIT_PORTS.iter() {
+                self.write(addr, data);
+            }
         }
 
         self.cycles = 0;
@@ -112,6 +191,7 @@ impl Bus {
 impl Bus {
     pub fn read(&mut self, addr: u16) -> u8 {
         let data = self.read_direct(addr);
+        self.watch.check(Access::Read, addr, data);
         self.step();
         data
     }
@@ -119,6 +199,10 @@ impl Bus {
     pub(crate) fn read_direct(&self, addr: u16) -> u8 {
         let index = addr as usize;
         let data = match addr {
+            0x0000..=0x08ff if self.boot_rom_mapped => match &self.boot_rom {
+                Some(boot_rom) => boot_rom.read(addr).unwrap_or_else(|| self.cart.read(addr)),
+                None => self.cart.read(addr),
+            },
             0x0000..=0x7fff => self.cart.read(addr),
             0x8000..=0x9fff => self.ppu.read(addr),
             0xa000..=0xbfff => self.cart.read(addr),
@@ -136,12 +220,24 @@ impl Bus {
         data
     }
 
+    /// Reads memory without side effects, unusable memory reads `0xff`.
+    pub fn peek(&self, addr: u16) -> u8 {
+        match addr {
+            0xfea0..=0xfeff => 0xff,
+            _ => self.read_direct(addr),
+        }
+    }
+
     pub fn write(&mut self, addr: u16, data: u8) {
         let index = addr as usize;
         match addr {
             0x0000..=0x7fff => self.cart.write(addr, data),
             0x8000..=0x9fff => self.ppu.write(addr, data),
-            0xa000..=0xbfff => self.cart.write(addr, data),
+            0xa000..=0xbfff => {
+                self.cart.write(addr, data);
+                // commands to the cart may start or stop its speaker
+                self.apu.set_speaker_tone(self.cart.tone());
+            }
             0xc000..=0xcfff => self.work_ram0[index - 0xc000] = data,
             0xd000..=0xdfff => self.work_ram1[self.wram_bank][index - 0xd000] = data,
             0xe000..=0xefff => self.work_ram0[index - 0xe000] = data,
@@ -152,22 +248,26 @@ impl Bus {
             0xff80..=0xfffe => self.high_ram[index - 0xff80] = data,
             0xffff => self.interrupt_handler.write(addr, data),
         };
+        self.watch.check(Access::Write, addr, data);
         self.step();
     }
 
     fn read_io(&self, addr: u16) -> u8 {
-        let cgb = self.cart.cgb();
+        let cgb = self.cgb;
         let index = addr as usize;
         match addr {
             0xff00 => self.joypad.read(addr),
+            0xff01..=0xff02 => self.serial.read(addr),
             0xff04..=0xff07 => self.timer.read(addr),
             0xff0f => self.interrupt_handler.read(addr),
             0xff10..=0xff3f => self.apu.read(addr),
             0xff46 => self.dma.read(addr),
             0xff40..=0xff4b => self.ppu.read(addr),
 
+            0xff4c => 0xff,
             0xff4d if cgb => ((self.speed_mode as u8) << 7) | (self.prepare_speed_switch as u8),
             0xff4f => self.ppu.read(addr),
+            0xff50 => 0xff,
             0xff51..=0xff55 if cgb => self.hdma.read(addr),
             0xff68..=0xff6b => self.ppu.read(addr),
             0xff70 if cgb => (self.wram_bank as u8 + 1) | 0xf8,
@@ -176,18 +276,26 @@ impl Bus {
     }
 
     fn write_io(&mut self, addr: u16, data: u8) {
-        let cgb = self.cart.cgb();
+        let cgb = self.cgb;
         let index = addr as usize;
         match addr {
             0xff00 => self.joypad.write(addr, data),
+            0xff01..=0xff02 => self.serial.write(addr, data),
             0xff04..=0xff07 => self.timer.write(addr, data),
             0xff0f => self.interrupt_handler.write(addr, data),
             0xff10..=0xff3f => self.apu.write(addr, data),
             0xff46 => self.dma.write(addr, data),
             0xff40..=0xff4b => self.ppu.write(addr, data),
 
+            // KEY0, cgb or dmg compatibility mode, locked after boot
+            0xff4c if !self.boot_rom_mapped => {}
             0xff4d if cgb => self.prepare_speed_switch = (data & 0b01) != 0,
             0xff4f => self.ppu.write(addr, data),
+            0xff50 => {
+                if self.boot_rom_mapped && data & 0b01 != 0 {
+                    self.unmap_boot_rom();
+                }
+            }
             0xff51..=0xff55 if cgb => self.hdma.write(addr, data),
             0xff68..=0xff6b => self.ppu.write(addr, data),
             0xff70 if cgb => self.wram_bank = (data & 0b0111).saturating_sub(1) as usize,
@@ -195,6 +303,20 @@ impl Bus {
         }
     }
 
+    fn unmap_boot_rom(&mut self) {
+        self.boot_rom_mapped = false;
+
+        if self.cgb && self.io_ports[0x4c] & KEY0_DMG_MODE != 0 {
+            self.cgb = false;
+            self.ppu.enter_compat_mode();
+            self.serial.set_cgb(false);
+        }
+    }
+
+    pub fn set_serial_link(&mut self, link: Box<dyn SerialLink>) {
+        self.serial.set_link(link);
+    }
+
     pub fn set_input(&mut self, states: JoypadState) {
         self.joypad.set_input(states);
         self.joypad.update(&mut self.interrupt_handler);
@@ -217,6 +339,87 @@ impl Bus {
     }
 }
 
+impl Savestate for Bus {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_bytes(self.work_ram0.as_ref());
+        for bank in self.work_ram1.iter() {
+            w.write_bytes(bank);
+        }
+        w.write_usize(self.wram_bank);
+
+        w.write_bytes(&self.io_ports);
+        w.write_bytes(&self.high_ram);
+
+        self.dma.save_state(w);
+        self.hdma.save_state(w);
+        self.timer.save_state(w);
+        self.joypad.save_state(w);
+        self.serial.save_state(w);
+        self.cart.save_state(w);
+        self.ppu.save_state(w);
+        self.apu.save_state(w);
+        self.interrupt_handler.save_state(w);
+
+        w.write_bool(self.boot_rom_mapped);
+        w.write_bool(self.cgb);
+
+        w.write_bool(self.prepare_speed_switch);
+        w.write_u8(self.speed_mode as u8);
+        w.write_u32(self.cycles);
+        w.write_u32(self.mcycles);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> std::io::Result<()> {
+        let palette = self.ppu.dmg_palette();
+
+        r.read_bytes(self.work_ram0.as_mut())?;
+        for bank in self.work_ram1.iter_mut() {
+            r.read_bytes(bank)?;
+        }
+        self.wram_bank = r.read_usize()?;
+        if self.wram_bank >= self.work_ram1.len() {
+            return Err(invalid_state("bad wram bank"));
+        }
+
+        r.read_bytes(&mut self.io_ports)?;
+        r.read_bytes(&mut self.high_ram)?;
+
+        self.dma.load_state(r)?;
+        self.hdma.load_state(r)?;
+        self.timer.load_state(r)?;
+        self.joypad.load_state(r)?;
+        self.serial.load_state(r)?;
+        self.cart.load_state(r)?;
+        self.ppu.load_state(r)?;
+        self.apu.load_state(r)?;
+        self.apu.set_speaker_tone(self.cart.tone());
+        self.interrupt_handler.load_state(r)?;
+
+        self.boot_rom_mapped = r.read_bool()?;
+        if self.boot_rom_mapped && self.boot_rom.is_none() {
+            return Err(invalid_state("save state needs a boot rom"));
+        }
+        self.cgb = r.read_bool()?;
+
+        self.prepare_speed_switch = r.read_bool()?;
+        self.speed_mode = match r.read_u8()? {
+            0 => SpeedMode::Normal,
+            1 => SpeedMode::Double,
+            _ => return Err(invalid_state("bad speed mode")),
+        };
+        self.cycles = r.read_u32()?;
+        self.mcycles = r.read_u32()?;
+
+        // dmg colors are the user's, unless the cgb boot rom picked them
+        let compat = !self.cgb && matches!(&self.boot_rom, Some(boot_rom) if boot_rom.cgb());
+        if !compat {
+            self.ppu.set_dmg_palette(&palette);
+        }
+
+        Ok(())
+    }
+}
+
 const INIT_PORTS: [(u16, u8); 31] = [
     (0xff05, 0x00), // TIMA
     (0xff06, 0x00), // TMA
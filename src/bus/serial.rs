@@ -0,0 +1,433 @@
+use crate::interrupt::{Interrupt, InterruptHandler};
+use crate::state::{Savestate, StateReader, StateWriter};
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+/// serial transfer data
+const SB_PORT: u16 = 0xff01;
+/// serial transfer control
+const SC_PORT: u16 = 0xff02;
+
+const TRANSFER_START_MASK: u8 = 0b1000_0000;
+const FAST_CLOCK_MASK: u8 = 0b0000_0010;
+const INTERNAL_CLOCK_MASK: u8 = 0b0000_0001;
+
+/// clocks per bit of the internal clock, 8192Hz
+const NORMAL_CLOCKS: u32 = 512;
+/// clocks per bit of the cgb fast internal clock, 262144Hz
+const FAST_CLOCKS: u32 = 16;
+
+/// The other end of the link cable.
+pub trait SerialLink: Send {
+    /// Transfers a byte clocked by this side, returns the byte shifted in
+    /// from the other side.
+    fn send(&mut self, data: u8) -> u8;
+
+    /// Polled while waiting for the other side to clock a transfer, `data` is
+    /// the byte this side shifts out. Returns the received byte once done.
+    fn poll(&mut self, data: u8) -> Option<u8>;
+
+    /// This side stopped waiting for the other side to clock a transfer.
+    fn cancel(&mut self) {}
+}
+
+/// Nothing connected, reads all ones.
+pub struct NullLink;
+
+impl SerialLink for NullLink {
+    fn send(&mut self, _data: u8) -> u8 {
+        0xff
+    }
+
+    fn poll(&mut self, _data: u8) -> Option<u8> {
+        None
+    }
+}
+
+/// Prints sent bytes to stdout, like blargg's test output.
+pub struct PrintLink;
+
+impl SerialLink for PrintLink {
+    fn send(&mut self, data: u8) -> u8 {
+        let stdout = std::io::stdout();
+        let mut lock = stdout.lock();
+        let _ = lock.write_all(&[data]);
+        let _ = lock.flush();
+        0xff
+    }
+
+    fn poll(&mut self, _data: u8) -> Option<u8> {
+        None
+    }
+}
+
+/// Records sent bytes, for test roms reporting through the link port.
+pub struct CaptureLink {
+    output: Arc<Mutex<Vec<u8>>>,
+}
+
+impl CaptureLink {
+    pub fn new() -> Self {
+        CaptureLink {
+            output: Default::default(),
+        }
+    }
+
+    /// Shared handle to the captured bytes.
+    pub fn output(&self) -> Arc<Mutex<Vec<u8>>> {
+        self.output.clone()
+    }
+}
+
+impl SerialLink for CaptureLink {
+    fn send(&mut self, data: u8) -> u8 {
+        self.output.lock().unwrap().push(data);
+        0xff
+    }
+
+    fn poll(&mut self, _data: u8) -> Option<u8> {
+        None
+    }
+}
+
+#[derive(Default)]
+struct CableState {
+    // byte shifted out by a side waiting on the external clock
+    waiting: [Option<u8>; 2],
+    // byte clocked in by the other side
+    incoming: [Option<u8>; 2],
+}
+
+/// One end of a cable connecting two gameboys.
+pub struct LinkPort {
+    side: usize,
+    cable: Arc<Mutex<CableState>>,
+}
+
+impl LinkPort {
+    /// Creates both ends of a link cable.
+    pub fn cable() -> (LinkPort, LinkPort) {
+        let cable = Arc::new(Mutex::new(CableState::default()));
+        (
+            LinkPort {
+                side: 0,
+                cable: cable.clone(),
+            },
+            LinkPort { side: 1, cable },
+        )
+    }
+}
+
+impl SerialLink for LinkPort {
+    fn send(&mut self, data: u8) -> u8 {
+        let other = self.side ^ 1;
+        let mut cable = self.cable.lock().unwrap();
+
+        if let Some(reply) = cable.waiting[other].take() {
+            cable.incoming[other] = Some(data);
+            reply
+        } else {
+            0xff
+        }
+    }
+
+    fn poll(&mut self, data: u8) -> Option<u8> {
+        let mut cable = self.cable.lock().unwrap();
+
+        let incoming = cable.incoming[self.side].take();
+        if incoming.is_none() {
+            cable.waiting[self.side] = Some(data);
+        }
+        incoming
+    }
+
+    fn cancel(&mut self) {
+        self.cable.lock().unwrap().waiting[self.side] = None;
+    }
+}
+
+pub struct Serial {
+    sb: u8,
+    sc: u8,
+
+    clocks: u32,
+    bits: u8,
+    cgb: bool,
+
+    link: Box<dyn SerialLink>,
+}
+
+impl Serial {
+    pub fn new(cgb: bool) -> Self {
+        Serial {
+            sb: 0,
+            sc: 0,
+
+            clocks: 0,
+            bits: 0,
+            cgb,
+
+            link: Box::new(NullLink),
+        }
+    }
+
+    pub fn set_cgb(&mut self, cgb: bool) {
+        self.cgb = cgb;
+    }
+
+    /// Stops any transfer, the link stays plugged in.
+    pub fn reset(&mut self, cgb: bool) {
+        self.sb = 0;
+        self.sc = 0;
+        self.clocks = 0;
+        self.bits = 0;
+        self.cgb = cgb;
+        self.link.cancel();
+    }
+
+    pub fn set_link(&mut self, link: Box<dyn SerialLink>) {
+        self.link = link;
+    }
+
+    pub fn update(&mut self, clocks: u32, interrupts: &mut InterruptHandler) {
+        if self.sc & TRANSFER_START_MASK == 0 {
+            return;
+        }
+
+        if self.sc & INTERNAL_CLOCK_MASK != 0 {
+            let period = if self.cgb && self.sc & FAST_CLOCK_MASK != 0 {
+                FAST_CLOCKS
+            } else {
+                NORMAL_CLOCKS
+            };
+
+            self.clocks += clocks;
+            while self.clocks >= period && self.sc & TRANSFER_START_MASK != 0 {
+                self.clocks -= period;
+                self.bits += 1;
+
+                if self.bits == 8 {
+                    let data = self.link.send(self.sb);
+                    self.finish(data, interrupts);
+                }
+            }
+        } else if let Some(data) = self.link.poll(self.sb) {
+            self.finish(data, interrupts);
+        }
+    }
+
+    fn finish(&mut self, data: u8, interrupts: &mut InterruptHandler) {
+        self.sb = data;
+        self.sc &= !TRANSFER_START_MASK;
+        self.clocks = 0;
+        self.bits = 0;
+
+        interrupts.request_interrupt(Interrupt::Serial);
+    }
+
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            SB_PORT => self.sb,
+            SC_PORT if self.cgb => self.sc | 0b0111_1100,
+            SC_PORT => self.sc | 0b0111_1110,
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, data: u8) {
+        match addr {
+            SB_PORT => self.sb = data,
+            SC_PORT => {
+                self.sc = data;
+                self.clocks = 0;
+                self.bits = 0;
+                // the other side must not clock a byte out of a port that
+                // no longer waits for it
+                if data & (TRANSFER_START_MASK | INTERNAL_CLOCK_MASK) != TRANSFER_START_MASK {
+                    self.link.cancel();
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl Savestate for Serial {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u8(self.sb);
+        w.write_u8(self.sc);
+        w.write_u32(self.clocks);
+        w.write_u8(self.bits);
+        w.write_bool(self.cgb);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> std::io::Result<()> {
+        self.sb = r.read_u8()?;
+        self.sc = r.read_u8()?;
+        self.clocks = r.read_u32()?;
+        self.bits = r.read_u8()? % 8;
+        self.cgb = r.read_bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Answers every transfer clocked by this side with the same byte.
+    struct Replier {
+        sent: Arc<Mutex<Vec<u8>>>,
+        reply: u8,
+    }
+
+    impl SerialLink for Replier {
+        fn send(&mut self, data: u8) -> u8 {
+            self.sent.lock().unwrap().push(data);
+            self.reply
+        }
+
+        fn poll(&mut self, _data: u8) -> Option<u8> {
+            None
+        }
+    }
+
+    fn serial(cgb: bool) -> (Serial, Arc<Mutex<Vec<u8>>>) {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let mut serial = Serial::new(cgb);
+        serial.set_link(Box::new(Replier {
+            sent: sent.clone(),
+            reply: 0x5a,
+        }));
+        (serial, sent)
+    }
+
+    fn serial_requested(interrupts: &InterruptHandler) -> bool {
+        interrupts.read(0xff0f) & 0b1000 != 0
+    }
+
+    /// Updates 4 clocks at a time, like the bus, until the transfer ends.
+    fn transfer_clocks(serial: &mut Serial, interrupts: &mut InterruptHandler) -> u32 {
+        let mut clocks = 0;
+        while serial.read(SC_PORT) & TRANSFER_START_MASK != 0 && clocks < 0x10000 {
+            serial.update(4, interrupts);
+            clocks += 4;
+        }
+        clocks
+    }
+
+    #[test]
+    fn internal_clock_transfer() {
+        let (mut serial, sent) = serial(false);
+        let mut interrupts = InterruptHandler::new();
+        serial.write(SB_PORT, 0x41);
+        serial.write(SC_PORT, 0x81);
+
+        serial.update(8 * NORMAL_CLOCKS - 4, &mut interrupts);
+        assert!(sent.lock().unwrap().is_empty());
+        assert!(!serial_requested(&interrupts));
+        assert_eq!(serial.read(SB_PORT), 0x41);
+
+        serial.update(4, &mut interrupts);
+        assert_eq!(*sent.lock().unwrap(), [0x41]);
+        assert!(serial_requested(&interrupts));
+        assert_eq!(serial.read(SB_PORT), 0x5a);
+        assert_eq!(serial.read(SC_PORT), 0x7f);
+    }
+
+    #[test]
+    fn transfer_timing() {
+        let mut interrupts = InterruptHandler::new();
+        let (mut dmg, _) = serial(false);
+        // no fast clock on the dmg
+        dmg.write(SC_PORT, 0x83);
+        assert_eq!(
+            transfer_clocks(&mut dmg, &mut interrupts),
+            8 * NORMAL_CLOCKS
+        );
+
+        let (mut cgb, _) = serial(true);
+        cgb.write(SC_PORT, 0x81);
+        assert_eq!(
+            transfer_clocks(&mut cgb, &mut interrupts),
+            8 * NORMAL_CLOCKS
+        );
+        cgb.write(SC_PORT, 0x83);
+        assert_eq!(transfer_clocks(&mut cgb, &mut interrupts), 8 * FAST_CLOCKS);
+        assert_eq!(cgb.read(SC_PORT), 0x7f);
+    }
+
+    #[test]
+    fn rewriting_control_restarts() {
+        let (mut serial, sent) = serial(false);
+        let mut interrupts = InterruptHandler::new();
+        serial.write(SC_PORT, 0x81);
+        serial.update(4 * NORMAL_CLOCKS, &mut interrupts);
+        serial.write(SC_PORT, 0x81);
+        serial.update(4 * NORMAL_CLOCKS, &mut interrupts);
+        assert!(sent.lock().unwrap().is_empty());
+
+        serial.write(SC_PORT, 0x01);
+        serial.update(8 * NORMAL_CLOCKS, &mut interrupts);
+        assert!(sent.lock().unwrap().is_empty());
+        assert!(!serial_requested(&interrupts));
+    }
+
+    #[test]
+    fn external_clock_waits_for_the_other_side() {
+        let (port0, port1) = LinkPort::cable();
+        let mut master = Serial::new(false);
+        let mut slave = Serial::new(false);
+        master.set_link(Box::new(port0));
+        slave.set_link(Box::new(port1));
+        let mut master_interrupts = InterruptHandler::new();
+        let mut slave_interrupts = InterruptHandler::new();
+
+        slave.write(SB_PORT, 0x22);
+        slave.write(SC_PORT, 0x80);
+        slave.update(0x10000, &mut slave_interrupts);
+        assert!(!serial_requested(&slave_interrupts));
+        assert_eq!(slave.read(SC_PORT), 0xfe);
+
+        master.write(SB_PORT, 0x11);
+        master.write(SC_PORT, 0x81);
+        transfer_clocks(&mut master, &mut master_interrupts);
+        assert_eq!(master.read(SB_PORT), 0x22);
+        assert!(serial_requested(&master_interrupts));
+
+        slave.update(4, &mut slave_interrupts);
+        assert_eq!(slave.read(SB_PORT), 0x11);
+        assert_eq!(slave.read(SC_PORT), 0x7e);
+        assert!(serial_requested(&slave_interrupts));
+    }
+
+    #[test]
+    fn stopped_wait_is_not_clocked() {
+        let (port0, port1) = LinkPort::cable();
+        let mut master = Serial::new(false);
+        let mut slave = Serial::new(false);
+        master.set_link(Box::new(port0));
+        slave.set_link(Box::new(port1));
+        let mut interrupts = InterruptHandler::new();
+
+        slave.write(SB_PORT, 0x22);
+        slave.write(SC_PORT, 0x80);
+        slave.update(4, &mut interrupts);
+        slave.write(SC_PORT, 0x00);
+
+        master.write(SC_PORT, 0x81);
+        transfer_clocks(&mut master, &mut interrupts);
+        assert_eq!(master.read(SB_PORT), 0xff);
+    }
+
+    #[test]
+    fn nothing_connected_reads_ones() {
+        let mut serial = Serial::new(false);
+        let mut interrupts = InterruptHandler::new();
+        serial.write(SB_PORT, 0x41);
+        serial.write(SC_PORT, 0x81);
+        transfer_clocks(&mut serial, &mut interrupts);
+        assert_eq!(serial.read(SB_PORT), 0xff);
+        assert!(serial_requested(&interrupts));
+    }
+}
@@ -0,0 +1,374 @@
+use super::MemoryBankController;
+use crate::state::{Savestate, StateReader, StateWriter};
+use std::io;
+
+/// picture size the camera captures
+pub const CAMERA_WIDTH: usize = 128;
+pub const CAMERA_HEIGHT: usize = 112;
+
+/// ram bank value that maps the sensor registers
+const REGISTER_BANK: u8 = 0x10;
+/// capture start, reads 1 while busy
+const START: u8 = 0x01;
+/// pictures are saved as tiles at 0xa100 of bank 0
+const PICTURE: usize = 0x100;
+/// start of the 4x4 dither matrix, 3 thresholds per pixel
+const DITHER: usize = 0x06;
+const REGISTERS: usize = 0x36;
+
+/// Picture the camera sensor sees, in place of the real sensor.
+pub trait ImageSource: Send {
+    /// Fills `image` with a `CAMERA_WIDTH` x `CAMERA_HEIGHT` grayscale
+    /// picture, row by row, 0 for black to 255 for white.
+    fn capture(&mut self, image: &mut [u8]);
+}
+
+/// Moving gray bars, the default image source.
+#[derive(Default)]
+pub struct TestPattern {
+    frame: usize,
+}
+
+impl ImageSource for TestPattern {
+    fn capture(&mut self, image: &mut [u8]) {
+        for y in 0..CAMERA_HEIGHT {
+            for x in 0..CAMERA_WIDTH {
+                // diagonal bars with a gradient across the picture
+                let bar = ((x + y + self.frame) / 16) % 2;
+                image[y * CAMERA_WIDTH + x] = x as u8 + bar as u8 * 0x80;
+            }
+        }
+        self.frame = self.frame.wrapping_add(1);
+    }
+}
+
+/// A still picture, scaled to the sensor size.
+#[derive(Clone)]
+pub struct StillImage {
+    pixels: Vec<u8>,
+}
+
+impl StillImage {
+    /// Loads a binary pgm (P5) image.
+    pub fn load<P: AsRef<std::path::Path>>(path: P) -> io::Result<Self> {
+        Self::parse_pgm(&std::fs::read(path)?)
+    }
+
+    pub fn parse_pgm(data: &[u8]) -> io::Result<Self> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg);
+
+        // magic, width, height and max value, separated by whitespace and
+        // comments, then a single whitespace before the pixels
+        let mut fields = Vec::new();
+        let mut pos = 0;
+        while fields.len() < 4 {
+            while pos < data.len() && data[pos].is_ascii_whitespace() {
+                pos += 1;
+            }
+            if pos < data.len() && data[pos] == b'#' {
+                while pos < data.len() && data[pos] != b'\n' {
+                    pos += 1;
+                }
+                continue;
+            }
+            let start = pos;
+            while pos < data.len() && !data[pos].is_ascii_whitespace() {
+                pos += 1;
+            }
+            if start == pos {
+                return Err(invalid("truncated pgm header"));
+            }
+            fields.push(String::from_utf8_lossy(&data[start..pos]).into_owned());
+        }
+        pos += 1;
+
+        if fields[0] != "P5" {
+            return Err(invalid("not a binary pgm image"));
+        }
+        let number = |s: &str| s.parse::<usize>().map_err(|_| invalid("bad pgm header"));
+        let (width, height, max) = (
+            number(&fields[1])?,
+            number(&fields[2])?,
+            number(&fields[3])?,
+        );
+        if width == 0 || height == 0 || max == 0 || max > 0xff {
+            return Err(invalid("unsupported pgm size or depth"));
+        }
+        let end = width
+            .checked_mul(height)
+            .and_then(|n| n.checked_add(pos))
+            .ok_or_else(|| invalid("bad pgm header"))?;
+        let raw = data
+            .get(pos..end)
+            .ok_or_else(|| invalid("truncated pgm image"))?;
+
+        // nearest neighbour, stretched to fill the sensor
+        let mut pixels = vec![0u8; CAMERA_WIDTH * CAMERA_HEIGHT];
+        for y in 0..CAMERA_HEIGHT {
+            for x in 0..CAMERA_WIDTH {
+                let p = raw[y * height / CAMERA_HEIGHT * width + x * width / CAMERA_WIDTH];
+                pixels[y * CAMERA_WIDTH + x] = (p as usize * 0xff / max) as u8;
+            }
+        }
+
+        Ok(StillImage { pixels })
+    }
+}
+
+impl ImageSource for StillImage {
+    fn capture(&mut self, image: &mut [u8]) {
+        image.copy_from_slice(&self.pixels);
+    }
+}
+
+/// Pocket Camera, 128 KiB of ram for pictures and the M64282FP sensor's
+/// registers in ram bank 0x10.
+///
+/// Captures finish at once, processed with the exposure, edge enhancement
+/// and dither matrix the game set.
+pub struct PocketCamera {
+    ram: Vec<u8>,
+    writes: u64,
+    rom_bank: u8,
+    ram_bank: u8,
+    ram_enable: bool,
+
+    max_rom: usize,
+
+    registers: [u8; REGISTERS],
+    source: Box<dyn ImageSource>,
+}
+
+impl PocketCamera {
+    pub fn new(rom_size: usize, ram_size: usize) -> Self {
+        PocketCamera {
+            ram: vec![0u8; ram_size.max(0x2000)],
+            writes: 0,
+            rom_bank: 0x01,
+            ram_bank: 0x00,
+            ram_enable: false,
+
+            max_rom: (rom_size / 0x4000).max(1),
+
+            registers: [0u8; REGISTERS],
+            source: Box::new(TestPattern::default()),
+        }
+    }
+
+    fn ram_addr(&self, addr: u16) -> usize {
+        (self.ram_bank as usize * 0x2000 + (addr - 0xa000) as usize) % self.ram.len()
+    }
+
+    fn write_register(&mut self, addr: u16, data: u8) {
+        let reg = (addr & 0x7f) as usize;
+        if reg >= REGISTERS {
+            return;
+        }
+
+        if reg == 0 {
+            self.registers[0] = data & 0x07;
+            if data & START != 0 {
+                self.capture();
+            }
+        } else {
+            self.registers[reg] = data;
+        }
+    }
+
+    fn capture(&mut self) {
+        let mut image = vec![0u8; CAMERA_WIDTH * CAMERA_HEIGHT];
+        self.source.capture(&mut image);
+
+        let exposure = (self.registers[2] as i32) << 8 | self.registers[3] as i32;
+        let invert = self.registers[4] & 0x08 != 0;
+        // edge enhancement ratio, in eighths
+        let edge = if self.registers[4] & 0x80 != 0 {
+            [4, 6, 8, 10, 16, 24, 32, 40][(self.registers[4] as usize >> 4) & 0x07]
+        } else {
+            0
+        };
+
+        let pixel = |x: usize, y: usize| {
+            let x = x.min(CAMERA_WIDTH - 1);
+            let y = y.min(CAMERA_HEIGHT - 1);
+            image[y * CAMERA_WIDTH + x] as i32
+        };
+
+        for y in 0..CAMERA_HEIGHT {
+            for x in 0..CAMERA_WIDTH {
+                let mut value = pixel(x, y);
+                if edge != 0 {
+                    let neighbours = pixel(x.saturating_sub(1), y)
+                        + pixel(x + 1, y)
+                        + pixel(x, y.saturating_sub(1))
+                        + pixel(x, y + 1);
+                    value += (value * 4 - neighbours) * edge / 8;
+                }
+                value = (value * exposure / 0x100).clamp(0, 0xff);
+                if invert {
+                    value = 0xff - value;
+                }
+
+                let matrix = DITHER + ((y & 3) * 4 + (x & 3)) * 3;
+                let thresholds = &self.registers[matrix..matrix + 3];
+                let color = thresholds.iter().filter(|&&t| value < t as i32).count() as u8;
+
+                let tile = (y / 8) * (CAMERA_WIDTH / 8) + x / 8;
+                let offset = PICTURE + tile * 16 + (y % 8) * 2;
+                let bit = 0x80 >> (x % 8);
+                for (plane, byte) in self.ram[offset..offset + 2].iter_mut().enumerate() {
+                    if color & (1 << plane) != 0 {
+                        *byte |= bit;
+                    } else {
+                        *byte &= !bit;
+                    }
+                }
+            }
+        }
+
+        // done at once
+        self.registers[0] &= !START;
+        self.writes += 1;
+    }
+}
+
+impl MemoryBankController for PocketCamera {
+    fn read(&self, rom: &[u8], addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x3fff => rom[addr as usize],
+            0x4000..=0x7fff => {
+                let addr = addr as usize - 0x4000;
+                rom[addr + 0x4000 * self.rom_bank as usize]
+            }
+            // only the first register reads back
+            0xa000..=0xbfff if self.ram_bank & REGISTER_BANK != 0 => match addr & 0x7f {
+                0x00 => self.registers[0],
+                _ => 0x00,
+            },
+            0xa000..=0xbfff => self.ram[self.ram_addr(addr)],
+
+            _ => unreachable!(),
+        }
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x0000..=0x1fff => self.ram_enable = data & 0x0f == 0x0a,
+            0x2000..=0x3fff => self.rom_bank = ((data & 0x3f) as usize % self.max_rom) as u8,
+            0x4000..=0x5fff => self.ram_bank = data & 0x1f,
+            0x6000..=0x7fff => {}
+            0xa000..=0xbfff if self.ram_bank & REGISTER_BANK != 0 => {
+                self.write_register(addr, data)
+            }
+            0xa000..=0xbfff => {
+                if self.ram_enable {
+                    let addr = self.ram_addr(addr);
+                    self.ram[addr] = data;
+                    self.writes += 1;
+                }
+            }
+
+            _ => unreachable!(),
+        }
+    }
+
+    fn set_image_source(&mut self, source: Box<dyn ImageSource>) {
+        self.source = source;
+    }
+
+    fn get_ram(&self) -> Option<&[u8]> {
+        Some(self.ram.as_slice())
+    }
+
+    fn get_ram_mut(&mut self) -> Option<&mut [u8]> {
+        Some(self.ram.as_mut_slice())
+    }
+
+    fn writes(&self) -> u64 {
+        self.writes
+    }
+
+    fn mbc_type(&self) -> &'static str {
+        "POCKET CAMERA"
+    }
+
+    fn rom_bank(&self) -> usize {
+        self.rom_bank as usize
+    }
+}
+
+impl Savestate for PocketCamera {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_bytes(&self.ram);
+        w.write_u8(self.rom_bank);
+        w.write_u8(self.ram_bank);
+        w.write_bool(self.ram_enable);
+        w.write_bytes(&self.registers);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> std::io::Result<()> {
+        r.read_bytes(&mut self.ram)?;
+        self.rom_bank = (r.read_u8()? as usize % self.max_rom) as u8;
+        self.ram_bank = r.read_u8()? & 0x1f;
+        self.ram_enable = r.read_bool()?;
+        r.read_bytes(&mut self.registers)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pgm(header: &str, pixels: &[u8]) -> io::Result<StillImage> {
+        let mut data = header.as_bytes().to_vec();
+        data.extend_from_slice(pixels);
+        StillImage::parse_pgm(&data)
+    }
+
+    fn parse_error(header: &str, pixels: &[u8]) -> String {
+        match pgm(header, pixels) {
+            Ok(_) => panic!("{:?} accepted", header),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn parse_pgm() {
+        // stretched over the whole sensor, scaled to the max value
+        let image = pgm("P5\n# comment\n2 1\n15\n", &[0, 15]).unwrap();
+        let row = &image.pixels[..CAMERA_WIDTH];
+        assert!(row[..CAMERA_WIDTH / 2].iter().all(|&p| p == 0x00));
+        assert!(row[CAMERA_WIDTH / 2..].iter().all(|&p| p == 0xff));
+        assert_eq!(image.pixels[CAMERA_WIDTH * (CAMERA_HEIGHT - 1)], 0x00);
+    }
+
+    #[test]
+    fn parse_pgm_errors() {
+        assert_eq!(parse_error("P2 1 1 255\n", &[0]), "not a binary pgm image");
+        assert_eq!(parse_error("P5 1 1", &[]), "truncated pgm header");
+        assert_eq!(parse_error("P5 1 x 255\n", &[0]), "bad pgm header");
+        assert_eq!(
+            parse_error("P5 1 1 256\n", &[0]),
+            "unsupported pgm size or depth"
+        );
+        assert_eq!(
+            parse_error("P5 0 1 255\n", &[]),
+            "unsupported pgm size or depth"
+        );
+        assert_eq!(parse_error("P5 2 2 255\n", &[0; 3]), "truncated pgm image");
+        // sizes that don't fit in memory
+        let huge = format!("P5 {} {} 255\n", usize::MAX / 2, 3);
+        assert_eq!(parse_error(&huge, &[0]), "bad pgm header");
+        let huge = format!("P5 {} 1 255\n", usize::MAX);
+        assert_eq!(parse_error(&huge, &[0]), "bad pgm header");
+    }
+
+    #[test]
+    fn large_rom_banks() {
+        // 4 MiB, more banks than fit a byte
+        let mut camera = PocketCamera::new(0x40_0000, 0x20000);
+        camera.write(0x2000, 0x3f);
+        assert_eq!(camera.rom_bank(), 0x3f);
+    }
+}
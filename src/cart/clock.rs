@@ -0,0 +1,63 @@
+use crate::GB_CLOCK_SPEED;
+use time::{Duration, Instant};
+
+/// Where the real time clock of a cartridge gets the time from.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ClockSource {
+    /// The host's clock, the cart keeps time while the emulator is closed.
+    Host,
+    /// Emulated cycles, so runs are deterministic. The clock jumps ahead by
+    /// `offset` when switched to.
+    Emulated { offset: std::time::Duration },
+}
+
+/// Time base of a cartridge clock, counting from when the source was set.
+pub(crate) struct RtcClock {
+    source: ClockSource,
+    /// cycles at 4 MHz, in both speed modes
+    cycles: u64,
+    instant: Instant,
+}
+
+impl RtcClock {
+    pub fn new() -> Self {
+        RtcClock {
+            source: ClockSource::Host,
+            cycles: 0,
+            instant: Instant::now(),
+        }
+    }
+
+    pub fn set_source(&mut self, source: ClockSource) {
+        self.source = source;
+        self.cycles = 0;
+        self.instant = Instant::now();
+    }
+
+    /// Whether time passes while the emulator is closed.
+    pub fn is_host(&self) -> bool {
+        self.source == ClockSource::Host
+    }
+
+    pub fn tick(&mut self, cycles: u32) {
+        self.cycles += cycles as u64;
+    }
+
+    pub fn now(&self) -> Duration {
+        match self.source {
+            ClockSource::Host => self.instant.elapsed(),
+            ClockSource::Emulated { offset } => {
+                let nanos = self.cycles as i128 * 1_000_000_000 / GB_CLOCK_SPEED as i128;
+                Duration::nanoseconds(nanos as i64) + offset
+            }
+        }
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn set_cycles(&mut self, cycles: u64) {
+        self.cycles = cycles;
+    }
+}
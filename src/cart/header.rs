@@ -0,0 +1,280 @@
+use super::CartError;
+
+/// end of the cartridge header
+pub const HEADER_END: usize = 0x150;
+
+/// logo the boot rom compares against, and refuses to boot without
+pub const NINTENDO_LOGO: [u8; 48] = [
+    0xce, 0xed, 0x66, 0x66, 0xcc, 0x0d, 0x00, 0x0b, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0c, 0x00, 0x0d,
+    0x00, 0x08, 0x11, 0x1f, 0x88, 0x89, 0x00, 0x0e, 0xdc, 0xcc, 0x6e, 0xe6, 0xdd, 0xdd, 0xd9, 0x99,
+    0xbb, 0xbb, 0x67, 0x63, 0x6e, 0x0e, 0xec, 0xcc, 0xdd, 0xdc, 0x99, 0x9f, 0xbb, 0xb9, 0x33, 0x3e,
+];
+
+/// cart types keeping their memory while off, with a battery, or with the
+/// flash or eeprom of the mbc6 and mbc7
+const BATTERY_TYPES: [u8; 15] = [
+    0x03, 0x06, 0x09, 0x0d, 0x0f, 0x10, 0x13, 0x1b, 0x1e, 0x20, 0x22, 0xfc, 0xfd, 0xfe, 0xff,
+];
+
+/// Whether a real gameboy would boot the header at the start of `rom`, the
+/// logo and the header checksum match.
+pub(crate) fn bootable(rom: &[u8]) -> bool {
+    rom.len() >= HEADER_END
+        && rom[0x104..0x134] == NINTENDO_LOGO[..]
+        && rom[0x14d] == header_checksum(rom)
+}
+
+fn header_checksum(rom: &[u8]) -> u8 {
+    rom[0x134..=0x14c]
+        .iter()
+        .fold(0u8, |sum, &b| sum.wrapping_sub(b).wrapping_sub(1))
+}
+
+/// The cartridge header at 0x0100-0x014f.
+#[derive(Clone, Debug)]
+pub struct CartridgeHeader {
+    pub title: String,
+    /// Only in some later carts, overlapping the end of the title.
+    pub manufacturer_code: [u8; 4],
+    pub cgb_flag: u8,
+    /// Used when `old_licensee` is 0x33.
+    pub new_licensee: [u8; 2],
+    pub sgb_flag: u8,
+    pub cart_type: u8,
+    pub rom_size: usize,
+    pub ram_size: usize,
+    /// 0x00 for Japan, 0x01 for everywhere else.
+    pub destination: u8,
+    pub old_licensee: u8,
+    pub version: u8,
+    pub header_checksum: u8,
+    pub global_checksum: u16,
+
+    logo_valid: bool,
+    computed_header_checksum: u8,
+    computed_global_checksum: u16,
+}
+
+impl CartridgeHeader {
+    /// Parses the header at the start of a rom, checksums are computed over
+    /// the whole rom.
+    pub fn parse(rom: &[u8]) -> Result<Self, CartError> {
+        if rom.len() < HEADER_END {
+            return Err(CartError::TooSmall(rom.len()));
+        }
+
+        let title = std::str::from_utf8(
+            rom[0x134..=0x142]
+                .iter()
+                .copied()
+                .take_while(|x| *x != 0)
+                .collect::<Vec<u8>>()
+                .as_slice(),
+        )
+        .unwrap_or("unkown")
+        .trim_end_matches('\0')
+        .to_owned();
+
+        let rom_size = match rom[0x148] {
+            code @ 0x00..=0x08 => 0x8000 << code,
+            code => return Err(CartError::BadHeader(format!("rom size 0x{:02x}", code))),
+        };
+        let ram_size = match rom[0x149] {
+            0x00 => 0x00,
+            0x01 => 0x800,
+            0x02 => 0x2000,
+            0x03 => 0x8000,
+            0x04 => 0x20000,
+            0x05 => 0x10000,
+            code => return Err(CartError::BadHeader(format!("ram size 0x{:02x}", code))),
+        };
+
+        let mut manufacturer_code = [0u8; 4];
+        manufacturer_code.copy_from_slice(&rom[0x13f..=0x142]);
+        let mut new_licensee = [0u8; 2];
+        new_licensee.copy_from_slice(&rom[0x144..=0x145]);
+
+        let computed_header_checksum = header_checksum(rom);
+        let computed_global_checksum = rom
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| i != 0x14e && i != 0x14f)
+            .fold(0u16, |sum, (_, &b)| sum.wrapping_add(b as u16));
+
+        Ok(CartridgeHeader {
+            title,
+            manufacturer_code,
+            cgb_flag: rom[0x143],
+            new_licensee,
+            sgb_flag: rom[0x146],
+            cart_type: rom[0x147],
+            rom_size,
+            ram_size,
+            destination: rom[0x14a],
+            old_licensee: rom[0x14b],
+            version: rom[0x14c],
+            header_checksum: rom[0x14d],
+            global_checksum: (rom[0x14e] as u16) << 8 | rom[0x14f] as u16,
+
+            logo_valid: rom[0x104..0x134] == NINTENDO_LOGO[..],
+            computed_header_checksum,
+            computed_global_checksum,
+        })
+    }
+
+    /// Supports cgb functions, 0x80 also runs on a dmg and 0xc0 doesn't.
+    pub fn cgb(&self) -> bool {
+        self.cgb_flag == 0xc0 || self.cgb_flag == 0x80
+    }
+
+    pub fn sgb(&self) -> bool {
+        self.sgb_flag == 0x03
+    }
+
+    /// Keeps its memory while switched off, only these carts have a save
+    /// file.
+    pub fn has_battery(&self) -> bool {
+        BATTERY_TYPES.contains(&self.cart_type)
+    }
+
+    /// Licensee code, the new one if the old one says so.
+    pub fn licensee(&self) -> String {
+        if self.old_licensee == 0x33 {
+            String::from_utf8_lossy(&self.new_licensee).into_owned()
+        } else {
+            format!("{:02x}", self.old_licensee)
+        }
+    }
+
+    /// Whether the logo matches, a real gameboy locks up otherwise.
+    pub fn logo_valid(&self) -> bool {
+        self.logo_valid
+    }
+
+    /// Whether the header checksum matches, a real gameboy locks up
+    /// otherwise.
+    pub fn header_checksum_valid(&self) -> bool {
+        self.header_checksum == self.computed_header_checksum
+    }
+
+    /// Whether the global checksum matches, which nothing checks.
+    pub fn global_checksum_valid(&self) -> bool {
+        self.global_checksum == self.computed_global_checksum
+    }
+
+    /// Hardware in the cartridge, as listed in the header.
+    pub fn cart_type_name(&self) -> &'static str {
+        match self.cart_type {
+            0x00 => "ROM ONLY",
+            0x01 => "MBC1",
+            0x02 => "MBC1+RAM",
+            0x03 => "MBC1+RAM+BATTERY",
+            0x05 => "MBC2",
+            0x06 => "MBC2+BATTERY",
+            0x08 => "ROM+RAM",
+            0x09 => "ROM+RAM+BATTERY",
+            0x0b => "MMM01",
+            0x0c => "MMM01+RAM",
+            0x0d => "MMM01+RAM+BATTERY",
+            0x0f => "MBC3+TIMER+BATTERY",
+            0x10 => "MBC3+TIMER+RAM+BATTERY",
+            0x11 => "MBC3",
+            0x12 => "MBC3+RAM",
+            0x13 => "MBC3+RAM+BATTERY",
+            0x19 => "MBC5",
+            0x1a => "MBC5+RAM",
+            0x1b => "MBC5+RAM+BATTERY",
+            0x1c => "MBC5+RUMBLE",
+            0x1d => "MBC5+RUMBLE+RAM",
+            0x1e => "MBC5+RUMBLE+RAM+BATTERY",
+            0x20 => "MBC6",
+            0x22 => "MBC7+SENSOR+RUMBLE+RAM+BATTERY",
+            0xfc => "POCKET CAMERA",
+            0xfd => "BANDAI TAMA5",
+            0xfe => "HuC3",
+            0xff => "HuC1+RAM+BATTERY",
+            _ => "unknown",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cart::test_rom;
+
+    #[test]
+    fn checksums() {
+        let mut rom = test_rom(0x01, 0x10000, 0x00);
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert_eq!(header.title, "TEST");
+        assert!(header.logo_valid());
+        assert!(header.header_checksum_valid());
+        assert!(!header.global_checksum_valid());
+
+        let sum = rom.iter().fold(0u16, |sum, &b| sum.wrapping_add(b as u16));
+        rom[0x14e..0x150].copy_from_slice(&sum.to_be_bytes());
+        assert!(CartridgeHeader::parse(&rom)
+            .unwrap()
+            .global_checksum_valid());
+
+        // the header checksum covers the title
+        rom[0x134] = b'B';
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert!(!header.header_checksum_valid());
+        assert!(!header.global_checksum_valid());
+
+        rom[0x104] = 0;
+        assert!(!CartridgeHeader::parse(&rom).unwrap().logo_valid());
+    }
+
+    #[test]
+    fn sizes() {
+        let mut rom = test_rom(0x00, 0x8000, 0x00);
+        for (code, size) in [(0x00, 0x8000), (0x05, 0x10_0000), (0x08, 0x80_0000)].iter() {
+            rom[0x148] = *code;
+            assert_eq!(CartridgeHeader::parse(&rom).unwrap().rom_size, *size);
+        }
+        rom[0x148] = 0x00;
+        for (code, size) in [(0x00, 0), (0x02, 0x2000), (0x03, 0x8000), (0x05, 0x10000)].iter() {
+            rom[0x149] = *code;
+            assert_eq!(CartridgeHeader::parse(&rom).unwrap().ram_size, *size);
+        }
+    }
+
+    #[test]
+    fn battery() {
+        let battery = |cart_type| {
+            let rom = test_rom(cart_type, 0x8000, 0x00);
+            CartridgeHeader::parse(&rom).unwrap().has_battery()
+        };
+        for &cart_type in [0x03, 0x06, 0x10, 0x13, 0x1b, 0x22, 0xfe].iter() {
+            assert!(battery(cart_type), "0x{:02x}", cart_type);
+        }
+        for &cart_type in [0x00, 0x02, 0x08, 0x12, 0x1a, 0x1d].iter() {
+            assert!(!battery(cart_type), "0x{:02x}", cart_type);
+        }
+    }
+
+    #[test]
+    fn bad_headers() {
+        let mut rom = test_rom(0x00, 0x8000, 0x00);
+        rom[0x148] = 0x52;
+        match CartridgeHeader::parse(&rom) {
+            Err(CartError::BadHeader(msg)) => assert_eq!(msg, "rom size 0x52"),
+            _ => panic!("rom size code 0x52 accepted"),
+        }
+
+        rom[0x148] = 0x00;
+        rom[0x149] = 0x06;
+        match CartridgeHeader::parse(&rom) {
+            Err(CartError::BadHeader(msg)) => assert_eq!(msg, "ram size 0x06"),
+            _ => panic!("ram size code 0x06 accepted"),
+        }
+
+        match CartridgeHeader::parse(&rom[..0x14f]) {
+            Err(CartError::TooSmall(0x14f)) => {}
+            _ => panic!("truncated header accepted"),
+        }
+    }
+}
@@ -0,0 +1,162 @@
+use super::MemoryBankController;
+use crate::state::{Savestate, StateReader, StateWriter};
+
+/// HuC1, an MBC1 like mapper with an infrared LED and receiver in place of
+/// the ram enable.
+pub struct HuC1 {
+    ram: Vec<u8>,
+    writes: u64,
+    rom_bank: u8,
+    ram_bank: u8,
+    /// 0xa000-0xbfff maps the ir port instead of ram
+    ir_mode: bool,
+    ir_led: bool,
+
+    max_rom: usize,
+}
+
+impl HuC1 {
+    pub fn new(rom_size: usize, ram_size: usize) -> Self {
+        HuC1 {
+            ram: vec![0u8; ram_size],
+            writes: 0,
+            rom_bank: 0x01,
+            ram_bank: 0x00,
+            ir_mode: false,
+            ir_led: false,
+
+            max_rom: (rom_size / 0x4000).max(1),
+        }
+    }
+
+    fn ram_addr(&self, addr: u16) -> usize {
+        (self.ram_bank as usize * 0x2000 + (addr - 0xa000) as usize) % self.ram.len()
+    }
+}
+
+impl MemoryBankController for HuC1 {
+    fn read(&self, rom: &[u8], addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x3fff => rom[addr as usize],
+            0x4000..=0x7fff => {
+                let addr = addr as usize - 0x4000;
+                rom[addr + 0x4000 * self.rom_bank as usize]
+            }
+            // no other gameboy's led to see, bit 0 is set when light is received
+            0xa000..=0xbfff if self.ir_mode => 0xc0,
+            0xa000..=0xbfff if !self.ram.is_empty() => self.ram[self.ram_addr(addr)],
+            0xa000..=0xbfff => 0xff,
+
+            _ => unreachable!(),
+        }
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x0000..=0x1fff => self.ir_mode = data & 0x0f == 0x0e,
+            0x2000..=0x3fff => self.rom_bank = ((data & 0x3f).max(1) as usize % self.max_rom) as u8,
+            0x4000..=0x5fff => self.ram_bank = data & 0x03,
+            0x6000..=0x7fff => {}
+            0xa000..=0xbfff if self.ir_mode => self.ir_led = data & 0x01 != 0,
+            0xa000..=0xbfff => {
+                if !self.ram.is_empty() {
+                    let addr = self.ram_addr(addr);
+                    self.ram[addr] = data;
+                    self.writes += 1;
+                }
+            }
+
+            _ => unreachable!(),
+        }
+    }
+
+    fn get_ram(&self) -> Option<&[u8]> {
+        if !self.ram.is_empty() {
+            Some(self.ram.as_slice())
+        } else {
+            None
+        }
+    }
+
+    fn get_ram_mut(&mut self) -> Option<&mut [u8]> {
+        if !self.ram.is_empty() {
+            Some(self.ram.as_mut_slice())
+        } else {
+            None
+        }
+    }
+
+    fn writes(&self) -> u64 {
+        self.writes
+    }
+
+    fn mbc_type(&self) -> &'static str {
+        "HuC1"
+    }
+
+    fn rom_bank(&self) -> usize {
+        self.rom_bank as usize
+    }
+}
+
+impl Savestate for HuC1 {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_bytes(&self.ram);
+        w.write_u8(self.rom_bank);
+        w.write_u8(self.ram_bank);
+        w.write_bool(self.ir_mode);
+        w.write_bool(self.ir_led);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> std::io::Result<()> {
+        r.read_bytes(&mut self.ram)?;
+        self.rom_bank = (r.read_u8()? as usize % self.max_rom) as u8;
+        self.ram_bank = r.read_u8()? & 0x03;
+        self.ir_mode = r.read_bool()?;
+        self.ir_led = r.read_bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ir_port() {
+        let rom = vec![0u8; 0x8000];
+        let mut huc1 = HuC1::new(rom.len(), 0x2000);
+        huc1.write(0xa000, 0x12);
+        assert_eq!(huc1.read(&rom, 0xa000), 0x12);
+
+        huc1.write(0x0000, 0x0e);
+        assert_eq!(huc1.read(&rom, 0xa000), 0xc0);
+        huc1.write(0xa000, 0x01);
+        assert!(huc1.ir_led);
+        huc1.write(0xa000, 0x00);
+        assert!(!huc1.ir_led);
+
+        // any other value maps the ram back
+        huc1.write(0x0000, 0x0a);
+        assert_eq!(huc1.read(&rom, 0xa000), 0x12);
+        assert_eq!(huc1.writes(), 1);
+    }
+
+    #[test]
+    fn banks() {
+        let mut rom = vec![0u8; 0x40_0000];
+        for (bank, data) in rom.chunks_mut(0x4000).enumerate() {
+            data[0] = bank as u8;
+        }
+        // more banks than fit a byte
+        let mut huc1 = HuC1::new(rom.len(), 0x8000);
+        huc1.write(0x2000, 0x25);
+        assert_eq!(huc1.read(&rom, 0x4000), 0x25);
+        huc1.write(0x2000, 0x00);
+        assert_eq!(huc1.read(&rom, 0x4000), 0x01);
+
+        huc1.write(0x4000, 0x02);
+        huc1.write(0xa001, 0x34);
+        assert_eq!(huc1.get_ram().unwrap()[0x4001], 0x34);
+    }
+}
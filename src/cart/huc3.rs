@@ -0,0 +1,482 @@
+use super::clock::RtcClock;
+use super::{unix_time, ClockSource, MemoryBankController};
+use crate::state::{Savestate, StateReader, StateWriter};
+use std::convert::TryInto;
+use time::Duration;
+
+/// clock counters wrap after 4096 days
+const MAX_DAYS: i64 = 0x1000;
+/// alarm minutes, days and enable in the rtc's nibble memory
+const ALARM_MINUTES: usize = 0x58;
+const ALARM_DAYS: usize = 0x5b;
+const ALARM_ENABLE: usize = 0x5f;
+/// tone the speaker plays, set before starting it
+const TONE: usize = 0x27;
+/// rtc block of the save file, laid out like SameBoy's
+const RTC_SAVE_SIZE: usize = 17;
+
+/// HuC3, with a real time clock, a piezo speaker and an infrared port.
+///
+/// Writes to 0x0000-0x1fff select what 0xa000-0xbfff maps. The rtc is a
+/// small microcontroller with 256 nibbles of memory, talked to by writing a
+/// command, then reading the response.
+pub struct HuC3 {
+    ram: Vec<u8>,
+    writes: u64,
+    rom_bank: u8,
+    ram_bank: u8,
+    /// 0x0 ram read only, 0xa ram, 0xb command, 0xc response, 0xd semaphore,
+    /// 0xe ir
+    mode: u8,
+    ir_led: bool,
+
+    max_rom: usize,
+
+    memory: [u8; 0x100],
+    addr: u8,
+    response: u8,
+    /// tone the speaker is playing
+    tone: Option<u8>,
+
+    /// minutes and days of the clock when `mark` was taken
+    current: Duration,
+    /// clock time when `current` was set
+    mark: Duration,
+    clock: RtcClock,
+}
+
+impl HuC3 {
+    pub fn new(rom_size: usize, ram_size: usize) -> Self {
+        HuC3 {
+            ram: vec![0u8; ram_size],
+            writes: 0,
+            rom_bank: 0x01,
+            ram_bank: 0x00,
+            mode: 0x00,
+            ir_led: false,
+
+            max_rom: (rom_size / 0x4000).max(1),
+
+            memory: [0u8; 0x100],
+            addr: 0x00,
+            response: 0x00,
+            tone: None,
+
+            current: Duration::zero(),
+            mark: Duration::zero(),
+            clock: RtcClock::new(),
+        }
+    }
+
+    fn ram_addr(&self, addr: u16) -> usize {
+        (self.ram_bank as usize * 0x2000 + (addr - 0xa000) as usize) % self.ram.len()
+    }
+
+    /// time since `current` was set
+    fn elapsed(&self) -> Duration {
+        self.clock.now() - self.mark
+    }
+
+    /// minutes of the day and days, 12 bits each
+    fn clock(&self) -> (u16, u16) {
+        let now = self.current + self.elapsed();
+        let minutes = now.whole_minutes() % (24 * 60);
+        let days = now.whole_days() % MAX_DAYS;
+        (minutes as u16, days as u16)
+    }
+
+    fn set_clock(&mut self, minutes: u16, days: u16) {
+        self.current = Duration::minutes(minutes as i64) + Duration::days(days as i64);
+        self.mark = self.clock.now();
+    }
+
+    fn nibbles(&self, addr: usize) -> u16 {
+        (0..3).fold(0, |value, i| {
+            value | (self.memory[addr + i] as u16) << (i * 4)
+        })
+    }
+
+    fn set_nibbles(&mut self, addr: usize, value: u16) {
+        for i in 0..3 {
+            self.memory[addr + i] = (value >> (i * 4)) as u8 & 0x0f;
+        }
+    }
+
+    fn command(&mut self, data: u8) {
+        let arg = data & 0x0f;
+        match data >> 4 {
+            // read, then increment the address
+            0x1 => {
+                self.response = 0x10 | self.memory[self.addr as usize];
+                self.addr = self.addr.wrapping_add(1);
+            }
+            // write, 0x3 also increments the address
+            0x2 | 0x3 => {
+                self.memory[self.addr as usize] = arg;
+                self.writes += 1;
+                if data >> 4 == 0x3 {
+                    self.addr = self.addr.wrapping_add(1);
+                }
+            }
+            0x4 => self.addr = (self.addr & 0xf0) | arg,
+            0x5 => self.addr = (self.addr & 0x0f) | arg << 4,
+            0x6 => self.extended(arg),
+            _ => {}
+        }
+    }
+
+    fn extended(&mut self, arg: u8) {
+        match arg {
+            // copy the clock to memory
+            0x0 => {
+                let (minutes, days) = self.clock();
+                self.set_nibbles(0x00, minutes);
+                self.set_nibbles(0x03, days);
+            }
+            // set the clock from memory
+            0x1 => {
+                let minutes = self.nibbles(0x00) % (24 * 60);
+                let days = self.nibbles(0x03);
+                self.set_clock(minutes, days);
+                self.writes += 1;
+            }
+            // status, always ready
+            0x2 => self.response = 0x61,
+            0xe => self.tone = Some(self.memory[TONE]),
+            0xf => self.tone = None,
+            _ => {}
+        }
+    }
+}
+
+impl MemoryBankController for HuC3 {
+    fn read(&self, rom: &[u8], addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x3fff => rom[addr as usize],
+            0x4000..=0x7fff => {
+                let addr = addr as usize - 0x4000;
+                rom[addr + 0x4000 * self.rom_bank as usize]
+            }
+            0xa000..=0xbfff => match self.mode {
+                0x0 | 0xa if !self.ram.is_empty() => self.ram[self.ram_addr(addr)],
+                0xc => 0x80 | self.response,
+                // commands finish at once
+                0xd => 0xff,
+                // no other gameboy's led to see, bit 0 is set when light is received
+                0xe => 0xc0,
+                _ => 0xff,
+            },
+
+            _ => unreachable!(),
+        }
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x0000..=0x1fff => self.mode = data & 0x0f,
+            0x2000..=0x3fff => self.rom_bank = ((data & 0x7f).max(1) as usize % self.max_rom) as u8,
+            0x4000..=0x5fff => self.ram_bank = data & 0x03,
+            0x6000..=0x7fff => {}
+            0xa000..=0xbfff => match self.mode {
+                0xa if !self.ram.is_empty() => {
+                    let addr = self.ram_addr(addr);
+                    self.ram[addr] = data;
+                    self.writes += 1;
+                }
+                0xb => self.command(data & 0x7f),
+                0xe => self.ir_led = data & 0x01 != 0,
+                _ => {}
+            },
+
+            _ => unreachable!(),
+        }
+    }
+
+    fn get_ram(&self) -> Option<&[u8]> {
+        if !self.ram.is_empty() {
+            Some(self.ram.as_slice())
+        } else {
+            None
+        }
+    }
+
+    fn get_ram_mut(&mut self) -> Option<&mut [u8]> {
+        if !self.ram.is_empty() {
+            Some(self.ram.as_mut_slice())
+        } else {
+            None
+        }
+    }
+
+    fn save_rtc(&self) -> Option<Vec<u8>> {
+        let (minutes, days) = self.clock();
+        let timestamp = unix_time();
+
+        let mut data = Vec::with_capacity(RTC_SAVE_SIZE);
+        data.extend_from_slice(&timestamp.to_le_bytes());
+        data.extend_from_slice(&minutes.to_le_bytes());
+        data.extend_from_slice(&days.to_le_bytes());
+        data.extend_from_slice(&self.nibbles(ALARM_MINUTES).to_le_bytes());
+        data.extend_from_slice(&self.nibbles(ALARM_DAYS).to_le_bytes());
+        data.push(self.memory[ALARM_ENABLE] & 0x01);
+        Some(data)
+    }
+
+    fn load_rtc(&mut self, data: &[u8]) {
+        if data.len() < RTC_SAVE_SIZE {
+            return;
+        }
+        let u16_at = |i: usize| u16::from_le_bytes([data[i], data[i + 1]]);
+
+        let timestamp = u64::from_le_bytes(data[0..8].try_into().unwrap());
+        self.set_clock(u16_at(8) % (24 * 60), u16_at(10) & 0x0fff);
+        self.set_nibbles(ALARM_MINUTES, u16_at(12));
+        self.set_nibbles(ALARM_DAYS, u16_at(14));
+        self.memory[ALARM_ENABLE] = data[16] & 0x01;
+
+        // the clock kept running while the emulator was closed
+        if self.clock.is_host() {
+            self.current += Duration::seconds(unix_time().saturating_sub(timestamp) as i64);
+        }
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        self.clock.tick(cycles);
+    }
+
+    fn set_clock_source(&mut self, source: ClockSource) {
+        // the clock keeps its time, then counts on from the new source
+        self.current += self.elapsed();
+        self.clock.set_source(source);
+        self.mark = Duration::zero();
+    }
+
+    fn writes(&self) -> u64 {
+        self.writes
+    }
+
+    fn mbc_type(&self) -> &'static str {
+        "HuC3"
+    }
+
+    fn rom_bank(&self) -> usize {
+        self.rom_bank as usize
+    }
+
+    fn tone(&self) -> Option<u8> {
+        self.tone
+    }
+}
+
+impl Savestate for HuC3 {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_bytes(&self.ram);
+        w.write_u8(self.rom_bank);
+        w.write_u8(self.ram_bank);
+        w.write_u8(self.mode);
+        w.write_bool(self.ir_led);
+
+        w.write_bytes(&self.memory);
+        w.write_u8(self.addr);
+        w.write_u8(self.response);
+        w.write_u8(self.tone.unwrap_or(0xff));
+
+        w.write_i64(self.current.whole_nanoseconds() as i64);
+        w.write_i64(self.elapsed().whole_nanoseconds() as i64);
+        w.write_u64(self.clock.cycles());
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> std::io::Result<()> {
+        r.read_bytes(&mut self.ram)?;
+        self.rom_bank = (r.read_u8()? as usize % self.max_rom) as u8;
+        self.ram_bank = r.read_u8()? & 0x03;
+        self.mode = r.read_u8()? & 0x0f;
+        self.ir_led = r.read_bool()?;
+
+        r.read_bytes(&mut self.memory)?;
+        self.memory.iter_mut().for_each(|n| *n &= 0x0f);
+        self.addr = r.read_u8()?;
+        self.response = r.read_u8()? & 0x7f;
+        self.tone = match r.read_u8()? {
+            0xff => None,
+            tone => Some(tone & 0x0f),
+        };
+
+        self.current = Duration::nanoseconds(r.read_i64()?);
+        let elapsed = Duration::nanoseconds(r.read_i64()?);
+        self.clock.set_cycles(r.read_u64()?);
+        self.mark = self.clock.now() - elapsed;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GB_CLOCK_SPEED;
+
+    fn emulated_huc3() -> HuC3 {
+        let mut huc3 = HuC3::new(0x8000, 0x2000);
+        huc3.set_clock_source(ClockSource::Emulated {
+            offset: Default::default(),
+        });
+        huc3
+    }
+
+    /// Sends a command, then reads the response.
+    fn command(huc3: &mut HuC3, data: u8) -> u8 {
+        huc3.write(0x0000, 0x0b);
+        huc3.write(0xa000, data);
+        huc3.write(0x0000, 0x0c);
+        huc3.read(&[], 0xa000)
+    }
+
+    fn set_addr(huc3: &mut HuC3, addr: u8) {
+        command(huc3, 0x40 | (addr & 0x0f));
+        command(huc3, 0x50 | addr >> 4);
+    }
+
+    /// Reads 3 nibbles from `addr` on.
+    fn read_nibbles(huc3: &mut HuC3, addr: u8) -> u16 {
+        set_addr(huc3, addr);
+        (0..3).fold(0, |value, i| {
+            value | ((command(huc3, 0x10) & 0x0f) as u16) << (i * 4)
+        })
+    }
+
+    fn write_nibbles(huc3: &mut HuC3, addr: u8, value: u16) {
+        set_addr(huc3, addr);
+        for i in 0..3 {
+            command(huc3, 0x30 | (value >> (i * 4)) as u8 & 0x0f);
+        }
+    }
+
+    fn tick_minutes(huc3: &mut HuC3, minutes: u32) {
+        for _ in 0..minutes {
+            huc3.tick(GB_CLOCK_SPEED * 60);
+        }
+    }
+
+    #[test]
+    fn memory_commands() {
+        let mut huc3 = emulated_huc3();
+        set_addr(&mut huc3, 0x10);
+        command(&mut huc3, 0x35);
+        command(&mut huc3, 0x3a);
+        // 0x2 leaves the address where it is
+        command(&mut huc3, 0x27);
+        command(&mut huc3, 0x28);
+
+        set_addr(&mut huc3, 0x10);
+        assert_eq!(command(&mut huc3, 0x10), 0x95);
+        assert_eq!(command(&mut huc3, 0x10), 0x9a);
+        assert_eq!(command(&mut huc3, 0x10), 0x98);
+        assert_eq!(huc3.writes(), 4);
+
+        // status
+        assert_eq!(command(&mut huc3, 0x62), 0xe1);
+        // commands are done at once
+        huc3.write(0x0000, 0x0d);
+        assert_eq!(huc3.read(&[], 0xa000), 0xff);
+    }
+
+    #[test]
+    fn clock_commands() {
+        let mut huc3 = emulated_huc3();
+        tick_minutes(&mut huc3, 90);
+        command(&mut huc3, 0x60);
+        assert_eq!(read_nibbles(&mut huc3, 0x00), 90);
+        assert_eq!(read_nibbles(&mut huc3, 0x03), 0);
+
+        write_nibbles(&mut huc3, 0x00, 23 * 60 + 59);
+        write_nibbles(&mut huc3, 0x03, 0x123);
+        command(&mut huc3, 0x61);
+        tick_minutes(&mut huc3, 2);
+        command(&mut huc3, 0x60);
+        assert_eq!(read_nibbles(&mut huc3, 0x00), 1);
+        assert_eq!(read_nibbles(&mut huc3, 0x03), 0x124);
+    }
+
+    #[test]
+    fn tone_commands() {
+        let mut huc3 = emulated_huc3();
+        assert_eq!(huc3.tone(), None);
+        set_addr(&mut huc3, TONE as u8);
+        command(&mut huc3, 0x25);
+        command(&mut huc3, 0x6e);
+        assert_eq!(huc3.tone(), Some(5));
+        command(&mut huc3, 0x6f);
+        assert_eq!(huc3.tone(), None);
+    }
+
+    #[test]
+    fn ir_port() {
+        let rom = vec![0u8; 0x8000];
+        let mut huc3 = emulated_huc3();
+        huc3.write(0x0000, 0x0a);
+        huc3.write(0xa000, 0x12);
+
+        huc3.write(0x0000, 0x0e);
+        assert_eq!(huc3.read(&rom, 0xa000), 0xc0);
+        huc3.write(0xa000, 0x01);
+        assert!(huc3.ir_led);
+        huc3.write(0xa000, 0x00);
+        assert!(!huc3.ir_led);
+
+        // the ram is untouched, and read only in mode 0
+        huc3.write(0x0000, 0x00);
+        huc3.write(0xa000, 0x34);
+        assert_eq!(huc3.read(&rom, 0xa000), 0x12);
+        assert_eq!(huc3.writes(), 1);
+    }
+
+    #[test]
+    fn save_footer() {
+        let mut huc3 = emulated_huc3();
+        huc3.set_clock(100, 3);
+        write_nibbles(&mut huc3, ALARM_MINUTES as u8, 0x2bc);
+        write_nibbles(&mut huc3, ALARM_DAYS as u8, 0x00a);
+        set_addr(&mut huc3, ALARM_ENABLE as u8);
+        command(&mut huc3, 0x21);
+
+        let footer = huc3.save_rtc().unwrap();
+        assert_eq!(footer.len(), RTC_SAVE_SIZE);
+        assert_eq!(footer[8..], [100, 0, 3, 0, 0xbc, 0x02, 0x0a, 0x00, 0x01]);
+
+        let mut loaded = emulated_huc3();
+        loaded.load_rtc(&footer);
+        assert_eq!(loaded.clock(), (100, 3));
+        assert_eq!(loaded.save_rtc().unwrap()[8..], footer[8..]);
+
+        // too short to be a clock
+        let mut short = emulated_huc3();
+        short.load_rtc(&footer[..RTC_SAVE_SIZE - 1]);
+        assert_eq!(short.clock(), (0, 0));
+    }
+
+    #[test]
+    fn host_clock_catches_up() {
+        let mut footer = emulated_huc3().save_rtc().unwrap();
+        let saved = unix_time() - 2 * 60 * 60;
+        footer[..8].copy_from_slice(&saved.to_le_bytes());
+
+        let mut huc3 = HuC3::new(0x8000, 0x2000);
+        huc3.load_rtc(&footer);
+        assert_eq!(huc3.clock(), (120, 0));
+
+        // but not on emulated time
+        let mut huc3 = emulated_huc3();
+        huc3.load_rtc(&footer);
+        assert_eq!(huc3.clock(), (0, 0));
+    }
+
+    #[test]
+    fn large_rom_banks() {
+        // 4 MiB, more banks than fit a byte
+        let mut huc3 = HuC3::new(0x40_0000, 0);
+        huc3.write(0x2000, 0x45);
+        assert_eq!(huc3.rom_bank(), 0x45);
+        huc3.write(0x2000, 0x00);
+        assert_eq!(huc3.rom_bank(), 1);
+    }
+}
@@ -1,4 +1,5 @@
 use super::MemoryBankController;
+use crate::state::{Savestate, StateReader, StateWriter};
 
 pub struct MBC0;
 
@@ -22,3 +23,11 @@ impl MemoryBankController for MBC0 {
         "MBC0"
     }
 }
+
+impl Savestate for MBC0 {
+    fn save_state(&self, _w: &mut StateWriter) {}
+
+    fn load_state(&mut self, _r: &mut StateReader) -> std::io::Result<()> {
+        Ok(())
+    }
+}
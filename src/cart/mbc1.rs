@@ -1,46 +1,96 @@
-use super::MemoryBankController;
+use super::{MemoryBankController, NINTENDO_LOGO};
+use crate::state::{invalid_state, Savestate, StateReader, StateWriter};
+
+/// multicarts are all 8 Mbit, with a game every 16 banks
+const MULTICART_SIZE: usize = 0x10_0000;
 
 enum Mode {
+    /// bank2 only selects upper rom banks at 0x4000-0x7fff
     Rom,
+    /// bank2 also selects the ram bank and the rom bank at 0x0000-0x3fff
     Ram,
 }
 
 pub struct MBC1 {
     ram: Vec<u8>,
-    rom_bank: u8,
-    ram_bank: u8,
+    writes: u64,
+    /// 5 bit register at 0x2000-0x3fff, never 0
+    bank1: u8,
+    /// 2 bit register at 0x4000-0x5fff
+    bank2: u8,
     ram_enable: bool,
     mode: Mode,
 
-    max_rom: u8,
+    rom_banks: usize,
+    /// MBC1M wires bank2 one bit lower, leaving bank1 4 bits
+    multicart: bool,
 }
 
 impl MBC1 {
-    pub fn new(rom_size: usize, ram_size: usize) -> Self {
+    pub fn new(rom_size: usize, ram_size: usize, multicart: bool) -> Self {
         MBC1 {
             ram: vec![0u8; ram_size],
-            rom_bank: 0x01,
-            ram_bank: 0x00,
+            writes: 0,
+            bank1: 0x01,
+            bank2: 0x00,
             ram_enable: false,
             mode: Mode::Rom,
 
-            max_rom: (rom_size / 0x4000) as u8,
+            rom_banks: (rom_size / 0x4000).max(1),
+            multicart,
+        }
+    }
+
+    /// Whether a rom is a multicart, found by the logo of a second game's
+    /// header at bank 0x10.
+    pub fn is_multicart(rom: &[u8]) -> bool {
+        rom.len() == MULTICART_SIZE && rom[0x40104..0x40134] == NINTENDO_LOGO[..]
+    }
+
+    fn bank2_shift(&self) -> u8 {
+        if self.multicart {
+            4
+        } else {
+            5
+        }
+    }
+
+    fn low_bank(&self) -> usize {
+        match self.mode {
+            Mode::Rom => 0,
+            Mode::Ram => ((self.bank2 << self.bank2_shift()) as usize) % self.rom_banks,
         }
     }
+
+    fn high_bank(&self) -> usize {
+        let bank1 = if self.multicart {
+            self.bank1 & 0x0f
+        } else {
+            self.bank1
+        };
+        ((self.bank2 << self.bank2_shift()) | bank1) as usize % self.rom_banks
+    }
+
+    fn ram_addr(&self, addr: u16) -> usize {
+        let bank = match self.mode {
+            Mode::Rom => 0,
+            Mode::Ram => self.bank2 as usize,
+        };
+        (bank * 0x2000 + (addr - 0xa000) as usize) % self.ram.len()
+    }
 }
 
 impl MemoryBankController for MBC1 {
     fn read(&self, rom: &[u8], addr: u16) -> u8 {
         match addr {
-            0x0000..=0x3fff => rom[addr as usize],
+            0x0000..=0x3fff => rom[addr as usize + 0x4000 * self.low_bank()],
             0x4000..=0x7fff => {
                 let addr = addr as usize - 0x4000;
-                rom[addr + 0x4000 * self.rom_bank as usize]
+                rom[addr + 0x4000 * self.high_bank()]
             }
             0xa000..=0xbfff => {
-                let addr = (addr - 0xa000) as usize;
                 if self.ram_enable {
-                    self.ram[addr + self.ram_bank as usize * 0x2000]
+                    self.ram[self.ram_addr(addr)]
                 } else {
                     0xff
                 }
@@ -51,44 +101,27 @@ impl MemoryBankController for MBC1 {
     }
 
     fn write(&mut self, addr: u16, data: u8) {
-        fn map_rom_bank(n: u8) -> u8 {
-            match n {
-                0x00 | 0x20 | 0x40 | 0x60 => n + 1,
-                _ => n,
-            }
-        }
-
         match addr {
             // enable ram
             0x0000..=0x1fff => self.ram_enable = self.ram.len() > 0 && (data & 0x0f == 0x0a),
-            // lower 5 bits of rom_bank
-            0x2000..=0x3fff => {
-                let bank = ((self.rom_bank & 0b0110_0000) | (data & 0x1f)) % self.max_rom;
-                self.rom_bank = map_rom_bank(bank);
-            }
-            // upper 5-6bits of ram/rom bank
-            0x4000..=0x5fff => match self.mode {
-                Mode::Rom => {
-                    let bank = ((self.rom_bank & 0x1f) | (data & 0b11) << 5) % self.max_rom;
-                    self.rom_bank = map_rom_bank(bank);
-                }
-                Mode::Ram => self.ram_bank = data & 0b11,
-            },
+            // lower 5 bits of rom bank, 0 reads as 1
+            0x2000..=0x3fff => self.bank1 = (data & 0x1f).max(1),
+            // upper 2 bits of rom bank, or ram bank
+            0x4000..=0x5fff => self.bank2 = data & 0b11,
             // mode select
             0x6000..=0x7fff => {
                 self.mode = if data & 0b01 == 1 {
-                    self.rom_bank = map_rom_bank(self.rom_bank % 0x20);
                     Mode::Ram
                 } else {
-                    self.ram_bank = 0x00;
                     Mode::Rom
                 };
             }
             // write extern ram banks
             0xa000..=0xbfff => {
                 if self.ram_enable {
-                    let addr = addr - 0xa000 + self.ram_bank as u16 * 0x2000;
-                    self.ram[addr as usize] = data;
+                    let addr = self.ram_addr(addr);
+                    self.ram[addr] = data;
+                    self.writes += 1;
                 }
             }
 
@@ -112,7 +145,157 @@ impl MemoryBankController for MBC1 {
         }
     }
 
+    fn writes(&self) -> u64 {
+        self.writes
+    }
+
     fn mbc_type(&self) -> &'static str {
-        "MBC1"
+        if self.multicart {
+            "MBC1M"
+        } else {
+            "MBC1"
+        }
+    }
+
+    fn rom_bank(&self) -> usize {
+        self.high_bank()
+    }
+}
+
+impl Savestate for MBC1 {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_bytes(&self.ram);
+        w.write_u8(self.bank1);
+        w.write_u8(self.bank2);
+        w.write_bool(self.ram_enable);
+        w.write_u8(match self.mode {
+            Mode::Rom => 0,
+            Mode::Ram => 1,
+        });
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> std::io::Result<()> {
+        r.read_bytes(&mut self.ram)?;
+        self.bank1 = (r.read_u8()? & 0x1f).max(1);
+        self.bank2 = r.read_u8()? & 0b11;
+        self.ram_enable = r.read_bool()? && !self.ram.is_empty();
+        self.mode = match r.read_u8()? {
+            0 => Mode::Rom,
+            1 => Mode::Ram,
+            _ => return Err(invalid_state("bad mbc1 mode")),
+        };
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// every bank starts with its number
+    fn numbered_rom(size: usize) -> Vec<u8> {
+        let mut rom = vec![0u8; size];
+        for (bank, data) in rom.chunks_mut(0x4000).enumerate() {
+            data[0] = bank as u8;
+        }
+        rom
+    }
+
+    fn banks(mbc: &MBC1, rom: &[u8]) -> (u8, u8) {
+        (mbc.read(rom, 0x0000), mbc.read(rom, 0x4000))
+    }
+
+    #[test]
+    fn rom_banks() {
+        let rom = numbered_rom(0x20_0000);
+        let mut mbc = MBC1::new(rom.len(), 0, false);
+        assert_eq!(banks(&mbc, &rom), (0x00, 0x01));
+
+        mbc.write(0x2000, 0x00);
+        assert_eq!(banks(&mbc, &rom), (0x00, 0x01));
+        mbc.write(0x2000, 0x3f);
+        assert_eq!(banks(&mbc, &rom), (0x00, 0x1f));
+        // only the low 5 bits count for the zero check
+        mbc.write(0x2000, 0x20);
+        assert_eq!(banks(&mbc, &rom), (0x00, 0x01));
+
+        mbc.write(0x4000, 0x03);
+        assert_eq!(banks(&mbc, &rom), (0x00, 0x61));
+        mbc.write(0x6000, 0x01);
+        assert_eq!(banks(&mbc, &rom), (0x60, 0x61));
+        assert_eq!(mbc.rom_bank(), 0x61);
+    }
+
+    #[test]
+    fn small_rom_wraps() {
+        let rom = numbered_rom(0x1_0000);
+        let mut mbc = MBC1::new(rom.len(), 0, false);
+        mbc.write(0x2000, 0x05);
+        assert_eq!(banks(&mbc, &rom), (0x00, 0x01));
+        mbc.write(0x4000, 0x01);
+        mbc.write(0x6000, 0x01);
+        assert_eq!(banks(&mbc, &rom), (0x00, 0x01));
+    }
+
+    #[test]
+    fn ram_banks() {
+        let rom = numbered_rom(0x8000);
+        let mut mbc = MBC1::new(rom.len(), 0x8000, false);
+        assert_eq!(mbc.read(&rom, 0xa000), 0xff);
+
+        mbc.write(0x0000, 0x0a);
+        mbc.write(0x4000, 0x02);
+        mbc.write(0xa000, 0x12);
+        // bank 0 until ram banking mode
+        assert_eq!(mbc.get_ram().unwrap()[0x0000], 0x12);
+
+        mbc.write(0x6000, 0x01);
+        mbc.write(0xa001, 0x34);
+        assert_eq!(mbc.get_ram().unwrap()[0x4001], 0x34);
+        assert_eq!(mbc.read(&rom, 0xa001), 0x34);
+        assert_eq!(mbc.writes(), 2);
+
+        mbc.write(0x0000, 0x00);
+        assert_eq!(mbc.read(&rom, 0xa001), 0xff);
+    }
+
+    #[test]
+    fn multicart_banks() {
+        let mut rom = numbered_rom(MULTICART_SIZE);
+        assert!(!MBC1::is_multicart(&rom));
+        rom[0x40104..0x40134].copy_from_slice(&NINTENDO_LOGO);
+        assert!(MBC1::is_multicart(&rom));
+
+        let mut mbc = MBC1::new(rom.len(), 0, true);
+        // bank1 loses its top bit
+        mbc.write(0x2000, 0x12);
+        assert_eq!(banks(&mbc, &rom), (0x00, 0x02));
+        mbc.write(0x2000, 0x10);
+        assert_eq!(banks(&mbc, &rom), (0x00, 0x00));
+
+        mbc.write(0x2000, 0x01);
+        mbc.write(0x4000, 0x01);
+        assert_eq!(banks(&mbc, &rom), (0x00, 0x11));
+        mbc.write(0x6000, 0x01);
+        assert_eq!(banks(&mbc, &rom), (0x10, 0x11));
+        mbc.write(0x4000, 0x03);
+        assert_eq!(banks(&mbc, &rom), (0x30, 0x31));
+    }
+
+    #[test]
+    fn state_without_ram_keeps_ram_disabled() {
+        let rom = numbered_rom(0x8000);
+        let mut w = StateWriter::new();
+        w.write_u8(0x01);
+        w.write_u8(0x00);
+        // ram enabled, as a corrupt state could have it
+        w.write_bool(true);
+        w.write_u8(0);
+        let state = w.into_inner();
+
+        let mut mbc = MBC1::new(rom.len(), 0, false);
+        mbc.load_state(&mut StateReader::new(&state).unwrap())
+            .unwrap();
+        assert_eq!(mbc.read(&rom, 0xa000), 0xff);
     }
 }
@@ -1,21 +1,24 @@
 use super::MemoryBankController;
+use crate::state::{Savestate, StateReader, StateWriter};
 
 pub struct MBC2 {
     ram: Vec<u8>,
+    writes: u64,
     rom_bank: u8,
     ram_enable: bool,
 
-    max_rom: u8,
+    max_rom: usize,
 }
 
 impl MBC2 {
     pub fn new(rom_size: usize) -> Self {
         MBC2 {
             ram: vec![0u8; 0x200],
+            writes: 0,
             rom_bank: 0x01,
             ram_enable: false,
 
-            max_rom: (rom_size / 0x4000) as u8,
+            max_rom: rom_size / 0x4000,
         }
     }
 }
@@ -57,13 +60,14 @@ impl MemoryBankController for MBC2 {
             // select rom_bank
             0x2000..=0x3fff => {
                 if addr & 0x100 != 0 {
-                    self.rom_bank = map_rom_bank((data & 0x0f) % self.max_rom);
+                    self.rom_bank = map_rom_bank(((data & 0x0f) as usize % self.max_rom) as u8);
                 }
             }
             // read extern ram banks
             0xa000..=0xa1ff => {
                 if self.ram_enable {
                     self.ram[addr as usize - 0xa000] = data & 0x0f;
+                    self.writes += 1;
                 }
             }
 
@@ -79,7 +83,30 @@ impl MemoryBankController for MBC2 {
         Some(self.ram.as_mut_slice())
     }
 
+    fn writes(&self) -> u64 {
+        self.writes
+    }
+
     fn mbc_type(&self) -> &'static str {
         "MBC2"
     }
+
+    fn rom_bank(&self) -> usize {
+        self.rom_bank as usize
+    }
+}
+
+impl Savestate for MBC2 {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_bytes(&self.ram);
+        w.write_u8(self.rom_bank);
+        w.write_bool(self.ram_enable);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> std::io::Result<()> {
+        r.read_bytes(&mut self.ram)?;
+        self.rom_bank = ((r.read_u8()? & 0x0f) as usize % self.max_rom) as u8;
+        self.ram_enable = r.read_bool()?;
+        Ok(())
+    }
 }
@@ -1,5 +1,14 @@
-use super::MemoryBankController;
-use time::{Duration, Instant};
+use super::clock::RtcClock;
+use super::{unix_time, ClockSource, MemoryBankController};
+use crate::state::{invalid_state, Savestate, StateReader, StateWriter};
+use std::convert::TryInto;
+use time::Duration;
+
+/// rtc footer of the save file, the registers and the latched registers as
+/// u32s then a u64 timestamp, as VBA and BGB write it
+const RTC_FOOTER_SIZE: usize = 48;
+/// older footer with a u32 timestamp
+const RTC_FOOTER_SIZE_32: usize = 44;
 
 enum Mode {
     Ram,
@@ -16,11 +25,14 @@ enum RtcMode {
 
 pub struct MBC3 {
     ram: Vec<u8>,
+    writes: u64,
     rom_bank: u8,
     ram_bank: u8,
     ram_enable: bool,
 
-    max_rom: u8,
+    max_rom: usize,
+    /// carts with a timer save it after the ram
+    rtc: bool,
 
     mode: Mode,
     latch: Latch,
@@ -28,21 +40,25 @@ pub struct MBC3 {
     rtc_mode: RtcMode,
 
     current: Duration,
-    instant: Instant,
+    /// clock time when `current` was set
+    mark: Duration,
+    clock: RtcClock,
 
     carry: bool,
     halt: bool,
 }
 
 impl MBC3 {
-    pub fn new(rom_size: usize, ram_size: usize) -> Self {
+    pub fn new(rom_size: usize, ram_size: usize, rtc: bool) -> Self {
         MBC3 {
             ram: vec![0u8; ram_size],
+            writes: 0,
             rom_bank: 0x01,
             ram_bank: 0x00,
             ram_enable: false,
 
-            max_rom: (rom_size / 0x4000) as u8,
+            max_rom: rom_size / 0x4000,
+            rtc,
 
             mode: Mode::Ram,
             latch: Latch::Step0,
@@ -50,12 +66,38 @@ impl MBC3 {
             rtc_mode: RtcMode::Seconds,
 
             current: Duration::zero(),
-            instant: Instant::now(),
+            mark: Duration::zero(),
+            clock: RtcClock::new(),
 
             carry: false,
             halt: true,
         }
     }
+
+    /// time since `current` was set
+    fn elapsed(&self) -> Duration {
+        self.clock.now() - self.mark
+    }
+
+    /// seconds, minutes, hours, days low and days high registers
+    fn rtc_registers(&self) -> [u8; 5] {
+        let current = if self.halt {
+            self.current
+        } else {
+            self.current + self.elapsed()
+        };
+        let days = current.whole_days();
+
+        [
+            (current.whole_seconds() - current.whole_minutes() * 60) as u8,
+            (current.whole_minutes() - current.whole_hours() * 60) as u8,
+            (current.whole_hours() - days * 24) as u8,
+            days as u8,
+            ((days >> 8) & 0x01) as u8
+                | (self.halt as u8) << 6
+                | ((self.carry || days > 511) as u8) << 7,
+        ]
+    }
 }
 
 impl MemoryBankController for MBC3 {
@@ -78,7 +120,7 @@ impl MemoryBankController for MBC3 {
                             let current = if self.halt || self.latch.latch() {
                                 self.current
                             } else {
-                                self.current + self.instant.elapsed()
+                                self.current + self.elapsed()
                             };
 
                             match self.rtc_mode {
@@ -121,7 +163,9 @@ impl MemoryBankController for MBC3 {
             // enable ram
             0x0000..=0x1fff => self.ram_enable = data & 0x0f == 0x0a,
             // rom banks
-            0x2000..=0x3fff => self.rom_bank = map_rom_bank((data & 0x7f) % self.max_rom),
+            0x2000..=0x3fff => {
+                self.rom_bank = map_rom_bank(((data & 0x7f) as usize % self.max_rom) as u8)
+            }
             // rtc or ram
             0x4000..=0x5fff => match data {
                 0x00..=0x03 => {
@@ -152,14 +196,16 @@ impl MemoryBankController for MBC3 {
                         Mode::Ram => {
                             let addr = addr - 0xa000 + self.ram_bank as u16 * 0x2000;
                             self.ram[addr as usize] = data;
+                            self.writes += 1;
                         }
                         Mode::Rtc => {
                             let current = if self.halt || self.latch.latch() {
                                 self.current
                             } else {
-                                self.current + self.instant.elapsed()
+                                self.current + self.elapsed()
                             };
-                            self.instant = Instant::now();
+                            self.mark = self.clock.now();
+                            self.writes += 1;
 
                             match self.rtc_mode {
                                 RtcMode::Seconds => {
@@ -230,9 +276,148 @@ impl MemoryBankController for MBC3 {
         }
     }
 
+    fn save_rtc(&self) -> Option<Vec<u8>> {
+        if !self.rtc {
+            return None;
+        }
+
+        let registers = self.rtc_registers();
+        let mut data = Vec::with_capacity(RTC_FOOTER_SIZE);
+        // latched registers are the same, they aren't kept apart
+        for _ in 0..2 {
+            for &reg in registers.iter() {
+                data.extend_from_slice(&(reg as u32).to_le_bytes());
+            }
+        }
+        data.extend_from_slice(&unix_time().to_le_bytes());
+        Some(data)
+    }
+
+    fn load_rtc(&mut self, data: &[u8]) {
+        let timestamp = match data.len() {
+            RTC_FOOTER_SIZE => u64::from_le_bytes(data[40..48].try_into().unwrap()),
+            RTC_FOOTER_SIZE_32 => u32::from_le_bytes(data[40..44].try_into().unwrap()) as u64,
+            _ => return,
+        };
+        if !self.rtc {
+            return;
+        }
+
+        let reg = |i: usize| data[i * 4] as i64;
+        let days = reg(3) | (reg(4) & 0x01) << 8;
+        self.current = Duration::seconds(reg(0))
+            + Duration::minutes(reg(1))
+            + Duration::hours(reg(2))
+            + Duration::days(days);
+        self.halt = reg(4) & 0x40 != 0;
+        self.carry = reg(4) & 0x80 != 0;
+
+        // the clock kept running while the emulator was closed
+        if !self.halt && self.clock.is_host() {
+            self.current += Duration::seconds(unix_time().saturating_sub(timestamp) as i64);
+        }
+        self.mark = self.clock.now();
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        self.clock.tick(cycles);
+    }
+
+    fn set_clock_source(&mut self, source: ClockSource) {
+        // the registers keep their time, then count on from the new source
+        if !self.halt {
+            self.current += self.elapsed();
+        }
+        self.clock.set_source(source);
+        self.mark = Duration::zero();
+    }
+
+    fn writes(&self) -> u64 {
+        self.writes
+    }
+
     fn mbc_type(&self) -> &'static str {
         "MBC3"
     }
+
+    fn rom_bank(&self) -> usize {
+        self.rom_bank as usize
+    }
+}
+
+impl Savestate for MBC3 {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_bytes(&self.ram);
+        w.write_u8(self.rom_bank);
+        w.write_u8(self.ram_bank);
+        w.write_bool(self.ram_enable);
+
+        w.write_u8(match self.mode {
+            Mode::Ram => 0,
+            Mode::Rtc => 1,
+        });
+        w.write_u8(match self.latch {
+            Latch::Step0 => 0,
+            Latch::Step1 => 1,
+            Latch::Latch0 => 2,
+            Latch::Latch1 => 3,
+        });
+        w.write_u8(match self.rtc_mode {
+            RtcMode::Seconds => 0,
+            RtcMode::Minutes => 1,
+            RtcMode::Hours => 2,
+            RtcMode::DaysLow => 3,
+            RtcMode::DaysHigh => 4,
+        });
+
+        w.write_i64(self.current.whole_nanoseconds() as i64);
+        w.write_i64(self.elapsed().whole_nanoseconds() as i64);
+        w.write_u64(self.clock.cycles());
+
+        w.write_bool(self.carry);
+        w.write_bool(self.halt);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> std::io::Result<()> {
+        r.read_bytes(&mut self.ram)?;
+        self.rom_bank = ((r.read_u8()? & 0x7f) as usize % self.max_rom) as u8;
+        self.ram_bank = r.read_u8()? & 0x03;
+        if (self.ram_bank as usize + 1) * 0x2000 > self.ram.len() {
+            self.ram_bank = 0;
+        }
+        self.ram_enable = r.read_bool()?;
+
+        self.mode = match r.read_u8()? {
+            0 => Mode::Ram,
+            1 => Mode::Rtc,
+            _ => return Err(invalid_state("bad mbc3 mode")),
+        };
+        self.latch = match r.read_u8()? {
+            0 => Latch::Step0,
+            1 => Latch::Step1,
+            2 => Latch::Latch0,
+            3 => Latch::Latch1,
+            _ => return Err(invalid_state("bad mbc3 latch")),
+        };
+        self.rtc_mode = match r.read_u8()? {
+            0 => RtcMode::Seconds,
+            1 => RtcMode::Minutes,
+            2 => RtcMode::Hours,
+            3 => RtcMode::DaysLow,
+            4 => RtcMode::DaysHigh,
+            _ => return Err(invalid_state("bad mbc3 rtc register")),
+        };
+
+        self.current = Duration::nanoseconds(r.read_i64()?);
+        let elapsed = Duration::nanoseconds(r.read_i64()?);
+        self.clock.set_cycles(r.read_u64()?);
+        self.mark = self.clock.now() - elapsed;
+
+        self.carry = r.read_bool()?;
+        self.halt = r.read_bool()?;
+
+        Ok(())
+    }
 }
 
 #[derive(Eq, PartialEq)]
@@ -274,3 +459,36 @@ impl Latch {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn large_rom_banks() {
+        // 4 MiB, more banks than fit a byte
+        let mut mbc = MBC3::new(0x40_0000, 0, false);
+        mbc.write(0x2000, 0x05);
+        assert_eq!(mbc.rom_bank(), 5);
+        mbc.write(0x2000, 0x00);
+        assert_eq!(mbc.rom_bank(), 1);
+    }
+
+    #[test]
+    fn corrupt_state_banks_stay_mapped() {
+        let mut mbc = MBC3::new(0x10000, 0x2000, false);
+        let mut w = StateWriter::new();
+        mbc.save_state(&mut w);
+        let mut state = w.into_inner();
+        // rom and ram bank right after the magic, version and ram
+        state[6 + 0x2000] = 0xff;
+        state[6 + 0x2001] = 0x03;
+
+        mbc.load_state(&mut StateReader::new(&state).unwrap())
+            .unwrap();
+        let rom = vec![0u8; 0x10000];
+        mbc.write(0x0000, 0x0a);
+        mbc.read(&rom, 0x7fff);
+        mbc.read(&rom, 0xbfff);
+    }
+}
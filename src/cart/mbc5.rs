@@ -1,7 +1,9 @@
 use super::MemoryBankController;
+use crate::state::{Savestate, StateReader, StateWriter};
 
 pub struct MBC5 {
     ram: Vec<u8>,
+    writes: u64,
     rom_bank: u16,
     ram_bank: u8,
     ram_enable: bool,
@@ -14,6 +16,7 @@ impl MBC5 {
     pub fn new(rom_size: usize, ram_size: usize) -> Self {
         MBC5 {
             ram: vec![0u8; ram_size],
+            writes: 0,
             rom_bank: 0x01,
             ram_bank: 0x00,
             ram_enable: false,
@@ -71,6 +74,7 @@ impl MemoryBankController for MBC5 {
                 if self.ram_enable {
                     let addr = addr - 0xa000 + self.ram_bank as u16 * 0x2000;
                     self.ram[addr as usize] = data;
+                    self.writes += 1;
                 }
             }
 
@@ -94,7 +98,32 @@ impl MemoryBankController for MBC5 {
         }
     }
 
+    fn writes(&self) -> u64 {
+        self.writes
+    }
+
     fn mbc_type(&self) -> &'static str {
         "MBC5"
     }
+
+    fn rom_bank(&self) -> usize {
+        self.rom_bank as usize
+    }
+}
+
+impl Savestate for MBC5 {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_bytes(&self.ram);
+        w.write_u16(self.rom_bank);
+        w.write_u8(self.ram_bank);
+        w.write_bool(self.ram_enable);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> std::io::Result<()> {
+        r.read_bytes(&mut self.ram)?;
+        self.rom_bank = (r.read_u16()? & 0x1ff) % self.max_rom;
+        self.ram_bank = (r.read_u8()? & 0x0f) % self.max_ram.max(1);
+        self.ram_enable = r.read_bool()? && self.max_ram > 0;
+        Ok(())
+    }
 }
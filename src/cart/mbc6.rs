@@ -0,0 +1,274 @@
+use super::MemoryBankController;
+use crate::state::{invalid_state, Savestate, StateReader, StateWriter};
+
+/// 8 Mbit Macronix flash
+const FLASH_SIZE: usize = 0x10_0000;
+/// flash erases 128 KiB sectors
+const FLASH_SECTOR: usize = 0x2_0000;
+
+/// source of a rom window, register value 0x08 selects flash
+#[derive(Copy, Clone, Eq, PartialEq)]
+enum Source {
+    Rom,
+    Flash,
+}
+
+/// flash command state, commands start with writing 0xaa then 0x55
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum FlashState {
+    Read,
+    Unlock1,
+    Unlock2,
+    /// The next write programs a byte.
+    Program,
+    /// An erase command, unlocked again before saying what to erase.
+    Erase,
+    EraseUnlock1,
+    EraseUnlock2,
+    /// Reads return the chip id.
+    Id,
+}
+
+/// MBC6, two 8 KiB rom windows that can each map rom or flash, and two
+/// 4 KiB ram windows.
+pub struct MBC6 {
+    /// ram followed by flash, saved together
+    memory: Vec<u8>,
+    writes: u64,
+    ram_size: usize,
+    ram_enable: bool,
+    ram_bank: [u8; 2],
+
+    rom_bank: [u8; 2],
+    source: [Source; 2],
+
+    flash_enable: bool,
+    flash_write_enable: bool,
+    flash_state: FlashState,
+
+    max_rom: usize,
+}
+
+impl MBC6 {
+    pub fn new(rom_size: usize, ram_size: usize) -> Self {
+        let mut memory = vec![0u8; ram_size + FLASH_SIZE];
+        // erased flash
+        memory[ram_size..].iter_mut().for_each(|b| *b = 0xff);
+
+        MBC6 {
+            memory,
+            writes: 0,
+            ram_size,
+            ram_enable: false,
+            ram_bank: [0; 2],
+
+            rom_bank: [0; 2],
+            source: [Source::Rom; 2],
+
+            flash_enable: false,
+            flash_write_enable: false,
+            flash_state: FlashState::Read,
+
+            max_rom: (rom_size / 0x2000).max(1),
+        }
+    }
+
+    fn flash_addr(&self, window: usize, addr: u16) -> usize {
+        let bank = self.rom_bank[window] as usize % (FLASH_SIZE / 0x2000);
+        bank * 0x2000 + (addr & 0x1fff) as usize
+    }
+
+    fn flash(&mut self) -> &mut [u8] {
+        &mut self.memory[self.ram_size..]
+    }
+
+    fn write_flash(&mut self, window: usize, addr: u16, data: u8) {
+        let offset = self.flash_addr(window, addr);
+
+        self.flash_state = match (self.flash_state, data) {
+            (FlashState::Program, _) => {
+                if self.flash_write_enable {
+                    // programming only clears bits
+                    self.flash()[offset] &= data;
+                    self.writes += 1;
+                }
+                FlashState::Read
+            }
+            // reset
+            (_, 0xf0) => FlashState::Read,
+            (FlashState::Read, 0xaa) | (FlashState::Id, 0xaa) => FlashState::Unlock1,
+            (FlashState::Unlock1, 0x55) => FlashState::Unlock2,
+            (FlashState::Unlock2, 0xa0) => FlashState::Program,
+            (FlashState::Unlock2, 0x80) => FlashState::Erase,
+            (FlashState::Unlock2, 0x90) => FlashState::Id,
+            (FlashState::Erase, 0xaa) => FlashState::EraseUnlock1,
+            (FlashState::EraseUnlock1, 0x55) => FlashState::EraseUnlock2,
+            (FlashState::EraseUnlock2, command) => {
+                if self.flash_write_enable {
+                    match command {
+                        // sector erase
+                        0x30 => {
+                            let sector = offset / FLASH_SECTOR * FLASH_SECTOR;
+                            self.flash()[sector..sector + FLASH_SECTOR]
+                                .iter_mut()
+                                .for_each(|b| *b = 0xff);
+                        }
+                        // chip erase
+                        0x10 => self.flash().iter_mut().for_each(|b| *b = 0xff),
+                        _ => {}
+                    }
+                    self.writes += 1;
+                }
+                FlashState::Read
+            }
+            _ => FlashState::Read,
+        };
+    }
+}
+
+impl MemoryBankController for MBC6 {
+    fn read(&self, rom: &[u8], addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x3fff => rom[addr as usize],
+            0x4000..=0x7fff => {
+                let window = (addr as usize - 0x4000) / 0x2000;
+                match self.source[window] {
+                    Source::Rom => {
+                        let bank = self.rom_bank[window] as usize % self.max_rom;
+                        rom[bank * 0x2000 + (addr & 0x1fff) as usize]
+                    }
+                    Source::Flash if self.flash_state == FlashState::Id => match addr & 0xff {
+                        // Macronix MX29F008
+                        0x00 => 0xc2,
+                        _ => 0x81,
+                    },
+                    Source::Flash if self.flash_enable => {
+                        self.memory[self.ram_size + self.flash_addr(window, addr)]
+                    }
+                    Source::Flash => 0xff,
+                }
+            }
+            0xa000..=0xbfff if self.ram_enable && self.ram_size > 0 => {
+                let window = (addr as usize - 0xa000) / 0x1000;
+                let bank = self.ram_bank[window] as usize;
+                self.memory[(bank * 0x1000 + (addr & 0x0fff) as usize) % self.ram_size]
+            }
+            0xa000..=0xbfff => 0xff,
+
+            _ => unreachable!(),
+        }
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x0000..=0x03ff => self.ram_enable = data & 0x0f == 0x0a,
+            0x0400..=0x07ff => self.ram_bank[0] = data & 0x07,
+            0x0800..=0x0bff => self.ram_bank[1] = data & 0x07,
+            0x0c00..=0x0fff => self.flash_enable = data & 0x01 != 0,
+            0x1000 => self.flash_write_enable = data & 0x01 != 0,
+            0x1001..=0x1fff => {}
+            0x2000..=0x27ff => self.rom_bank[0] = data & 0x7f,
+            0x2800..=0x2fff => self.source[0] = source(data),
+            0x3000..=0x37ff => self.rom_bank[1] = data & 0x7f,
+            0x3800..=0x3fff => self.source[1] = source(data),
+            0x4000..=0x7fff => {
+                let window = (addr as usize - 0x4000) / 0x2000;
+                if self.source[window] == Source::Flash && self.flash_enable {
+                    self.write_flash(window, addr, data);
+                }
+            }
+            0xa000..=0xbfff => {
+                if self.ram_enable && self.ram_size > 0 {
+                    let window = (addr as usize - 0xa000) / 0x1000;
+                    let bank = self.ram_bank[window] as usize;
+                    let addr = (bank * 0x1000 + (addr & 0x0fff) as usize) % self.ram_size;
+                    self.memory[addr] = data;
+                    self.writes += 1;
+                }
+            }
+
+            _ => unreachable!(),
+        }
+    }
+
+    fn get_ram(&self) -> Option<&[u8]> {
+        Some(self.memory.as_slice())
+    }
+
+    fn get_ram_mut(&mut self) -> Option<&mut [u8]> {
+        Some(self.memory.as_mut_slice())
+    }
+
+    fn writes(&self) -> u64 {
+        self.writes
+    }
+
+    fn mbc_type(&self) -> &'static str {
+        "MBC6"
+    }
+
+    fn rom_bank(&self) -> usize {
+        // in 16 KiB banks, as the debugger shows them
+        self.rom_bank[0] as usize / 2
+    }
+}
+
+fn source(data: u8) -> Source {
+    if data & 0x08 != 0 {
+        Source::Flash
+    } else {
+        Source::Rom
+    }
+}
+
+impl Savestate for MBC6 {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_bytes(&self.memory);
+        w.write_bool(self.ram_enable);
+        w.write_bytes(&self.ram_bank);
+        w.write_bytes(&self.rom_bank);
+        for &source in self.source.iter() {
+            w.write_bool(source == Source::Flash);
+        }
+        w.write_bool(self.flash_enable);
+        w.write_bool(self.flash_write_enable);
+        w.write_u8(match self.flash_state {
+            FlashState::Read => 0,
+            FlashState::Unlock1 => 1,
+            FlashState::Unlock2 => 2,
+            FlashState::Program => 3,
+            FlashState::Erase => 4,
+            FlashState::EraseUnlock1 => 5,
+            FlashState::EraseUnlock2 => 6,
+            FlashState::Id => 7,
+        });
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> std::io::Result<()> {
+        r.read_bytes(&mut self.memory)?;
+        self.ram_enable = r.read_bool()?;
+        r.read_bytes(&mut self.ram_bank)?;
+        r.read_bytes(&mut self.rom_bank)?;
+        for source in self.source.iter_mut() {
+            *source = if r.read_bool()? {
+                Source::Flash
+            } else {
+                Source::Rom
+            };
+        }
+        self.flash_enable = r.read_bool()?;
+        self.flash_write_enable = r.read_bool()?;
+        self.flash_state = match r.read_u8()? {
+            0 => FlashState::Read,
+            1 => FlashState::Unlock1,
+            2 => FlashState::Unlock2,
+            3 => FlashState::Program,
+            4 => FlashState::Erase,
+            5 => FlashState::EraseUnlock1,
+            6 => FlashState::EraseUnlock2,
+            7 => FlashState::Id,
+            _ => return Err(invalid_state("bad flash state")),
+        };
+        Ok(())
+    }
+}
@@ -0,0 +1,482 @@
+use super::MemoryBankController;
+use crate::joypad::TiltState;
+use crate::state::{invalid_state, Savestate, StateReader, StateWriter};
+
+/// accelerometer reading when level
+const ACCEL_CENTER: f32 = 0x81d0 as f32;
+/// accelerometer change per g
+const ACCEL_PER_G: f32 = 0x70 as f32;
+/// 93LC56 in 16 bit mode, 128 words
+const EEPROM_SIZE: usize = 0x100;
+
+/// MBC7, with an accelerometer and a serial eeprom instead of ram.
+pub struct MBC7 {
+    rom_bank: u8,
+    ram_enable1: bool,
+    ram_enable2: bool,
+
+    max_rom: usize,
+
+    tilt: TiltState,
+    // latched accelerometer readings, erased to 0x8000
+    accel_x: u16,
+    accel_y: u16,
+    latch_ready: bool,
+
+    eeprom: Eeprom,
+}
+
+impl MBC7 {
+    pub fn new(rom_size: usize) -> Self {
+        MBC7 {
+            rom_bank: 0x01,
+            ram_enable1: false,
+            ram_enable2: false,
+
+            max_rom: (rom_size / 0x4000).max(1),
+
+            tilt: TiltState::default(),
+            accel_x: 0x8000,
+            accel_y: 0x8000,
+            latch_ready: false,
+
+            eeprom: Eeprom::new(),
+        }
+    }
+
+    fn ram_enabled(&self) -> bool {
+        self.ram_enable1 && self.ram_enable2
+    }
+}
+
+impl MemoryBankController for MBC7 {
+    fn read(&self, rom: &[u8], addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x3fff => rom[addr as usize],
+            0x4000..=0x7fff => {
+                let addr = addr as usize - 0x4000;
+                rom[addr + 0x4000 * self.rom_bank as usize]
+            }
+            0xa000..=0xafff if self.ram_enabled() => match (addr >> 4) & 0x0f {
+                0x2 => self.accel_x as u8,
+                0x3 => (self.accel_x >> 8) as u8,
+                0x4 => self.accel_y as u8,
+                0x5 => (self.accel_y >> 8) as u8,
+                0x6 => 0x00,
+                0x8 => self.eeprom.read(),
+                _ => 0xff,
+            },
+            0xa000..=0xbfff => 0xff,
+
+            _ => unreachable!(),
+        }
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        match addr {
+            // both ram enables are needed for the registers
+            0x0000..=0x1fff => self.ram_enable1 = data == 0x0a,
+            0x2000..=0x3fff => self.rom_bank = ((data & 0x7f) as usize % self.max_rom) as u8,
+            0x4000..=0x5fff => self.ram_enable2 = data == 0x40,
+            0x6000..=0x7fff => {}
+            0xa000..=0xafff if self.ram_enabled() => match (addr >> 4) & 0x0f {
+                // erase the latched values
+                0x0 if data == 0x55 => {
+                    self.accel_x = 0x8000;
+                    self.accel_y = 0x8000;
+                    self.latch_ready = true;
+                }
+                // latch the accelerometer, only after an erase
+                0x1 if data == 0xaa && self.latch_ready => {
+                    let reading = |g: f32| (ACCEL_CENTER + g * ACCEL_PER_G) as u16;
+                    self.accel_x = reading(self.tilt.x);
+                    self.accel_y = reading(self.tilt.y);
+                    self.latch_ready = false;
+                }
+                0x8 => self.eeprom.write(data),
+                _ => {}
+            },
+            0xa000..=0xbfff => {}
+
+            _ => unreachable!(),
+        }
+    }
+
+    fn get_ram(&self) -> Option<&[u8]> {
+        Some(&self.eeprom.data)
+    }
+
+    fn get_ram_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.eeprom.data)
+    }
+
+    fn writes(&self) -> u64 {
+        self.eeprom.writes
+    }
+
+    fn mbc_type(&self) -> &'static str {
+        "MBC7"
+    }
+
+    fn rom_bank(&self) -> usize {
+        self.rom_bank as usize
+    }
+
+    fn set_tilt(&mut self, tilt: TiltState) {
+        self.tilt = tilt;
+    }
+}
+
+impl Savestate for MBC7 {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u8(self.rom_bank);
+        w.write_bool(self.ram_enable1);
+        w.write_bool(self.ram_enable2);
+        w.write_u16(self.accel_x);
+        w.write_u16(self.accel_y);
+        w.write_bool(self.latch_ready);
+        self.eeprom.save_state(w);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> std::io::Result<()> {
+        self.rom_bank = (r.read_u8()? as usize % self.max_rom) as u8;
+        self.ram_enable1 = r.read_bool()?;
+        self.ram_enable2 = r.read_bool()?;
+        self.accel_x = r.read_u16()?;
+        self.accel_y = r.read_u16()?;
+        self.latch_ready = r.read_bool()?;
+        self.eeprom.load_state(r)
+    }
+}
+
+/// chip select, clock, data in and data out pins of the eeprom register
+const EEPROM_CS: u8 = 0b1000_0000;
+const EEPROM_CLK: u8 = 0b0100_0000;
+const EEPROM_DI: u8 = 0b0000_0010;
+const EEPROM_DO: u8 = 0b0000_0001;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum EepromState {
+    /// Waiting for a start bit.
+    Idle,
+    /// Shifting in the 2 bit opcode and 8 bit address.
+    Command { bits: u16, count: u8 },
+    /// Shifting out a word.
+    Read { addr: u8, count: u8 },
+    /// Shifting in a word, to one address or to all of them.
+    Write {
+        addr: Option<u8>,
+        bits: u16,
+        count: u8,
+    },
+}
+
+/// 93LC56 serial eeprom, bit banged through 0xa080.
+struct Eeprom {
+    data: [u8; EEPROM_SIZE],
+    writes: u64,
+    state: EepromState,
+    write_enable: bool,
+
+    cs: bool,
+    clk: bool,
+    di: bool,
+    out: bool,
+}
+
+impl Eeprom {
+    fn new() -> Self {
+        Eeprom {
+            data: [0xff; EEPROM_SIZE],
+            writes: 0,
+            state: EepromState::Idle,
+            write_enable: false,
+
+            cs: false,
+            clk: false,
+            di: false,
+            out: true,
+        }
+    }
+
+    fn read(&self) -> u8 {
+        let pin = |on: bool, mask: u8| if on { mask } else { 0 };
+        0b0011_1100
+            | pin(self.cs, EEPROM_CS)
+            | pin(self.clk, EEPROM_CLK)
+            | pin(self.di, EEPROM_DI)
+            | pin(self.out, EEPROM_DO)
+    }
+
+    fn write(&mut self, data: u8) {
+        let cs = data & EEPROM_CS != 0;
+        let clk = data & EEPROM_CLK != 0;
+        self.di = data & EEPROM_DI != 0;
+
+        if !cs {
+            // deselecting aborts any command
+            self.state = EepromState::Idle;
+        } else if !self.clk && clk {
+            self.clock(self.di);
+        }
+
+        self.cs = cs;
+        self.clk = clk;
+    }
+
+    // shifts a bit in on the rising clock edge
+    fn clock(&mut self, bit: bool) {
+        self.state = match self.state {
+            EepromState::Idle if bit => EepromState::Command { bits: 0, count: 0 },
+            EepromState::Idle => EepromState::Idle,
+            EepromState::Command { bits, count } => {
+                let bits = bits << 1 | bit as u16;
+                if count + 1 < 10 {
+                    EepromState::Command {
+                        bits,
+                        count: count + 1,
+                    }
+                } else {
+                    self.command(bits)
+                }
+            }
+            EepromState::Read { addr, count } => {
+                let word = self.word(addr);
+                self.out = word & (0x8000 >> count) != 0;
+                if count + 1 < 16 {
+                    EepromState::Read {
+                        addr,
+                        count: count + 1,
+                    }
+                } else {
+                    // sequential reads carry on with the next word
+                    EepromState::Read {
+                        addr: (addr + 1) & 0x7f,
+                        count: 0,
+                    }
+                }
+            }
+            EepromState::Write { addr, bits, count } => {
+                let bits = bits << 1 | bit as u16;
+                if count + 1 < 16 {
+                    EepromState::Write {
+                        addr,
+                        bits,
+                        count: count + 1,
+                    }
+                } else {
+                    if self.write_enable {
+                        match addr {
+                            Some(addr) => self.set_word(addr, bits),
+                            None => (0..0x80).for_each(|addr| self.set_word(addr, bits)),
+                        }
+                    }
+                    // writes finish at once, ready
+                    self.out = true;
+                    EepromState::Idle
+                }
+            }
+        };
+    }
+
+    fn command(&mut self, bits: u16) -> EepromState {
+        let addr = (bits & 0x7f) as u8;
+        match (bits >> 8, (bits >> 6) & 0b11) {
+            // READ, a dummy 0 comes before the data
+            (0b10, _) => {
+                self.out = false;
+                EepromState::Read { addr, count: 0 }
+            }
+            // WRITE
+            (0b01, _) => EepromState::Write {
+                addr: Some(addr),
+                bits: 0,
+                count: 0,
+            },
+            // ERASE
+            (0b11, _) => {
+                if self.write_enable {
+                    self.set_word(addr, 0xffff);
+                }
+                self.out = true;
+                EepromState::Idle
+            }
+            // EWDS
+            (0b00, 0b00) => {
+                self.write_enable = false;
+                EepromState::Idle
+            }
+            // WRAL
+            (0b00, 0b01) => EepromState::Write {
+                addr: None,
+                bits: 0,
+                count: 0,
+            },
+            // ERAL
+            (0b00, 0b10) => {
+                if self.write_enable {
+                    self.data = [0xff; EEPROM_SIZE];
+                    self.writes += 1;
+                }
+                self.out = true;
+                EepromState::Idle
+            }
+            // EWEN
+            _ => {
+                self.write_enable = true;
+                EepromState::Idle
+            }
+        }
+    }
+
+    fn word(&self, addr: u8) -> u16 {
+        let i = addr as usize * 2;
+        self.data[i] as u16 | (self.data[i + 1] as u16) << 8
+    }
+
+    fn set_word(&mut self, addr: u8, word: u16) {
+        let i = addr as usize * 2;
+        self.data[i] = word as u8;
+        self.data[i + 1] = (word >> 8) as u8;
+        self.writes += 1;
+    }
+}
+
+impl Savestate for Eeprom {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_bytes(&self.data);
+        match self.state {
+            EepromState::Idle => w.write_u8(0),
+            EepromState::Command { bits, count } => {
+                w.write_u8(1);
+                w.write_u16(bits);
+                w.write_u8(count);
+            }
+            EepromState::Read { addr, count } => {
+                w.write_u8(2);
+                w.write_u8(addr);
+                w.write_u8(count);
+            }
+            EepromState::Write { addr, bits, count } => {
+                w.write_u8(3);
+                w.write_u8(addr.unwrap_or(0xff));
+                w.write_u16(bits);
+                w.write_u8(count);
+            }
+        }
+        w.write_bool(self.write_enable);
+        w.write_bool(self.cs);
+        w.write_bool(self.clk);
+        w.write_bool(self.di);
+        w.write_bool(self.out);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> std::io::Result<()> {
+        r.read_bytes(&mut self.data)?;
+        self.state = match r.read_u8()? {
+            0 => EepromState::Idle,
+            1 => EepromState::Command {
+                bits: r.read_u16()?,
+                count: r.read_u8()? % 10,
+            },
+            2 => EepromState::Read {
+                addr: r.read_u8()? & 0x7f,
+                count: r.read_u8()? % 16,
+            },
+            3 => EepromState::Write {
+                addr: match r.read_u8()? {
+                    0xff => None,
+                    addr => Some(addr & 0x7f),
+                },
+                bits: r.read_u16()?,
+                count: r.read_u8()? % 16,
+            },
+            _ => return Err(invalid_state("bad eeprom state")),
+        };
+        self.write_enable = r.read_bool()?;
+        self.cs = r.read_bool()?;
+        self.clk = r.read_bool()?;
+        self.di = r.read_bool()?;
+        self.out = r.read_bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clock_in(eeprom: &mut Eeprom, bits: u32, count: u32) {
+        for i in (0..count).rev() {
+            let di = if bits >> i & 1 != 0 { EEPROM_DI } else { 0 };
+            eeprom.write(EEPROM_CS | di);
+            eeprom.write(EEPROM_CS | EEPROM_CLK | di);
+        }
+    }
+
+    fn clock_out(eeprom: &mut Eeprom, count: u32) -> u32 {
+        (0..count).fold(0, |bits, _| {
+            eeprom.write(EEPROM_CS);
+            eeprom.write(EEPROM_CS | EEPROM_CLK);
+            bits << 1 | (eeprom.read() & EEPROM_DO) as u32
+        })
+    }
+
+    // selects the chip again, then sends the start bit, opcode and address
+    fn command(eeprom: &mut Eeprom, op: u32, addr: u32) {
+        eeprom.write(0);
+        clock_in(eeprom, 0b1 << 10 | op << 8 | addr, 11);
+    }
+
+    #[test]
+    fn eeprom_write_needs_ewen() {
+        let mut eeprom = Eeprom::new();
+        command(&mut eeprom, 0b01, 0x05);
+        clock_in(&mut eeprom, 0x1234, 16);
+        assert_eq!(eeprom.word(0x05), 0xffff);
+        assert_eq!(eeprom.writes, 0);
+
+        // EWEN
+        command(&mut eeprom, 0b00, 0xc0);
+        command(&mut eeprom, 0b01, 0x05);
+        clock_in(&mut eeprom, 0x1234, 16);
+        assert_eq!(eeprom.word(0x05), 0x1234);
+        assert_eq!(eeprom.data[0x0a..0x0c], [0x34, 0x12]);
+        assert_eq!(eeprom.writes, 1);
+
+        // EWDS
+        command(&mut eeprom, 0b00, 0x00);
+        command(&mut eeprom, 0b01, 0x05);
+        clock_in(&mut eeprom, 0x5678, 16);
+        assert_eq!(eeprom.word(0x05), 0x1234);
+    }
+
+    #[test]
+    fn eeprom_read() {
+        let mut eeprom = Eeprom::new();
+        eeprom.set_word(0x10, 0xa55a);
+        eeprom.set_word(0x11, 0x0ff0);
+
+        command(&mut eeprom, 0b10, 0x10);
+        // dummy 0 before the data
+        assert_eq!(eeprom.read() & EEPROM_DO, 0);
+        assert_eq!(clock_out(&mut eeprom, 16), 0xa55a);
+        assert_eq!(clock_out(&mut eeprom, 16), 0x0ff0);
+
+        // deselecting ends the read
+        eeprom.write(0);
+        clock_in(&mut eeprom, 0, 4);
+        assert_eq!(eeprom.state, EepromState::Idle);
+    }
+
+    #[test]
+    fn large_rom_banks() {
+        let mut mbc = MBC7::new(0x40_0000);
+        mbc.write(0x2000, 0x7f);
+        assert_eq!(mbc.rom_bank(), 0x7f);
+
+        let mut mbc = MBC7::new(0x8000);
+        mbc.write(0x2000, 0x03);
+        assert_eq!(mbc.rom_bank(), 0x01);
+    }
+}
@@ -0,0 +1,245 @@
+use super::header::bootable;
+use super::MemoryBankController;
+use crate::state::{Savestate, StateReader, StateWriter};
+
+/// MMM01, the multicart mapper.
+///
+/// It starts unmapped, showing the menu in the last 32 KiB of the rom. The
+/// menu sets the outer banks of a game and which bits it may still change,
+/// then maps it in, after which it behaves like an MBC1 until reset.
+pub struct MMM01 {
+    ram: Vec<u8>,
+    writes: u64,
+    ram_enable: bool,
+    mapped: bool,
+
+    /// 9 bit rom bank, the upper 4 bits are only writable while unmapped
+    rom_bank: u16,
+    /// bits 1-4 of the rom bank the game can't change
+    rom_mask: u8,
+    /// 4 bit ram bank, the upper 2 bits are only writable while unmapped
+    ram_bank: u8,
+    /// bits 0-1 of the ram bank the game can't change
+    ram_mask: u8,
+    mbc1_mode: bool,
+    /// the game can't switch to mbc1 mode
+    mode_locked: bool,
+
+    rom_banks: usize,
+}
+
+impl MMM01 {
+    pub fn new(rom_size: usize, ram_size: usize) -> Self {
+        MMM01 {
+            ram: vec![0u8; ram_size],
+            writes: 0,
+            ram_enable: false,
+            mapped: false,
+
+            rom_bank: 0x00,
+            rom_mask: 0x00,
+            ram_bank: 0x00,
+            ram_mask: 0x00,
+            mbc1_mode: false,
+            mode_locked: false,
+
+            rom_banks: (rom_size / 0x4000).max(2),
+        }
+    }
+
+    /// Whether a rom is for the MMM01, which keeps its menu and header in
+    /// the last 32 KiB. Only roms that wouldn't boot from their first header
+    /// are checked.
+    pub fn is_mmm01(rom: &[u8]) -> bool {
+        if rom.len() < 0x8000 || bootable(rom) {
+            return false;
+        }
+        let menu = &rom[rom.len() - 0x8000..];
+        (0x0b..=0x0d).contains(&menu[0x147]) && bootable(menu)
+    }
+
+    fn low_bank(&self) -> usize {
+        if !self.mapped {
+            return self.rom_banks - 2;
+        }
+
+        // the bits the game controls read as 0
+        let fixed = (self.rom_mask as u16) << 1 | 0x01e0;
+        (self.rom_bank & fixed) as usize % self.rom_banks
+    }
+
+    fn high_bank(&self) -> usize {
+        if !self.mapped {
+            return self.rom_banks - 1;
+        }
+
+        let writable = 0x1f & !((self.rom_mask as u16) << 1);
+        let mut bank = self.rom_bank;
+        // like the mbc1, bank 0 of the game reads as bank 1
+        if bank & writable == 0 {
+            bank |= 0x01;
+        }
+        bank as usize % self.rom_banks
+    }
+
+    fn ram_addr(&self, addr: u16) -> usize {
+        let bank = if self.mbc1_mode || !self.mapped {
+            self.ram_bank
+        } else {
+            self.ram_bank & (0b1100 | self.ram_mask)
+        };
+        (bank as usize * 0x2000 + (addr - 0xa000) as usize) % self.ram.len()
+    }
+}
+
+impl MemoryBankController for MMM01 {
+    fn read(&self, rom: &[u8], addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x3fff => rom[addr as usize + 0x4000 * self.low_bank()],
+            0x4000..=0x7fff => {
+                let addr = addr as usize - 0x4000;
+                rom[addr + 0x4000 * self.high_bank()]
+            }
+            0xa000..=0xbfff => {
+                if self.ram_enable && !self.ram.is_empty() {
+                    self.ram[self.ram_addr(addr)]
+                } else {
+                    0xff
+                }
+            }
+
+            _ => unreachable!(),
+        }
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x0000..=0x1fff => {
+                self.ram_enable = data & 0x0f == 0x0a;
+                if !self.mapped {
+                    self.ram_mask = (data >> 4) & 0b11;
+                    self.mapped = data & 0x40 != 0;
+                }
+            }
+            0x2000..=0x3fff => {
+                let writable = if self.mapped {
+                    0x1f & !((self.rom_mask as u16) << 1)
+                } else {
+                    0x7f
+                };
+                self.rom_bank = (self.rom_bank & !writable) | (data as u16 & writable);
+            }
+            0x4000..=0x5fff => {
+                let writable = if self.mapped {
+                    !self.ram_mask & 0b11
+                } else {
+                    0b1111
+                };
+                self.ram_bank = (self.ram_bank & !writable) | (data & writable);
+                if !self.mapped {
+                    self.rom_bank = (self.rom_bank & 0x7f) | ((data as u16 >> 4) & 0b11) << 7;
+                    self.mode_locked = data & 0x40 != 0;
+                }
+            }
+            0x6000..=0x7fff => {
+                if !self.mapped || !self.mode_locked {
+                    self.mbc1_mode = data & 0x01 != 0;
+                }
+                if !self.mapped {
+                    self.rom_mask = (data >> 2) & 0x0f;
+                }
+            }
+            0xa000..=0xbfff => {
+                if self.ram_enable && !self.ram.is_empty() {
+                    let addr = self.ram_addr(addr);
+                    self.ram[addr] = data;
+                    self.writes += 1;
+                }
+            }
+
+            _ => unreachable!(),
+        }
+    }
+
+    fn get_ram(&self) -> Option<&[u8]> {
+        if !self.ram.is_empty() {
+            Some(self.ram.as_slice())
+        } else {
+            None
+        }
+    }
+
+    fn get_ram_mut(&mut self) -> Option<&mut [u8]> {
+        if !self.ram.is_empty() {
+            Some(self.ram.as_mut_slice())
+        } else {
+            None
+        }
+    }
+
+    fn writes(&self) -> u64 {
+        self.writes
+    }
+
+    fn mbc_type(&self) -> &'static str {
+        "MMM01"
+    }
+
+    fn rom_bank(&self) -> usize {
+        self.high_bank()
+    }
+}
+
+impl Savestate for MMM01 {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_bytes(&self.ram);
+        w.write_bool(self.ram_enable);
+        w.write_bool(self.mapped);
+        w.write_u16(self.rom_bank);
+        w.write_u8(self.rom_mask);
+        w.write_u8(self.ram_bank);
+        w.write_u8(self.ram_mask);
+        w.write_bool(self.mbc1_mode);
+        w.write_bool(self.mode_locked);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> std::io::Result<()> {
+        r.read_bytes(&mut self.ram)?;
+        self.ram_enable = r.read_bool()?;
+        self.mapped = r.read_bool()?;
+        self.rom_bank = r.read_u16()? & 0x1ff;
+        self.rom_mask = r.read_u8()? & 0x0f;
+        self.ram_bank = r.read_u8()? & 0x0f;
+        self.ram_mask = r.read_u8()? & 0b11;
+        self.mbc1_mode = r.read_bool()?;
+        self.mode_locked = r.read_bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cart::{test_rom, NINTENDO_LOGO};
+
+    #[test]
+    fn detection() {
+        let menu = test_rom(0x0b, 0x8000, 0x00);
+        let mut rom = vec![0u8; 0x20000];
+        rom[0x18000..].copy_from_slice(&menu);
+        assert!(MMM01::is_mmm01(&rom));
+
+        // the menu has to boot
+        rom[0x18000 + 0x14d] ^= 0xff;
+        assert!(!MMM01::is_mmm01(&rom));
+        rom[0x18000 + 0x14d] ^= 0xff;
+        rom[0x18000 + 0x104] = 0;
+        assert!(!MMM01::is_mmm01(&rom));
+        rom[0x18000 + 0x104] = NINTENDO_LOGO[0];
+
+        // a rom that boots on its own is never an mmm01
+        let game = test_rom(0x01, 0x20000, 0x00);
+        rom[..0x150].copy_from_slice(&game[..0x150]);
+        assert!(!MMM01::is_mmm01(&rom));
+    }
+}
@@ -1,28 +1,154 @@
-use std::fs::OpenOptions;
-use std::io::{Read, Write};
+use crate::joypad::TiltState;
+use crate::state::{invalid_state, Savestate, StateReader, StateWriter};
+use std::fmt;
+use std::path::{Path, PathBuf};
 
+mod camera;
+mod clock;
+mod header;
+mod huc1;
+mod huc3;
 mod mbc0;
 mod mbc1;
 mod mbc2;
 mod mbc3;
 mod mbc5;
+mod mbc6;
+mod mbc7;
+mod mmm01;
+mod save;
 
+pub use self::camera::{
+    ImageSource, PocketCamera, StillImage, TestPattern, CAMERA_HEIGHT, CAMERA_WIDTH,
+};
+pub use self::clock::ClockSource;
+pub use self::header::{CartridgeHeader, NINTENDO_LOGO};
+pub use self::huc1::HuC1;
+pub use self::huc3::HuC3;
 pub use self::mbc0::MBC0;
 pub use self::mbc1::MBC1;
 pub use self::mbc2::MBC2;
 pub use self::mbc3::MBC3;
 pub use self::mbc5::MBC5;
+pub use self::mbc6::MBC6;
+pub use self::mbc7::MBC7;
+pub use self::mmm01::MMM01;
+pub use self::save::SaveFormat;
+
+/// Why a rom could not be loaded.
+#[derive(Debug)]
+pub enum CartError {
+    Io(std::io::Error),
+    /// The file is shorter than the cartridge header.
+    TooSmall(usize),
+    /// A header field has a value no cartridge uses.
+    BadHeader(String),
+    UnsupportedMapper(u8),
+    /// The file size doesn't match the rom size in the header, the rom is
+    /// truncated or overdumped.
+    SizeMismatch {
+        header: usize,
+        file: usize,
+    },
+}
+
+impl fmt::Display for CartError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CartError::Io(e) => write!(f, "{}", e),
+            CartError::TooSmall(len) => write!(f, "rom too small: {} bytes", len),
+            CartError::BadHeader(msg) => write!(f, "bad cartridge header: {}", msg),
+            CartError::UnsupportedMapper(cart_type) => {
+                write!(f, "unsupported cartridge type: 0x{:02x}", cart_type)
+            }
+            CartError::SizeMismatch { header, file } => write!(
+                f,
+                "rom size 0x{:06x} doesn't match the header's 0x{:06x}",
+                file, header
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CartError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CartError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for CartError {
+    fn from(e: std::io::Error) -> Self {
+        CartError::Io(e)
+    }
+}
 
 pub struct Cartridge {
     rom: Vec<u8>,
     mbc: Box<dyn MemoryBankController>,
 
     entry_point: u16,
-    title: String,
-    cgb: bool,
+    header: CartridgeHeader,
+    save_path: PathBuf,
+    /// mapper writes when the battery memory last matched the save file,
+    /// `None` after it was replaced
+    saved_writes: Option<u64>,
 }
 
 impl Cartridge {
+    /// A cartridge for a rom already in memory, without loading its save.
+    pub(crate) fn from_rom(rom: Vec<u8>, save_path: PathBuf) -> Result<Cartridge, CartError> {
+        let entry = 0x100;
+
+        // the mmm01 menu and its header are at the end of the rom
+        let mmm01 = MMM01::is_mmm01(&rom);
+        let header = if mmm01 {
+            CartridgeHeader::parse(&rom[rom.len() - 0x8000..])?
+        } else {
+            CartridgeHeader::parse(&rom)?
+        };
+        let (rom_size, ram_size) = (header.rom_size, header.ram_size);
+        if rom_size != rom.len() {
+            return Err(CartError::SizeMismatch {
+                header: rom_size,
+                file: rom.len(),
+            });
+        }
+
+        let cart_type = header.cart_type;
+        let mbc: Box<dyn MemoryBankController> = match cart_type {
+            _ if mmm01 => Box::new(MMM01::new(rom_size, ram_size)),
+            0x00 => Box::new(MBC0::new()),
+            0x01..=0x03 => Box::new(MBC1::new(rom_size, ram_size, MBC1::is_multicart(&rom))),
+            0x05..=0x06 => Box::new(MBC2::new(rom_size)),
+            0x0f..=0x13 => Box::new(MBC3::new(rom_size, ram_size, cart_type <= 0x10)),
+            0x19..=0x1e => Box::new(MBC5::new(rom_size, ram_size)),
+            0x20 => Box::new(MBC6::new(rom_size, ram_size)),
+            0x22 => Box::new(MBC7::new(rom_size)),
+            0xfc => Box::new(PocketCamera::new(rom_size, ram_size)),
+            0xfe => Box::new(HuC3::new(rom_size, ram_size)),
+            0xff => Box::new(HuC1::new(rom_size, ram_size)),
+            _ => return Err(CartError::UnsupportedMapper(cart_type)),
+        };
+
+        println!("title   : {}", header.title);
+        println!("mbc type: 0x{:02x} - {}", cart_type, mbc.mbc_type());
+        println!("rom size: 0x{:06x}", rom_size);
+        println!("ram size: 0x{:06x}", ram_size);
+        println!("cgb flag: 0x{:02x}", header.cgb_flag);
+
+        Ok(Cartridge {
+            rom,
+            mbc,
+            entry_point: entry,
+            header,
+            save_path,
+            saved_writes: Some(0),
+        })
+    }
+
     pub fn read(&self, addr: u16) -> u8 {
         self.mbc.read(self.rom.as_ref(), addr)
     }
@@ -31,122 +157,233 @@ impl Cartridge {
         self.mbc.write(addr, data);
     }
 
+    /// Feeds the accelerometer of carts that have one.
+    pub fn set_tilt(&mut self, tilt: TiltState) {
+        self.mbc.set_tilt(tilt);
+    }
+
+    /// Advances the clock of carts with one, in 4 MHz cycles.
+    pub fn tick(&mut self, cycles: u32) {
+        self.mbc.tick(cycles);
+    }
+
+    /// Sets where the clock of carts with one gets the time from.
+    pub fn set_clock_source(&mut self, source: ClockSource) {
+        self.mbc.set_clock_source(source);
+    }
+
+    /// Feeds the sensor of carts with a camera.
+    pub fn set_image_source(&mut self, source: Box<dyn ImageSource>) {
+        self.mbc.set_image_source(source);
+    }
+
+    pub fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    /// Rom bank currently mapped at 0x4000-0x7fff.
+    pub fn rom_bank(&self) -> usize {
+        self.mbc.rom_bank()
+    }
+
+    /// Tone the cartridge's speaker is playing, `None` when silent.
+    pub fn tone(&self) -> Option<u8> {
+        self.mbc.tone()
+    }
+
     pub fn entry_point(&self) -> u16 {
         self.entry_point
     }
 
+    pub fn header(&self) -> &CartridgeHeader {
+        &self.header
+    }
+
     pub fn title(&self) -> String {
-        self.title.clone()
+        self.header.title.clone()
     }
 
     pub fn cgb(&self) -> bool {
-        self.cgb
+        self.header.cgb()
     }
 
-    pub fn save_game(&self) {
-        save_game(self.mbc.get_ram(), self.title.as_str());
+    /// Battery save file, written by `save_game`.
+    pub fn save_path(&self) -> &Path {
+        &self.save_path
     }
-}
 
-pub fn load_cartridge<P: AsRef<std::path::Path>>(path: P) -> std::io::Result<Cartridge> {
-    let rom = std::fs::read(path)?;
-    let entry = 0x100;
-
-    let title = std::str::from_utf8(
-        rom[0x134..=0x142]
-            .iter()
-            .copied()
-            .take_while(|x| *x != 0)
-            .collect::<Vec<u8>>()
-            .as_slice(),
-    )
-    .unwrap_or("unkown")
-    .trim_end_matches(|n| n == 0 as char)
-    .to_owned();
-
-    let rom_size = 0x8000 << rom[0x148];
-    let ram_size = match rom[0x149] {
-        0x00 => 0x00,
-        0x01 => 0x800,
-        0x02 => 0x2000,
-        0x03 => 0x8000,
-        0x04 => 0x20000,
-        0x05 => 0x10000,
-        _ => unreachable!(),
-    };
-    assert_eq!(rom_size, rom.len());
-
-    let cgb_flag = rom[0x0143];
-    let cart_type = rom[0x147];
-    let mut mbc: Box<dyn MemoryBankController> = match cart_type {
-        0x00 => Box::new(MBC0::new()),
-        0x01..=0x03 => Box::new(MBC1::new(rom_size, ram_size)),
-        0x05..=0x06 => Box::new(MBC2::new(rom_size)),
-        0x0f..=0x13 => Box::new(MBC3::new(rom_size, ram_size)),
-        0x19..=0x1e => Box::new(MBC5::new(rom_size, ram_size)),
-        _ => panic!("unimplemented type: 0x{:02x}", cart_type),
-    };
+    /// Whether the battery memory changed since it was last saved.
+    pub fn dirty(&self) -> bool {
+        self.header.has_battery() && self.saved_writes != Some(self.mbc.writes())
+    }
 
-    println!("title   : {}", title);
-    println!("mbc type: 0x{:02x} - {}", cart_type, mbc.mbc_type());
-    println!("rom size: 0x{:06x}", rom_size);
-    println!("ram size: 0x{:06x}", ram_size);
-    println!("cgb flag: 0x{:02x}", cgb_flag);
-
-    load_save(mbc.get_ram_mut(), title.as_str());
-    Ok(Cartridge {
-        rom,
-        mbc,
-        entry_point: entry,
-        title,
-        cgb: cgb_flag == 0xc0 || cgb_flag == 0x80,
-    })
-}
+    /// Writes to the battery memory so far, rising while the game keeps
+    /// writing.
+    pub fn writes(&self) -> u64 {
+        self.mbc.writes()
+    }
 
-fn load_save(ram: Option<&mut [u8]>, title: &str) {
-    if let Some(ram) = ram {
-        if ram.len() > 0 {
-            let name = title.to_lowercase() + ".sav";
-            if let Ok(mut file) = OpenOptions::new().read(true).open(name.as_str()) {
-                if let Err(_) = file.read(ram) {
-                    println!("load save failed: {:?}", name);
-                }
-            }
+    /// Writes the battery save, carts without one have nothing to save.
+    pub fn save_game(&mut self) -> std::io::Result<()> {
+        let data = save::save_data(self.mbc.as_ref(), SaveFormat::RtcFooter);
+        if data.is_empty() || !self.header.has_battery() {
+            return Ok(());
         }
+        save::write_atomic(&self.save_path, &data)?;
+        self.saved_writes = Some(self.mbc.writes());
+        Ok(())
+    }
+
+    /// Replaces the battery memory with a save from another emulator or a
+    /// flash cart, with or without a clock after the ram.
+    pub fn import_save<P: AsRef<Path>>(&mut self, path: P) -> std::io::Result<()> {
+        let data = std::fs::read(path)?;
+        save::load_save_data(self.mbc.as_mut(), &data);
+        // not in the save file yet
+        self.saved_writes = None;
+        Ok(())
+    }
+
+    /// Writes the battery memory to a file for another emulator or a flash
+    /// cart.
+    pub fn export_save<P: AsRef<Path>>(&self, path: P, format: SaveFormat) -> std::io::Result<()> {
+        let data = save::save_data(self.mbc.as_ref(), format);
+        save::write_atomic(path.as_ref(), &data)
     }
 }
 
-fn save_game(ram: Option<&[u8]>, title: &str) {
-    if let Some(ram) = ram {
-        if ram.len() > 0 {
-            let name = title.to_lowercase() + ".sav";
-            if let Ok(mut file) = OpenOptions::new()
-                .create(true)
-                .write(true)
-                .truncate(true)
-                .open(name.as_str())
-            {
-                if let Err(_) = file.write_all(ram) {
-                    eprintln!("save game failed: {}", name);
-                } else {
-                    println!("saved: {}", name);
-                }
-            } else {
-                eprintln!("open save file failed: {}", name);
-            }
+impl Savestate for Cartridge {
+    fn save_state(&self, w: &mut StateWriter) {
+        // header checksum and global checksum identify the rom
+        w.write_bytes(&self.rom[0x14d..=0x14f]);
+        self.mbc.save_state(w);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> std::io::Result<()> {
+        let mut checksums = [0u8; 3];
+        r.read_bytes(&mut checksums)?;
+        if checksums != self.rom[0x14d..=0x14f] {
+            return Err(invalid_state("save state belongs to another cartridge"));
         }
+
+        self.mbc.load_state(r)?;
+        // the save file no longer matches the ram
+        self.saved_writes = None;
+        Ok(())
+    }
+}
+
+/// Loads a rom, with its battery save next to it.
+pub fn load_cartridge<P: AsRef<Path>>(path: P) -> Result<Cartridge, CartError> {
+    load_cartridge_with_saves(path, None, ClockSource::Host)
+}
+
+/// Loads a rom, keeping its battery save in `save_dir` if given. The clock of
+/// carts with one runs on `clock`, a saved clock catches up on the time since
+/// it was saved only with the host's.
+pub fn load_cartridge_with_saves<P: AsRef<Path>>(
+    path: P,
+    save_dir: Option<&Path>,
+    clock: ClockSource,
+) -> Result<Cartridge, CartError> {
+    let path = path.as_ref();
+    let rom = std::fs::read(path)?;
+
+    let mut cart = Cartridge::from_rom(rom, save::save_path(path, save_dir))?;
+    cart.set_clock_source(clock);
+    load_save(cart.mbc.as_mut(), &cart.save_path, &cart.header.title);
+    Ok(cart)
+}
+
+/// A blank rom with a valid header, spinning at the entry point, for tests.
+#[cfg(test)]
+pub(crate) fn test_rom(cart_type: u8, rom_size: usize, ram_code: u8) -> Vec<u8> {
+    let mut rom = vec![0u8; rom_size];
+    // jr -2
+    rom[0x100..0x102].copy_from_slice(&[0x18, 0xfe]);
+    rom[0x104..0x134].copy_from_slice(&NINTENDO_LOGO);
+    rom[0x134..0x138].copy_from_slice(b"TEST");
+    rom[0x147] = cart_type;
+    rom[0x148] = (rom_size / 0x8000).trailing_zeros() as u8;
+    rom[0x149] = ram_code;
+    rom[0x14d] = rom[0x134..=0x14c]
+        .iter()
+        .fold(0u8, |sum, &b| sum.wrapping_sub(b).wrapping_sub(1));
+    rom
+}
+
+/// A cartridge that never touches the disk unless saved.
+#[cfg(test)]
+pub(crate) fn test_cartridge(rom: Vec<u8>) -> Cartridge {
+    Cartridge::from_rom(rom, PathBuf::from("test.sav")).unwrap()
+}
+
+/// Seconds since the unix epoch, clocks in save files count on from it.
+fn unix_time() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// The save file is the ram, followed by the clock of carts with one.
+fn load_save(mbc: &mut dyn MemoryBankController, path: &Path, title: &str) {
+    // saves were named after the title in the working directory before
+    let legacy = save::legacy_save_path(title);
+    let path = if !path.exists() && legacy.exists() {
+        legacy.as_path()
+    } else {
+        path
+    };
+
+    match std::fs::read(path) {
+        Ok(data) => save::load_save_data(mbc, &data),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => eprintln!("load save {} failed: {}", path.display(), e),
     }
 }
 
-pub trait MemoryBankController: Send {
+pub trait MemoryBankController: Savestate + Send {
     fn read(&self, rom: &[u8], addr: u16) -> u8;
     fn write(&mut self, addr: u16, data: u8);
     fn mbc_type(&self) -> &'static str;
 
+    /// rom bank mapped at 0x4000-0x7fff
+    fn rom_bank(&self) -> usize {
+        1
+    }
+
+    /// accelerometer input, for carts that have one
+    fn set_tilt(&mut self, _tilt: TiltState) {}
+
+    /// emulated time passing, in 4 MHz cycles
+    fn tick(&mut self, _cycles: u32) {}
+
+    /// time source of the clock, for carts with a clock
+    fn set_clock_source(&mut self, _source: ClockSource) {}
+
+    /// camera sensor input, for carts that have one
+    fn set_image_source(&mut self, _source: Box<dyn ImageSource>) {}
+
+    /// speaker tone playing, for carts with a speaker
+    fn tone(&self) -> Option<u8> {
+        None
+    }
+
     fn get_ram(&self) -> Option<&[u8]> {
         None
     }
     fn get_ram_mut(&mut self) -> Option<&mut [u8]> {
         None
     }
+
+    /// battery memory writes so far
+    fn writes(&self) -> u64 {
+        0
+    }
+
+    /// clock state saved after the ram, for carts with a clock
+    fn save_rtc(&self) -> Option<Vec<u8>> {
+        None
+    }
+    fn load_rtc(&mut self, _data: &[u8]) {}
 }
@@ -0,0 +1,199 @@
+use super::MemoryBankController;
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Layout of a battery save file.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SaveFormat {
+    /// Only the ram, what flash carts and most tools read.
+    Raw,
+    /// The ram followed by the clock of carts with one, the 48 byte footer
+    /// VBA and BGB use for the MBC3.
+    RtcFooter,
+}
+
+/// Save file of a rom, `name.sav` next to it or in `save_dir`.
+pub(crate) fn save_path(rom: &Path, save_dir: Option<&Path>) -> PathBuf {
+    let mut name = rom.file_stem().map(OsString::from).unwrap_or_default();
+    name.push(".sav");
+    match save_dir {
+        Some(dir) => dir.join(name),
+        None => rom.with_file_name(name),
+    }
+}
+
+/// Where saves used to go, the lowercase title in the working directory.
+pub(crate) fn legacy_save_path(title: &str) -> PathBuf {
+    PathBuf::from(title.to_lowercase() + ".sav")
+}
+
+/// Battery backed memory of a cart, empty without any.
+pub(crate) fn save_data(mbc: &dyn MemoryBankController, format: SaveFormat) -> Vec<u8> {
+    let mut data = mbc.get_ram().map(|ram| ram.to_vec()).unwrap_or_default();
+    if format == SaveFormat::RtcFooter {
+        if let Some(rtc) = mbc.save_rtc() {
+            data.extend_from_slice(&rtc);
+        }
+    }
+    data
+}
+
+/// Restores the ram, and the clock if the file has one after the ram.
+pub(crate) fn load_save_data(mbc: &mut dyn MemoryBankController, data: &[u8]) {
+    let ram_len = match mbc.get_ram_mut() {
+        Some(ram) => {
+            let len = ram.len().min(data.len());
+            ram[..len].copy_from_slice(&data[..len]);
+            ram.len()
+        }
+        None => 0,
+    };
+    if data.len() > ram_len {
+        mbc.load_rtc(&data[ram_len..]);
+    }
+}
+
+/// Writes a file next to the target then renames it over, so a crash never
+/// leaves half a save.
+pub(crate) fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        if !dir.as_os_str().is_empty() {
+            fs::create_dir_all(dir)?;
+        }
+    }
+
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+
+    let mut file = File::create(&tmp)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    drop(file);
+    fs::rename(&tmp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cart::{load_cartridge_with_saves, test_cartridge, test_rom, Cartridge};
+    use crate::{ClockSource, GB_CLOCK_SPEED};
+
+    /// An empty directory of its own for each test.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("joy-save-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// An mbc3 with a running clock on emulated time, and a byte in its ram.
+    fn rtc_cartridge(byte: u8) -> Cartridge {
+        let mut cart = test_cartridge(test_rom(0x10, 0x8000, 0x02));
+        cart.set_clock_source(ClockSource::Emulated {
+            offset: Default::default(),
+        });
+        cart.write(0x0000, 0x0a);
+        // clear the halt bit in the day counter's high register
+        cart.write(0x4000, 0x0c);
+        cart.write(0xa000, 0x00);
+        cart.write(0x4000, 0x00);
+        cart.write(0xa000, byte);
+        cart
+    }
+
+    #[test]
+    fn save_path_next_to_rom_or_in_save_dir() {
+        let rom = Path::new("roms").join("game.gb");
+        assert_eq!(save_path(&rom, None), Path::new("roms").join("game.sav"));
+        assert_eq!(
+            save_path(&rom, Some(Path::new("saves"))),
+            Path::new("saves").join("game.sav")
+        );
+
+        let dir = temp_dir("path");
+        let rom = dir.join("game.gb");
+        fs::write(&rom, test_rom(0x03, 0x8000, 0x02)).unwrap();
+        let saves = dir.join("saves");
+        let mut cart = load_cartridge_with_saves(&rom, Some(&saves), ClockSource::Host).unwrap();
+        cart.write(0x0000, 0x0a);
+        cart.write(0xa000, 0x42);
+        cart.save_game().unwrap();
+        assert!(saves.join("game.sav").exists());
+        assert!(!dir.join("game.sav").exists());
+
+        let mut cart = load_cartridge_with_saves(&rom, Some(&saves), ClockSource::Host).unwrap();
+        cart.write(0x0000, 0x0a);
+        assert_eq!(cart.read(0xa000), 0x42);
+        let mut cart = load_cartridge_with_saves(&rom, None, ClockSource::Host).unwrap();
+        cart.write(0x0000, 0x0a);
+        assert_eq!(cart.read(0xa000), 0x00);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn ram_without_battery_is_not_saved() {
+        let dir = temp_dir("battery");
+        let rom = dir.join("game.gb");
+        // mbc1 with ram, but no battery
+        fs::write(&rom, test_rom(0x02, 0x8000, 0x02)).unwrap();
+        let mut cart = load_cartridge_with_saves(&rom, None, ClockSource::Host).unwrap();
+        cart.write(0x0000, 0x0a);
+        cart.write(0xa000, 0x42);
+        assert!(!cart.dirty());
+        cart.save_game().unwrap();
+        assert!(!dir.join("game.sav").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn write_atomic_replaces_the_file() {
+        let dir = temp_dir("atomic");
+        let path = dir.join("new").join("dir").join("game.sav");
+        write_atomic(&path, &[1, 2, 3]).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), [1, 2, 3]);
+        write_atomic(&path, &[4, 5]).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), [4, 5]);
+
+        // nothing left beside it
+        let files = fs::read_dir(path.parent().unwrap()).unwrap().count();
+        assert_eq!(files, 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn export_and_import() {
+        let dir = temp_dir("export");
+        let mut cart = rtc_cartridge(0x42);
+        cart.tick(GB_CLOCK_SPEED * 90);
+
+        let raw = dir.join("raw.sav");
+        let footer = dir.join("footer.sav");
+        cart.export_save(&raw, SaveFormat::Raw).unwrap();
+        cart.export_save(&footer, SaveFormat::RtcFooter).unwrap();
+        let raw_data = fs::read(&raw).unwrap();
+        let footer_data = fs::read(&footer).unwrap();
+        assert_eq!(raw_data.len(), 0x2000);
+        assert_eq!(footer_data.len(), 0x2000 + 48);
+        assert_eq!(raw_data[..], footer_data[..0x2000]);
+        // a minute and a half
+        assert_eq!(footer_data[0x2000..0x2008], [30, 0, 0, 0, 1, 0, 0, 0]);
+
+        // without a footer the clock is kept
+        let mut imported = rtc_cartridge(0x00);
+        imported.import_save(&raw).unwrap();
+        assert_eq!(imported.read(0xa000), 0x42);
+        assert!(imported.dirty());
+        let data = save_data(imported.mbc.as_ref(), SaveFormat::RtcFooter);
+        assert!(data[0x2000..0x2014].iter().all(|&b| b == 0));
+
+        let mut imported = rtc_cartridge(0x00);
+        imported.import_save(&footer).unwrap();
+        assert_eq!(imported.read(0xa000), 0x42);
+        let data = save_data(imported.mbc.as_ref(), SaveFormat::RtcFooter);
+        assert_eq!(data[0x2000..0x2014], footer_data[0x2000..0x2014]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
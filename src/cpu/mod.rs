@@ -1,8 +1,10 @@
 pub use self::reg::*;
 use crate::bus::Bus;
+use crate::disasm;
+use crate::state::{Savestate, StateReader, StateWriter};
 
 mod ins;
-mod ops;
+pub(crate) mod ops;
 mod reg;
 
 pub struct Cpu {
@@ -46,6 +48,15 @@ impl Cpu {
         self.halt = false;
     }
 
+    // Power-on state, the boot rom sets up the rest.
+    pub fn power_on(&mut self) {
+        self.reg = Default::default();
+
+        self.interrupt_master_enable = false;
+        self.interrupt_enable_delay = false;
+        self.halt = false;
+    }
+
     pub fn step(&mut self, io: &mut Bus) -> u32 {
         if self.interrupt_enable_delay {
             self.interrupt_enable_delay = false;
@@ -89,17 +100,46 @@ impl Cpu {
         }
     }
 
+    pub fn reg(&self) -> &Reg {
+        &self.reg
+    }
+
     pub fn debug_output(&self, io: &Bus) {
-        let op = io.read_direct(self.reg.pc);
         print!("{:?} (cy: {})", self.reg, io.mcycles());
 
-        let (op, desc) = if op != 0xcb {
-            (op, ops::OP_TABLE[op as usize].2)
-        } else {
-            let op = io.read_direct(self.reg.pc + 1);
-            (op, ops::OP_CB_TABLE[op as usize].2)
-        };
-        println!(" [{:02x}] {}", op, desc);
+        let ins = disasm::decode(self.reg.pc, |addr| io.peek(addr));
+        let addr = disasm::format_addr(self.reg.pc, io.cart.rom_bank());
+        println!(" [{}] {}", addr, ins);
+    }
+}
+
+impl Savestate for Cpu {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u16(self.reg.af());
+        w.write_u16(self.reg.bc);
+        w.write_u16(self.reg.de);
+        w.write_u16(self.reg.hl);
+        w.write_u16(self.reg.sp);
+        w.write_u16(self.reg.pc);
+
+        w.write_bool(self.interrupt_master_enable);
+        w.write_bool(self.interrupt_enable_delay);
+        w.write_bool(self.halt);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> std::io::Result<()> {
+        self.reg.set_af(r.read_u16()?);
+        self.reg.bc = r.read_u16()?;
+        self.reg.de = r.read_u16()?;
+        self.reg.hl = r.read_u16()?;
+        self.reg.sp = r.read_u16()?;
+        self.reg.pc = r.read_u16()?;
+
+        self.interrupt_master_enable = r.read_bool()?;
+        self.interrupt_enable_delay = r.read_bool()?;
+        self.halt = r.read_bool()?;
+
+        Ok(())
     }
 }
 
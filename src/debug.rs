@@ -0,0 +1,343 @@
+use crate::{Gameboy, GB_CLOCK_SPEED, GB_DEVICE_FPS};
+use std::ops::RangeInclusive;
+
+/// clocks between dropping audio on long runs
+const FRAME_CYCLES: u32 = GB_CLOCK_SPEED / GB_DEVICE_FPS;
+
+/// `CALL a16`, `CALL cc, a16`
+const CALL_OPS: [u8; 5] = [0xcd, 0xc4, 0xcc, 0xd4, 0xdc];
+/// `RET`, `RETI`, `RET cc`
+const RET_OPS: [u8; 6] = [0xc9, 0xd9, 0xc0, 0xc8, 0xd0, 0xd8];
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+/// A memory access made by the cpu.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct MemoryAccess {
+    pub access: Access,
+    pub addr: u16,
+    pub data: u8,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Breakpoint {
+    /// Stops before executing the instruction at the address.
+    Pc(u16),
+    /// Stops before executing the opcode, `0xcbxx` for prefixed ones.
+    Opcode(u16),
+    /// Stops after an instruction reads from the address range.
+    Read(RangeInclusive<u16>),
+    /// Watchpoint, stops after an instruction writes to the address range.
+    Write(RangeInclusive<u16>),
+}
+
+/// Why the debugger handed control back.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Stop {
+    /// The step, step over or run to return has completed.
+    Done,
+    /// The cycle budget was used up.
+    Timeout,
+    Breakpoint(Breakpoint),
+    Watch(Breakpoint, MemoryAccess),
+}
+
+/// Memory ranges watched on the bus, set up by the debugger.
+#[derive(Default)]
+pub(crate) struct Watch {
+    ranges: Vec<(RangeInclusive<u16>, Access)>,
+    hit: Option<MemoryAccess>,
+}
+
+impl Watch {
+    #[inline]
+    pub fn check(&mut self, access: Access, addr: u16, data: u8) {
+        if self.ranges.is_empty() || self.hit.is_some() {
+            return;
+        }
+
+        if self
+            .ranges
+            .iter()
+            .any(|(range, a)| *a == access && range.contains(&addr))
+        {
+            self.hit = Some(MemoryAccess { access, addr, data });
+        }
+    }
+}
+
+/// Breakpoints and stepping on top of a `Gameboy`.
+///
+/// The debugger does not own the machine, every call takes the gameboy to
+/// run. Memory breakpoints are installed on its bus while running.
+pub struct Debugger {
+    breakpoints: Vec<Breakpoint>,
+    /// pc of the last breakpoint stop, not stopped at again when resuming
+    resume_pc: Option<u16>,
+    /// clocks single stepped since audio was last dropped
+    step_cycles: u32,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Debugger {
+            breakpoints: Vec::new(),
+            resume_pc: None,
+            step_cycles: 0,
+        }
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) {
+        if !self.breakpoints.contains(&breakpoint) {
+            self.breakpoints.push(breakpoint);
+        }
+    }
+
+    pub fn remove_breakpoint(&mut self, index: usize) -> Option<Breakpoint> {
+        if index < self.breakpoints.len() {
+            Some(self.breakpoints.remove(index))
+        } else {
+            None
+        }
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    /// Runs for up to `max_cycles` clocks or until a breakpoint is hit.
+    ///
+    /// Breakpoints are checked before each instruction, including the first
+    /// one, except when resuming from a breakpoint stop at the same pc.
+    pub fn run(&mut self, gameboy: &mut Gameboy, max_cycles: u32) -> Stop {
+        self.run_until(gameboy, max_cycles, false, |_, _| false)
+    }
+
+    /// Executes a single instruction. Audio is dropped every frame's worth of
+    /// steps, so any number of them can be taken in a row.
+    pub fn step(&mut self, gameboy: &mut Gameboy) -> Stop {
+        self.resume_pc = None;
+        self.install(gameboy);
+        let (cycles, stop) = self.execute(gameboy);
+        self.uninstall(gameboy);
+
+        // the resampler only holds a few frames
+        self.step_cycles += cycles;
+        if self.step_cycles >= FRAME_CYCLES {
+            self.step_cycles = 0;
+            gameboy.apu_output(|_| {});
+        }
+        stop.unwrap_or(Stop::Done)
+    }
+
+    /// Executes a single instruction, running calls and restarts until they
+    /// return. Audio produced meanwhile is dropped.
+    pub fn step_over(&mut self, gameboy: &mut Gameboy, max_cycles: u32) -> Stop {
+        let reg = gameboy.registers();
+        let (pc, sp) = (reg.pc, reg.sp);
+
+        let op = gameboy.read_memory(pc);
+        let len = if CALL_OPS.contains(&op) {
+            3
+        } else if op & 0b1100_0111 == 0b1100_0111 {
+            // RST n
+            1
+        } else {
+            return self.step(gameboy);
+        };
+
+        let next = pc.wrapping_add(len);
+        self.run_until(gameboy, max_cycles, true, |gameboy, _| {
+            let reg = gameboy.registers();
+            reg.pc == next && reg.sp >= sp
+        })
+    }
+
+    /// Runs until the current function returns. Audio produced meanwhile is
+    /// dropped.
+    pub fn run_to_return(&mut self, gameboy: &mut Gameboy, max_cycles: u32) -> Stop {
+        let sp = gameboy.registers().sp;
+
+        self.run_until(gameboy, max_cycles, true, |gameboy, op| {
+            RET_OPS.contains(&op) && gameboy.registers().sp > sp
+        })
+    }
+
+    // runs until `done` returns true after an instruction, which also gets the
+    // executed opcode
+    fn run_until<F>(
+        &mut self,
+        gameboy: &mut Gameboy,
+        max_cycles: u32,
+        drop_audio: bool,
+        mut done: F,
+    ) -> Stop
+    where
+        F: FnMut(&Gameboy, u8) -> bool,
+    {
+        self.install(gameboy);
+
+        let resume_pc = self.resume_pc.take();
+        let mut first = true;
+        let mut current = 0;
+        let stop = loop {
+            let pc = gameboy.registers().pc;
+            if !(first && resume_pc == Some(pc)) {
+                if let Some(breakpoint) = self.check_pc(gameboy) {
+                    self.resume_pc = Some(pc);
+                    break Stop::Breakpoint(breakpoint);
+                }
+            }
+
+            if current >= max_cycles {
+                if first {
+                    // still resuming next time
+                    self.resume_pc = resume_pc;
+                }
+                break Stop::Timeout;
+            }
+            first = false;
+
+            let op = gameboy.read_memory(gameboy.registers().pc);
+            let (cycles, stop) = self.execute(gameboy);
+            if let Some(stop) = stop {
+                break stop;
+            }
+            current += cycles;
+
+            // the resampler only holds a few frames
+            if drop_audio && current % FRAME_CYCLES < cycles {
+                gameboy.apu_output(|_| {});
+            }
+
+            if done(gameboy, op) {
+                break Stop::Done;
+            }
+        };
+
+        self.uninstall(gameboy);
+        stop
+    }
+
+    // executes one instruction, returns the clocks taken and the watchpoint
+    // it triggered
+    fn execute(&self, gameboy: &mut Gameboy) -> (u32, Option<Stop>) {
+        let cycles = gameboy.step();
+
+        let stop = gameboy.bus.watch.hit.take().and_then(|access| {
+            self.breakpoints
+                .iter()
+                .find(|b| match (b, access.access) {
+                    (Breakpoint::Read(range), Access::Read) => range.contains(&access.addr),
+                    (Breakpoint::Write(range), Access::Write) => range.contains(&access.addr),
+                    _ => false,
+                })
+                .map(|b| Stop::Watch(b.clone(), access))
+        });
+
+        (cycles, stop)
+    }
+
+    fn check_pc(&self, gameboy: &Gameboy) -> Option<Breakpoint> {
+        let pc = gameboy.registers().pc;
+        let op = match gameboy.read_memory(pc) {
+            0xcb => 0xcb00 | gameboy.read_memory(pc.wrapping_add(1)) as u16,
+            op => op as u16,
+        };
+
+        self.breakpoints
+            .iter()
+            .find(|b| match b {
+                Breakpoint::Pc(addr) => *addr == pc,
+                Breakpoint::Opcode(code) => *code == op,
+                _ => false,
+            })
+            .cloned()
+    }
+
+    fn install(&self, gameboy: &mut Gameboy) {
+        let watch = &mut gameboy.bus.watch;
+        watch.hit = None;
+        watch.ranges = self
+            .breakpoints
+            .iter()
+            .filter_map(|b| match b {
+                Breakpoint::Read(range) => Some((range.clone(), Access::Read)),
+                Breakpoint::Write(range) => Some((range.clone(), Access::Write)),
+                _ => None,
+            })
+            .collect();
+    }
+
+    fn uninstall(&self, gameboy: &mut Gameboy) {
+        gameboy.bus.watch = Watch::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cart::{test_cartridge, test_rom};
+
+    // jumps past the header to nops at 0x150, 4 clocks each
+    fn nop_gameboy() -> Gameboy {
+        let mut rom = test_rom(0x00, 0x8000, 0x00);
+        rom[0x100..0x104].copy_from_slice(&[0x00, 0xc3, 0x50, 0x01]);
+        for byte in &mut rom[0x150..0x160] {
+            *byte = 0x00;
+        }
+        rom[0x160..0x162].copy_from_slice(&[0x18, 0xfe]);
+        let mut gameboy = Gameboy::new(test_cartridge(rom));
+        gameboy.step();
+        gameboy.step();
+        assert_eq!(gameboy.registers().pc, 0x150);
+        gameboy
+    }
+
+    #[test]
+    fn breakpoint_on_slice_boundary() {
+        let mut gameboy = nop_gameboy();
+        let mut debugger = Debugger::new();
+
+        // the slice ends right where the breakpoint is
+        assert_eq!(debugger.run(&mut gameboy, 12), Stop::Timeout);
+        assert_eq!(gameboy.registers().pc, 0x153);
+        debugger.add_breakpoint(Breakpoint::Pc(0x153));
+        assert_eq!(
+            debugger.run(&mut gameboy, 12),
+            Stop::Breakpoint(Breakpoint::Pc(0x153))
+        );
+        assert_eq!(gameboy.registers().pc, 0x153);
+
+        // an empty slice keeps resuming past it
+        assert_eq!(debugger.run(&mut gameboy, 0), Stop::Timeout);
+        assert_eq!(debugger.run(&mut gameboy, 4), Stop::Timeout);
+        assert_eq!(gameboy.registers().pc, 0x154);
+
+        // and is hit when it is reached with the last clocks of a slice
+        let mut gameboy = nop_gameboy();
+        assert_eq!(
+            debugger.run(&mut gameboy, 12),
+            Stop::Breakpoint(Breakpoint::Pc(0x153))
+        );
+        assert_eq!(gameboy.registers().pc, 0x153);
+    }
+
+    #[test]
+    fn many_steps_drop_audio() {
+        let mut gameboy = nop_gameboy();
+        let mut debugger = Debugger::new();
+        // a few frames, more than the resampler holds
+        for _ in 0..100_000 {
+            assert_eq!(debugger.step(&mut gameboy), Stop::Done);
+        }
+    }
+}
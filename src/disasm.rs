@@ -0,0 +1,264 @@
+use crate::cpu::ops::{OP_CB_TABLE, OP_TABLE};
+use crate::Gameboy;
+
+use std::fmt;
+
+/// cartridge header, not code
+const HEADER: std::ops::Range<u16> = 0x0104..0x0150;
+
+/// hardware register names, as in hardware.inc
+const IO_NAMES: &[(u16, &str)] = &[
+    (0xff00, "rP1"),
+    (0xff01, "rSB"),
+    (0xff02, "rSC"),
+    (0xff04, "rDIV"),
+    (0xff05, "rTIMA"),
+    (0xff06, "rTMA"),
+    (0xff07, "rTAC"),
+    (0xff0f, "rIF"),
+    (0xff10, "rNR10"),
+    (0xff11, "rNR11"),
+    (0xff12, "rNR12"),
+    (0xff13, "rNR13"),
+    (0xff14, "rNR14"),
+    (0xff16, "rNR21"),
+    (0xff17, "rNR22"),
+    (0xff18, "rNR23"),
+    (0xff19, "rNR24"),
+    (0xff1a, "rNR30"),
+    (0xff1b, "rNR31"),
+    (0xff1c, "rNR32"),
+    (0xff1d, "rNR33"),
+    (0xff1e, "rNR34"),
+    (0xff20, "rNR41"),
+    (0xff21, "rNR42"),
+    (0xff22, "rNR43"),
+    (0xff23, "rNR44"),
+    (0xff24, "rNR50"),
+    (0xff25, "rNR51"),
+    (0xff26, "rNR52"),
+    (0xff40, "rLCDC"),
+    (0xff41, "rSTAT"),
+    (0xff42, "rSCY"),
+    (0xff43, "rSCX"),
+    (0xff44, "rLY"),
+    (0xff45, "rLYC"),
+    (0xff46, "rDMA"),
+    (0xff47, "rBGP"),
+    (0xff48, "rOBP0"),
+    (0xff49, "rOBP1"),
+    (0xff4a, "rWY"),
+    (0xff4b, "rWX"),
+    (0xff4c, "rKEY0"),
+    (0xff4d, "rKEY1"),
+    (0xff4f, "rVBK"),
+    (0xff50, "rBANK"),
+    (0xff51, "rHDMA1"),
+    (0xff52, "rHDMA2"),
+    (0xff53, "rHDMA3"),
+    (0xff54, "rHDMA4"),
+    (0xff55, "rHDMA5"),
+    (0xff56, "rRP"),
+    (0xff68, "rBCPS"),
+    (0xff69, "rBCPD"),
+    (0xff6a, "rOCPS"),
+    (0xff6b, "rOCPD"),
+    (0xff70, "rSVBK"),
+    (0xffff, "rIE"),
+];
+
+/// A decoded instruction.
+pub struct Instruction {
+    pub addr: u16,
+    pub bytes: Vec<u8>,
+    pub text: String,
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bytes = self
+            .bytes
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<Vec<_>>();
+        write!(f, "{:<8}  {}", bytes.join(" "), self.text)
+    }
+}
+
+/// Decodes the instruction at `addr`, reading memory through `read`.
+pub fn decode<F: Fn(u16) -> u8>(addr: u16, read: F) -> Instruction {
+    let op = read(addr);
+    if op == 0xcb {
+        let op = read(addr.wrapping_add(1));
+        return Instruction {
+            addr,
+            bytes: vec![0xcb, op],
+            text: OP_CB_TABLE[op as usize].2.to_owned(),
+        };
+    }
+
+    let template = OP_TABLE[op as usize].2;
+    if template == "INVALID_OP" {
+        return Instruction {
+            addr,
+            bytes: vec![op],
+            text: format!("db ${:02x}", op),
+        };
+    }
+
+    let operands = if template.contains("d16") || template.contains("a16") {
+        2
+    } else if ["d8", "a8", "r8"].iter().any(|t| template.contains(t)) || template == "STOP" {
+        1
+    } else {
+        0
+    };
+
+    let bytes = (0..=operands)
+        .map(|i| read(addr.wrapping_add(i)))
+        .collect::<Vec<_>>();
+    let byte = bytes.get(1).copied().unwrap_or(0);
+    let word = byte as u16 | (bytes.get(2).copied().unwrap_or(0) as u16) << 8;
+
+    let text = if template.contains("d16") {
+        template.replace("d16", &format!("${:04x}", word))
+    } else if template.contains("a16") {
+        template.replace("a16", &address_name(word))
+    } else if template.contains("a8") {
+        template.replace("a8", &address_name(0xff00 | byte as u16))
+    } else if template.contains("d8") {
+        template.replace("d8", &format!("${:02x}", byte))
+    } else if template.contains("SP+r8") {
+        template.replace("SP+r8", &format!("SP{:+}", byte as i8))
+    } else if template.starts_with("JR") {
+        let target = addr.wrapping_add(2).wrapping_add(byte as i8 as u16);
+        template.replace("r8", &format!("${:04x}", target))
+    } else if template.contains("r8") {
+        template.replace("r8", &format!("{}", byte as i8))
+    } else {
+        template.to_owned()
+    };
+
+    Instruction { addr, bytes, text }
+}
+
+/// `0xff00+n` addresses by register name, others as hex.
+pub fn address_name(addr: u16) -> String {
+    match IO_NAMES.iter().find(|(a, _)| *a == addr) {
+        Some((_, name)) => (*name).to_owned(),
+        None => format!("${:04x}", addr),
+    }
+}
+
+/// Formats an address with the rom bank it is in, like `01:4000`.
+pub fn format_addr(addr: u16, bank: usize) -> String {
+    match addr {
+        0x0000..=0x3fff => format!("00:{:04x}", addr),
+        0x4000..=0x7fff => format!("{:02x}:{:04x}", bank, addr),
+        _ => format!("   {:04x}", addr),
+    }
+}
+
+/// Disassembles `count` instructions from `addr`, as the cpu sees memory.
+pub fn disassemble(gameboy: &Gameboy, addr: u16, count: usize) -> Vec<String> {
+    let bank = gameboy.bus.cart.rom_bank();
+
+    let mut addr = addr;
+    let mut lines = Vec::with_capacity(count);
+    for _ in 0..count {
+        let ins = decode(addr, |addr| gameboy.read_memory(addr));
+        lines.push(format!("{}  {}", format_addr(addr, bank), ins));
+        addr = addr.wrapping_add(ins.bytes.len() as u16);
+    }
+    lines
+}
+
+/// Disassembles a whole rom bank, `None` if the rom is too small.
+///
+/// This is a linear sweep, data mixed into code will show up as garbage
+/// instructions.
+pub fn disassemble_bank(rom: &[u8], bank: usize) -> Option<String> {
+    let data = rom.get(bank * 0x4000..(bank + 1) * 0x4000)?;
+    let base = if bank == 0 { 0x0000 } else { 0x4000 };
+    let end = base + data.len() as u16;
+    let read = |addr: u16| data.get((addr - base) as usize).copied().unwrap_or(0xff);
+
+    let mut out = String::new();
+    let mut addr = base;
+    while addr < end {
+        if bank == 0 && HEADER.contains(&addr) {
+            let len = 8.min(HEADER.end - addr);
+            let bytes = (addr..addr + len)
+                .map(|addr| format!("${:02x}", read(addr)))
+                .collect::<Vec<_>>();
+            let addr_text = format_addr(addr, bank);
+            out += &format!("{}  {:<8}  db {}\n", addr_text, "", bytes.join(", "));
+            addr += len;
+            continue;
+        }
+
+        let ins = decode(addr, |addr| if addr < end { read(addr) } else { 0xff });
+        let len = ins.bytes.len() as u16;
+        if end - addr < len {
+            // cut off at the end of the bank
+            let addr_text = format_addr(addr, bank);
+            out += &format!("{}  {:<8}  db ${:02x}\n", addr_text, "", read(addr));
+            addr += 1;
+            continue;
+        }
+
+        out += &format!("{}  {}\n", format_addr(addr, bank), ins);
+        addr += len;
+    }
+
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_bytes(addr: u16, bytes: &[u8]) -> Instruction {
+        decode(addr, |a| {
+            bytes
+                .get(a.wrapping_sub(addr) as usize)
+                .copied()
+                .unwrap_or(0)
+        })
+    }
+
+    #[test]
+    fn operands() {
+        let ins = decode_bytes(0x0150, &[0x01, 0x34, 0x12]);
+        assert_eq!(ins.bytes, [0x01, 0x34, 0x12]);
+        assert_eq!(ins.text, "LD BC, $1234");
+
+        assert_eq!(decode_bytes(0, &[0x3e, 0x80]).text, "LD A, $80");
+        assert_eq!(decode_bytes(0, &[0xe0, 0x40]).text, "LDH (rLCDC), A");
+        assert_eq!(decode_bytes(0, &[0xc3, 0x50, 0x01]).text, "JP $0150");
+        assert_eq!(decode_bytes(0, &[0xf8, 0xfe]).text, "LD HL, SP-2");
+        assert_eq!(decode_bytes(0x0100, &[0x00]).text, "NOP");
+    }
+
+    #[test]
+    fn relative_jump() {
+        let ins = decode_bytes(0x0200, &[0x18, 0xfe]);
+        assert_eq!(ins.bytes.len(), 2);
+        assert_eq!(ins.text, "JR $0200");
+    }
+
+    #[test]
+    fn cb_prefix() {
+        let ins = decode_bytes(0x4000, &[0xcb, 0x7c]);
+        assert_eq!(ins.bytes, [0xcb, 0x7c]);
+        assert_eq!(ins.text, "BIT 7, H");
+    }
+
+    #[test]
+    fn invalid_opcode() {
+        let ins = decode_bytes(0, &[0xd3, 0x12]);
+        assert_eq!(ins.bytes, [0xd3]);
+        assert_eq!(ins.text, "db $d3");
+        assert_eq!(ins.to_string(), "d3        db $d3");
+    }
+}
@@ -1,8 +1,14 @@
-use crate::{Bus, Cartridge, Cpu, JoypadState};
+use crate::apu::{Channel, ChannelControl, OutputFilter};
+use crate::bus::{BootRom, SerialLink};
+use crate::cart::{ClockSource, ImageSource};
+use crate::cpu::Reg;
+use crate::ppu::{self, DmgPalette};
+use crate::state::{invalid_state, Savestate, StateReader, StateWriter};
+use crate::{Bus, Cartridge, Cpu, JoypadState, TiltState};
 
 pub struct Gameboy {
-    cpu: Cpu,
-    bus: Bus,
+    pub(crate) cpu: Cpu,
+    pub(crate) bus: Bus,
 }
 
 impl Gameboy {
@@ -17,29 +23,311 @@ impl Gameboy {
         g
     }
 
+    /// Starts from the boot rom, a cgb boot rom runs dmg carts in cgb
+    /// compatibility mode.
+    pub fn with_boot_rom(cart: Cartridge, boot_rom: BootRom) -> Self {
+        let mut g = Self {
+            cpu: Cpu::new(boot_rom.cgb()),
+            bus: Bus::with_boot_rom(cart, boot_rom),
+        };
+        g.reset();
+
+        g
+    }
+
     pub fn reset(&mut self) {
-        self.cpu.reset();
         self.bus.reset();
+        if self.bus.boot_rom_mapped() {
+            self.cpu.power_on();
+        } else {
+            self.cpu.reset();
+        }
     }
 
     pub fn emulate(&mut self, max_cycles: u32, states: JoypadState) {
-        self.bus.set_input(states);
+        self.set_input(states);
 
         let mut current = 0;
         while current < max_cycles {
-            current += self.cpu.step(&mut self.bus);
+            current += self.step();
         }
     }
 
-    pub fn save_game(&self) {
-        self.bus.cart.save_game();
+    pub fn set_input(&mut self, states: JoypadState) {
+        self.bus.set_input(states);
+    }
+
+    /// Tilts the gameboy, for carts with an accelerometer.
+    pub fn set_tilt(&mut self, tilt: TiltState) {
+        self.bus.cart.set_tilt(tilt);
+    }
+
+    /// Sets where the cartridge's clock gets the time from, emulated time
+    /// makes runs repeatable.
+    pub fn set_clock_source(&mut self, source: ClockSource) {
+        self.bus.cart.set_clock_source(source);
+    }
+
+    /// Points the camera of a Pocket Camera cart at an image.
+    pub fn set_image_source(&mut self, source: Box<dyn ImageSource>) {
+        self.bus.cart.set_image_source(source);
+    }
+
+    /// Executes one instruction, returns the clocks taken.
+    pub fn step(&mut self) -> u32 {
+        self.cpu.step(&mut self.bus)
+    }
+
+    pub fn registers(&self) -> &Reg {
+        self.cpu.reg()
+    }
+
+    /// Reads memory as the cpu sees it, without side effects.
+    pub fn read_memory(&self, addr: u16) -> u8 {
+        self.bus.peek(addr)
+    }
+
+    /// Prints the registers and the next instruction.
+    pub fn debug_output(&self) {
+        self.cpu.debug_output(&self.bus);
+    }
+
+    /// Sets the colors of dmg carts.
+    pub fn set_dmg_palette(&mut self, palette: &DmgPalette) {
+        self.bus.ppu.set_dmg_palette(palette);
+    }
+
+    pub fn dmg_palette(&self) -> DmgPalette {
+        self.bus.ppu.dmg_palette()
+    }
+
+    /// Colors a dmg cart the way a cgb without a boot rom dump would, from
+    /// the title lookup table or from the buttons held at boot.
+    pub fn colorize(&mut self, keys: &JoypadState) {
+        let palette =
+            ppu::key_palette(keys).unwrap_or_else(|| ppu::title_palette(self.bus.cart.rom()));
+        self.set_dmg_palette(&palette);
+    }
+
+    /// Plugs a device into the link port.
+    pub fn set_serial_link(&mut self, link: Box<dyn SerialLink>) {
+        self.bus.set_serial_link(link);
+    }
+
+    /// Whether the cartridge's battery memory changed since it was last
+    /// saved.
+    pub fn save_dirty(&self) -> bool {
+        self.bus.cart.dirty()
+    }
+
+    /// Writes to the cartridge's battery memory so far, for noticing new
+    /// ones.
+    pub fn save_writes(&self) -> u64 {
+        self.bus.cart.writes()
+    }
+
+    /// Writes the cartridge's battery save.
+    pub fn save_game(&mut self) -> std::io::Result<()> {
+        self.bus.cart.save_game()
+    }
+
+    /// Snapshots the whole machine into a versioned save state.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut w = StateWriter::new();
+        self.cpu.save_state(&mut w);
+        self.bus.save_state(&mut w);
+        w.into_inner()
+    }
+
+    /// Restores a save state made by `save_state` with the same cartridge.
+    ///
+    /// On error the machine may be partially overwritten and should be reset.
+    pub fn load_state(&mut self, state: &[u8]) -> std::io::Result<()> {
+        let mut r = StateReader::new(state)?;
+        self.cpu.load_state(&mut r)?;
+        self.bus.load_state(&mut r)?;
+
+        if !r.is_empty() {
+            return Err(invalid_state("trailing data in save state"));
+        }
+        Ok(())
     }
 
     pub fn apu_output(&mut self, cb: impl FnMut(&[i16])) {
         self.bus.apu.output(cb);
     }
 
+    /// Samples per second of `apu_output`.
+    pub fn sample_rate(&self) -> u32 {
+        self.bus.apu.sample_rate()
+    }
+
+    /// Changes the audio output rate, `AUDIO_FREQUENCY` by default.
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.bus.apu.set_sample_rate(sample_rate);
+    }
+
+    /// Nudges the audio output rate by `ratio`, to follow the host's audio
+    /// clock.
+    pub fn set_rate_adjust(&mut self, ratio: f64) {
+        self.bus.apu.set_rate_adjust(ratio);
+    }
+
+    pub fn audio_filter(&self) -> OutputFilter {
+        self.bus.apu.filter()
+    }
+
+    /// Changes the analog filters on the audio output, the model's high pass
+    /// by default.
+    pub fn set_audio_filter(&mut self, filter: OutputFilter) {
+        self.bus.apu.set_filter(filter);
+    }
+
+    /// Writes the audio output to a wav file, as `apu_output` returns it.
+    pub fn start_recording<P: AsRef<std::path::Path>>(&mut self, path: P) -> std::io::Result<()> {
+        self.bus.apu.start_recording(path)
+    }
+
+    pub fn stop_recording(&mut self) -> std::io::Result<()> {
+        self.bus.apu.stop_recording()
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.bus.apu.is_recording()
+    }
+
+    /// Also resamples each sound channel on its own, read with
+    /// `channel_output`.
+    pub fn set_channel_output(&mut self, enable: bool) {
+        self.bus.apu.set_channel_output(enable);
+    }
+
+    /// Mono samples of each sound channel since the last call.
+    pub fn channel_output(&mut self, cb: impl FnMut(Channel, &[i16])) {
+        self.bus.apu.channel_output(cb);
+    }
+
+    pub fn channel_control(&self, channel: Channel) -> ChannelControl {
+        self.bus.apu.channel_control(channel)
+    }
+
+    /// Mutes, solos, amplifies or pans a sound channel without the game
+    /// noticing.
+    pub fn set_channel_control(&mut self, channel: Channel, control: ChannelControl) {
+        self.bus.apu.set_channel_control(channel, control);
+    }
+
     pub fn get_frame_buffer(&self) -> &[u8] {
         self.bus.ppu.get_frame_buffer()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cart::{test_cartridge, test_rom};
+
+    /// Emulates a few frames, draining the audio like a frontend would.
+    fn run_frames(gameboy: &mut Gameboy, frames: u32) {
+        for _ in 0..frames {
+            gameboy.emulate(
+                crate::GB_CLOCK_SPEED / crate::GB_DEVICE_FPS,
+                JoypadState::default(),
+            );
+            gameboy.apu_output(|_| {});
+        }
+    }
+
+    fn running_gameboy(rom: Vec<u8>) -> Gameboy {
+        let mut gameboy = Gameboy::new(test_cartridge(rom));
+        run_frames(&mut gameboy, 6);
+        gameboy
+    }
+
+    #[test]
+    fn save_state_round_trip() {
+        // mbc1 with ram
+        let mut gameboy = running_gameboy(test_rom(0x03, 0x10000, 0x02));
+        let state = gameboy.save_state();
+
+        run_frames(&mut gameboy, 6);
+        assert_ne!(gameboy.save_state(), state);
+
+        gameboy.load_state(&state).unwrap();
+        assert_eq!(gameboy.save_state(), state);
+    }
+
+    #[test]
+    fn save_state_keeps_the_palette() {
+        let mut gameboy = running_gameboy(test_rom(0x03, 0x10000, 0x02));
+        let state = gameboy.save_state();
+
+        let palette = DmgPalette::preset("bgb").unwrap();
+        gameboy.set_dmg_palette(&palette);
+        gameboy.load_state(&state).unwrap();
+        assert_eq!(gameboy.dmg_palette(), palette);
+    }
+
+    #[test]
+    fn save_state_rejects_bad_states() {
+        let mut gameboy = running_gameboy(test_rom(0x03, 0x10000, 0x02));
+        let state = gameboy.save_state();
+
+        let mut other_rom = test_rom(0x03, 0x10000, 0x02);
+        other_rom[0x14e] = 0x12;
+        let other = running_gameboy(other_rom).save_state();
+        assert!(gameboy.load_state(&other).is_err());
+
+        let mut bad_version = state.clone();
+        bad_version[4] ^= 0xff;
+        assert!(gameboy.load_state(&bad_version).is_err());
+
+        assert!(gameboy.load_state(&state[..state.len() - 1]).is_err());
+
+        let mut trailing = state.clone();
+        trailing.push(0);
+        assert!(gameboy.load_state(&trailing).is_err());
+    }
+
+    #[test]
+    fn cart_speaker_plays() {
+        // huc3, with the apu off
+        let mut gameboy = running_gameboy(test_rom(0xfe, 0x10000, 0x02));
+        gameboy.bus.write(0xff26, 0x00);
+        let peak = |gameboy: &mut Gameboy| {
+            gameboy.emulate(
+                crate::GB_CLOCK_SPEED / crate::GB_DEVICE_FPS,
+                JoypadState::default(),
+            );
+            let mut peak = 0;
+            gameboy.apu_output(|buf| {
+                peak = buf.iter().fold(peak, |peak, &s| peak.max((s as i32).abs()));
+            });
+            peak
+        };
+        peak(&mut gameboy);
+        assert!(peak(&mut gameboy) < 100);
+
+        // tone 5 at 0x27, then start it
+        gameboy.bus.write(0x0000, 0x0b);
+        for &command in [0x47, 0x52, 0x25, 0x6e].iter() {
+            gameboy.bus.write(0xa000, command);
+        }
+        assert!(peak(&mut gameboy) > 1000);
+    }
+
+    #[test]
+    fn reset_matches_power_on() {
+        let rom = test_rom(0x03, 0x10000, 0x02);
+        let mut gameboy = running_gameboy(rom.clone());
+        gameboy.bus.write(0xc000, 0x12);
+        gameboy.bus.write(0xff80, 0x34);
+        gameboy.bus.write(0xff07, 0x05);
+        gameboy.bus.write(0xff01, 0x56);
+        gameboy.bus.write(0xff02, 0x81);
+        gameboy.reset();
+
+        let fresh = Gameboy::new(test_cartridge(rom));
+        assert_eq!(gameboy.save_state(), fresh.save_state());
+    }
+}
@@ -1,3 +1,4 @@
+use crate::state::{Savestate, StateReader, StateWriter};
 use bitflags::bitflags;
 
 /// interrupt enable register
@@ -87,3 +88,16 @@ impl InterruptHandler {
         }
     }
 }
+
+impl Savestate for InterruptHandler {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u8(self.ie_port.bits());
+        w.write_u8(self.if_port.bits());
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> std::io::Result<()> {
+        self.ie_port = InterruptFlag::from_bits_truncate(r.read_u8()?);
+        self.if_port = InterruptFlag::from_bits_truncate(r.read_u8()?);
+        Ok(())
+    }
+}
@@ -1,4 +1,5 @@
 use crate::interrupt::{Interrupt, InterruptHandler};
+use crate::state::{Savestate, StateReader, StateWriter};
 use bitflags::bitflags;
 
 const BUTTON_SELECT_MASK: u8 = 0b0010_0000;
@@ -17,6 +18,14 @@ pub struct JoypadState {
     pub button_b: bool,
 }
 
+/// How far the gameboy is tilted, in g, for carts with an accelerometer.
+/// Positive x tilts right, positive y tilts towards the player.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct TiltState {
+    pub x: f32,
+    pub y: f32,
+}
+
 pub struct Joypad {
     select: SelectFlag,
 
@@ -79,3 +88,14 @@ bitflags! {
         const DIRECTION = DIRECTION_SELECT_MASK;
     }
 }
+
+impl Savestate for Joypad {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u8(self.select.bits());
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> std::io::Result<()> {
+        self.select = SelectFlag::from_bits_truncate(r.read_u8()?);
+        Ok(())
+    }
+}
@@ -6,12 +6,15 @@
 pub use self::{
     apu::Apu,
     bus::Bus,
-    cart::{load_cartridge, Cartridge},
+    cart::{load_cartridge, load_cartridge_with_saves, CartError, Cartridge, ClockSource},
     cpu::Cpu,
+    debug::Debugger,
     gameboy::Gameboy,
     interrupt::InterruptHandler,
-    joypad::{Joypad, JoypadState},
+    joypad::{Joypad, JoypadState, TiltState},
+    link::LinkedPair,
     ppu::Ppu,
+    runner::Runner,
     timer::Timer,
 };
 
@@ -19,10 +22,15 @@ pub mod apu;
 pub mod bus;
 pub mod cart;
 pub mod cpu;
+pub mod debug;
+pub mod disasm;
 pub mod gameboy;
 pub mod interrupt;
 pub mod joypad;
+pub mod link;
 pub mod ppu;
+pub mod runner;
+pub mod state;
 pub mod timer;
 
 /// LCD screen width
@@ -35,5 +43,5 @@ pub const GB_CLOCK_SPEED: u32 = 4_194_304;
 /// Emulator update speed
 pub const GB_DEVICE_FPS: u32 = 60;
 
-/// audio output frequency
+/// default audio output frequency
 pub const AUDIO_FREQUENCY: u32 = 192000;
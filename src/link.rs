@@ -0,0 +1,88 @@
+use crate::bus::LinkPort;
+use crate::{Gameboy, JoypadState};
+
+/// Two gameboys connected by a link cable.
+///
+/// The machine that is behind steps one instruction at a time, so the two
+/// never drift more than an instruction apart. A byte is exchanged when the
+/// clocking side shifts out its last bit: the other side receives it if it
+/// waits on the external clock by then, otherwise the clocking side reads
+/// 0xff, as with nothing connected.
+pub struct LinkedPair {
+    gameboys: [Gameboy; 2],
+}
+
+impl LinkedPair {
+    pub fn new(mut left: Gameboy, mut right: Gameboy) -> Self {
+        let (port0, port1) = LinkPort::cable();
+        left.set_serial_link(Box::new(port0));
+        right.set_serial_link(Box::new(port1));
+
+        LinkedPair {
+            gameboys: [left, right],
+        }
+    }
+
+    pub fn emulate(&mut self, max_cycles: u32, states: [JoypadState; 2]) {
+        let [left, right] = states;
+        self.gameboys[0].set_input(left);
+        self.gameboys[1].set_input(right);
+
+        let mut current = [0u32; 2];
+        while current[0] < max_cycles || current[1] < max_cycles {
+            // always advance the machine that is behind
+            let index = (current[1] < current[0]) as usize;
+            current[index] += self.gameboys[index].step();
+        }
+    }
+
+    pub fn gameboys(&self) -> &[Gameboy] {
+        &self.gameboys
+    }
+
+    pub fn gameboys_mut(&mut self) -> &mut [Gameboy] {
+        &mut self.gameboys
+    }
+
+    pub fn into_inner(self) -> [Gameboy; 2] {
+        self.gameboys
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cart::{test_cartridge, test_rom};
+    use crate::{GB_CLOCK_SPEED, GB_DEVICE_FPS};
+
+    /// Writes `sb` and `sc` to the serial ports, then spins.
+    fn transfer_gameboy(sb: u8, sc: u8) -> Gameboy {
+        let mut rom = test_rom(0x00, 0x8000, 0x00);
+        // nop; jp $0150
+        rom[0x100..0x104].copy_from_slice(&[0x00, 0xc3, 0x50, 0x01]);
+        // ld a,sb; ldh ($01),a; ld a,sc; ldh ($02),a; jr -2
+        rom[0x150..0x15a]
+            .copy_from_slice(&[0x3e, sb, 0xe0, 0x01, 0x3e, sc, 0xe0, 0x02, 0x18, 0xfe]);
+        Gameboy::new(test_cartridge(rom))
+    }
+
+    #[test]
+    fn exchanges_a_byte() {
+        let master = transfer_gameboy(0x42, 0x81);
+        let slave = transfer_gameboy(0x99, 0x80);
+        let mut pair = LinkedPair::new(master, slave);
+
+        for _ in 0..2 {
+            pair.emulate(GB_CLOCK_SPEED / GB_DEVICE_FPS, Default::default());
+            for gameboy in pair.gameboys_mut() {
+                gameboy.apu_output(|_| {});
+            }
+        }
+
+        let [master, slave] = pair.into_inner();
+        assert_eq!(master.read_memory(0xff01), 0x99);
+        assert_eq!(slave.read_memory(0xff01), 0x42);
+        assert_eq!(master.read_memory(0xff02) & 0x80, 0);
+        assert_eq!(slave.read_memory(0xff02) & 0x80, 0);
+    }
+}
@@ -0,0 +1,378 @@
+//! Colors the cgb boot rom picks for dmg carts.
+//!
+//! The boot rom sums the title of carts licensed by Nintendo and looks the
+//! sum up in a table of known games. Holding a direction, optionally with A
+//! or B, while the logo shows picks one of twelve palettes instead.
+
+use super::palette::{rgb555, DmgPalette};
+use crate::JoypadState;
+
+/// title sums of the known games
+const TITLE_CHECKSUMS: [u8; 94] = [
+    0x00, 0x88, 0x16, 0x36, 0xd1, 0xdb, 0xf2, 0x3c, 0x8c, 0x92, 0x3d, 0x5c, 0x58, 0xc9, 0x3e, 0x70,
+    0x1d, 0x59, 0x69, 0x19, 0x35, 0xa8, 0x14, 0xaa, 0x75, 0x95, 0x99, 0x34, 0x6f, 0x15, 0xff, 0x97,
+    0x4b, 0x90, 0x17, 0x10, 0x39, 0xf7, 0xf6, 0xa2, 0x49, 0x4e, 0x43, 0x68, 0xe0, 0x8b, 0xf0, 0xce,
+    0x0c, 0x29, 0xe8, 0xb7, 0x86, 0x9a, 0x52, 0x01, 0x9d, 0x71, 0x9c, 0xbd, 0x5d, 0x6d, 0x67, 0x3f,
+    0x6b, // sums from here on are shared, the 4th title letter tells them apart
+    0xb3, 0x46, 0x28, 0xa5, 0xc6, 0xd3, 0x27, 0x61, 0x18, 0x66, 0x6a, 0xbf, 0x0d, 0xf4, 0xb3, 0x46,
+    0x28, 0xa5, 0xc6, 0xd3, 0x27, 0x61, 0x18, 0x66, 0x6a, 0xbf, 0x0d, 0xf4, 0xb3,
+];
+/// first sum that needs the 4th letter checked
+const FIRST_DUPLICATE: usize = 65;
+/// 4th title letters of the games with shared sums
+const DUPLICATE_LETTERS: &[u8; 29] = b"BEFAARBEKEK R-URAR INAILICE R";
+
+/// palette combination of each known game, bit 7 is a boot tilemap flag
+const CHECKSUM_COMBINATIONS: [u8; 94] = [
+    0,
+    4,
+    5,
+    35,
+    34,
+    3,
+    31,
+    15,
+    10,
+    5,
+    19,
+    36,
+    7 | 0x80,
+    37,
+    30,
+    44,
+    21,
+    32,
+    31,
+    20,
+    5,
+    33,
+    13,
+    14,
+    5,
+    29,
+    5,
+    18,
+    9,
+    3,
+    2,
+    26,
+    25,
+    25,
+    41,
+    42,
+    26,
+    45,
+    42,
+    45,
+    36,
+    38,
+    26,
+    42,
+    30,
+    41,
+    34,
+    34,
+    5,
+    42,
+    6,
+    5,
+    33,
+    25,
+    42,
+    42,
+    40,
+    2,
+    16,
+    25,
+    42,
+    42,
+    5,
+    0,
+    39,
+    36,
+    22,
+    25,
+    6,
+    32,
+    12,
+    36,
+    11,
+    39,
+    18,
+    39,
+    24,
+    31,
+    50,
+    17,
+    46,
+    6,
+    27,
+    0,
+    47,
+    41,
+    41,
+    0,
+    0,
+    19,
+    34,
+    23,
+    18,
+    29,
+];
+
+const fn comb(obj0: usize, obj1: usize, bg: usize) -> [usize; 3] {
+    [obj0 * 4, obj1 * 4, bg * 4]
+}
+
+/// offsets into `COLORS` of the obj0, obj1 and bg palettes, a few start
+/// in the middle of a palette
+const COMBINATIONS: [[usize; 3]; 51] = [
+    comb(4, 4, 29),
+    comb(18, 18, 18),
+    comb(20, 20, 20),
+    comb(24, 24, 24),
+    comb(9, 9, 9),
+    comb(0, 0, 0),
+    comb(27, 27, 27),
+    comb(5, 5, 5),
+    comb(12, 12, 12),
+    comb(26, 26, 26),
+    comb(16, 8, 8),
+    comb(4, 28, 28),
+    comb(4, 2, 2),
+    comb(3, 4, 4),
+    comb(4, 29, 29),
+    comb(28, 4, 28),
+    comb(2, 17, 2),
+    comb(16, 16, 8),
+    comb(4, 4, 7),
+    comb(4, 4, 18),
+    comb(4, 4, 20),
+    comb(19, 19, 9),
+    [4 * 4 - 1, 4 * 4 - 1, 11 * 4],
+    comb(17, 17, 2),
+    comb(4, 4, 2),
+    comb(4, 4, 3),
+    comb(28, 28, 0),
+    comb(3, 3, 0),
+    comb(0, 0, 1),
+    comb(18, 22, 18),
+    comb(20, 22, 20),
+    comb(24, 22, 24),
+    comb(16, 22, 8),
+    comb(17, 4, 13),
+    [28 * 4 - 1, 0, 14 * 4],
+    [28 * 4 - 1, 4 * 4, 15 * 4],
+    comb(19, 22, 9),
+    comb(16, 28, 10),
+    comb(4, 23, 28),
+    comb(17, 22, 2),
+    comb(4, 0, 2),
+    comb(4, 28, 3),
+    comb(28, 3, 0),
+    comb(3, 28, 4),
+    comb(21, 28, 4),
+    comb(3, 28, 0),
+    comb(25, 3, 28),
+    comb(0, 28, 8),
+    comb(4, 3, 28),
+    comb(28, 3, 6),
+    comb(4, 28, 29),
+];
+
+/// the boot rom's palettes, 4 rgb555 colors each
+#[rustfmt::skip]
+const COLORS: [u16; 120] = [
+    0x7fff, 0x32bf, 0x00d0, 0x0000,
+    0x639f, 0x4279, 0x15b0, 0x04cb,
+    0x7fff, 0x6e31, 0x454a, 0x0000,
+    0x7fff, 0x1bef, 0x0200, 0x0000,
+    0x7fff, 0x421f, 0x1cf2, 0x0000,
+    0x7fff, 0x5294, 0x294a, 0x0000,
+    0x7fff, 0x03ff, 0x012f, 0x0000,
+    0x7fff, 0x03ef, 0x01d6, 0x0000,
+    0x7fff, 0x42b5, 0x3dc8, 0x0000,
+    0x7e74, 0x03ff, 0x0180, 0x0000,
+    0x67ff, 0x77ac, 0x1a13, 0x2d6b,
+    0x7ed6, 0x4bff, 0x2175, 0x0000,
+    0x53ff, 0x4a5f, 0x7e52, 0x0000,
+    0x4fff, 0x7ed2, 0x3a4c, 0x1ce0,
+    0x03ed, 0x7fff, 0x255f, 0x0000,
+    0x036a, 0x021f, 0x03ff, 0x7fff,
+    0x7fff, 0x01df, 0x0112, 0x0000,
+    0x231f, 0x035f, 0x00f2, 0x0009,
+    0x7fff, 0x03ea, 0x011f, 0x0000,
+    0x299f, 0x001a, 0x000c, 0x0000,
+    0x7fff, 0x027f, 0x001f, 0x0000,
+    0x7fff, 0x03e0, 0x0206, 0x0120,
+    0x7fff, 0x7eeb, 0x001f, 0x7c00,
+    0x7fff, 0x3fff, 0x7e00, 0x001f,
+    0x7fff, 0x03ff, 0x001f, 0x0000,
+    0x03ff, 0x001f, 0x000c, 0x0000,
+    0x7fff, 0x033f, 0x0193, 0x0000,
+    0x0000, 0x4200, 0x037f, 0x7fff,
+    0x7fff, 0x7e8c, 0x7c00, 0x0000,
+    0x7fff, 0x1bef, 0x6180, 0x0000,
+];
+
+/// combinations picked by right, left, up and down, alone, with A and with B
+const KEY_COMBINATIONS: [[usize; 4]; 3] = [[1, 48, 5, 8], [0, 40, 43, 3], [6, 7, 28, 49]];
+
+/// The palette the cgb boot rom picks for a dmg cart from its header.
+pub fn title_palette(rom: &[u8]) -> DmgPalette {
+    combination(title_combination(rom))
+}
+
+/// The palette picked by the buttons held at boot, `None` without a
+/// direction.
+pub fn key_palette(keys: &JoypadState) -> Option<DmgPalette> {
+    let direction = [keys.right, keys.left, keys.up, keys.down]
+        .iter()
+        .position(|&held| held)?;
+    let modifier = if keys.button_a {
+        1
+    } else if keys.button_b {
+        2
+    } else {
+        0
+    };
+
+    Some(combination(KEY_COMBINATIONS[modifier][direction]))
+}
+
+fn title_combination(rom: &[u8]) -> usize {
+    if rom.len() < 0x150 {
+        return 0;
+    }
+
+    // only Nintendo's own games are in the table
+    let nintendo = match rom[0x014b] {
+        0x01 => true,
+        0x33 => &rom[0x0144..0x0146] == b"01",
+        _ => false,
+    };
+    if !nintendo {
+        return 0;
+    }
+
+    let checksum = rom[0x0134..=0x0143]
+        .iter()
+        .fold(0u8, |sum, &b| sum.wrapping_add(b));
+    let letter = rom[0x0137];
+
+    TITLE_CHECKSUMS
+        .iter()
+        .enumerate()
+        .find(|&(i, &sum)| {
+            sum == checksum
+                && (i < FIRST_DUPLICATE || DUPLICATE_LETTERS[i - FIRST_DUPLICATE] == letter)
+        })
+        .map(|(i, _)| (CHECKSUM_COMBINATIONS[i] & 0x7f) as usize)
+        .unwrap_or(0)
+}
+
+fn combination(index: usize) -> DmgPalette {
+    let palette = |offset: usize| {
+        let mut colors = [[0u8; 3]; 4];
+        for (i, color) in colors.iter_mut().enumerate() {
+            *color = rgb555(COLORS[offset + i]);
+        }
+        colors
+    };
+
+    let [obj0, obj1, bg] = COMBINATIONS[index];
+    DmgPalette {
+        bg: palette(bg),
+        obj0: palette(obj0),
+        obj1: palette(obj1),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rom(title: &[u8], licensee: u8) -> Vec<u8> {
+        let mut rom = vec![0u8; 0x150];
+        rom[0x134..0x134 + title.len()].copy_from_slice(title);
+        rom[0x14b] = licensee;
+        rom
+    }
+
+    /// A title with `letter` 4th, summing to `sum`.
+    fn title_with_sum(letter: u8, sum: u8) -> [u8; 5] {
+        let mut title = [b'X', b'X', b'X', letter, 0];
+        title[4] = title[..4].iter().fold(sum, |rest, &b| rest.wrapping_sub(b));
+        title
+    }
+
+    #[test]
+    fn checksum_hit() {
+        assert_eq!(title_combination(&rom(b"TETRIS", 0x01)), 3);
+        assert_eq!(title_palette(&rom(b"TETRIS", 0x01)), combination(3));
+        // unknown games get the default
+        assert_eq!(title_combination(&rom(b"DEMO", 0x01)), 0);
+    }
+
+    #[test]
+    fn shared_sum_checks_4th_letter() {
+        assert_eq!(
+            title_combination(&rom(&title_with_sum(b'B', 0xb3), 0x01)),
+            36
+        );
+        assert_eq!(
+            title_combination(&rom(&title_with_sum(b'U', 0xb3), 0x01)),
+            17
+        );
+        assert_eq!(
+            title_combination(&rom(&title_with_sum(b'R', 0xb3), 0x01)),
+            29
+        );
+        assert_eq!(
+            title_combination(&rom(&title_with_sum(b'Z', 0xb3), 0x01)),
+            0
+        );
+    }
+
+    #[test]
+    fn other_licensees_get_the_default() {
+        assert_eq!(title_combination(&rom(b"TETRIS", 0x00)), 0);
+        assert_eq!(title_combination(&rom(b"TETRIS", 0x33)), 0);
+
+        // new licensee code, Nintendo is "01"
+        let mut new_licensee = rom(b"TETRIS", 0x33);
+        new_licensee[0x144..0x146].copy_from_slice(b"01");
+        assert_eq!(title_combination(&new_licensee), 3);
+        new_licensee[0x144..0x146].copy_from_slice(b"02");
+        assert_eq!(title_combination(&new_licensee), 0);
+    }
+
+    #[test]
+    fn keys_override() {
+        assert_eq!(key_palette(&JoypadState::default()), None);
+        // buttons alone don't pick one
+        let a = JoypadState {
+            button_a: true,
+            ..Default::default()
+        };
+        assert_eq!(key_palette(&a), None);
+
+        let up = JoypadState {
+            up: true,
+            ..Default::default()
+        };
+        assert_eq!(key_palette(&up), Some(combination(5)));
+        let left_a = JoypadState {
+            left: true,
+            button_a: true,
+            ..Default::default()
+        };
+        assert_eq!(key_palette(&left_a), Some(combination(40)));
+        let down_b = JoypadState {
+            down: true,
+            button_b: true,
+            ..Default::default()
+        };
+        assert_eq!(key_palette(&down_b), Some(combination(49)));
+    }
+}
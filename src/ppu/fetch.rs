@@ -1,4 +1,5 @@
 use super::{BgAttr, Ppu, Sprite, TileValue, LCDC};
+use crate::state::{invalid_state, Savestate, StateReader, StateWriter};
 use std::collections::VecDeque;
 
 pub enum FetchState {
@@ -34,6 +35,92 @@ pub struct Fetcher {
     sprite_fifo: VecDeque<(Sprite, TileValue)>,
 }
 
+impl Savestate for Fetcher {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_usize(self.ticks);
+        w.write_usize(self.fb_offset);
+        w.write_u8(match self.state {
+            FetchState::ReadTile => 0,
+            FetchState::ReadData0 => 1,
+            FetchState::ReadData1 => 2,
+            FetchState::Push => 3,
+        });
+
+        w.write_bool(self.bg_restart);
+        w.write_bool(self.window_start);
+        w.write_usize(self.map_start);
+        w.write_usize(self.tile_index);
+        w.write_u8(self.tile_attr.raw());
+        w.write_usize(self.fx);
+        w.write_usize(self.fy);
+        w.write_usize(self.scx);
+
+        w.write_usize(self.bg_fifo.len());
+        for (attr, tile) in self.bg_fifo.iter() {
+            w.write_u8(attr.raw());
+            w.write_u8(*tile as u8);
+        }
+
+        w.write_bool(self.sprite_fetching);
+        w.write_usize(self.sprite_fifo.len());
+        for (sprite, tile) in self.sprite_fifo.iter() {
+            sprite.save_state(w);
+            w.write_u8(*tile as u8);
+        }
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> std::io::Result<()> {
+        self.ticks = r.read_usize()?;
+        self.fb_offset = r.read_usize()?;
+        self.state = match r.read_u8()? {
+            0 => FetchState::ReadTile,
+            1 => FetchState::ReadData0,
+            2 => FetchState::ReadData1,
+            3 => FetchState::Push,
+            _ => return Err(invalid_state("bad fetcher state")),
+        };
+
+        self.bg_restart = r.read_bool()?;
+        self.window_start = r.read_bool()?;
+        self.map_start = r.read_usize()?;
+        self.tile_index = r.read_usize()?;
+        self.tile_attr = BgAttr::from_u8(r.read_u8()?);
+        self.fx = r.read_usize()?;
+        self.fy = r.read_usize()?;
+        self.scx = r.read_usize()?;
+
+        // everything indexing the frame buffer, the tile maps or the tiles
+        let fb_len = crate::GB_LCD_WIDTH * crate::GB_LCD_HEIGHT * 3;
+        if self.ticks > 1
+            || self.fb_offset > fb_len
+            || self.map_start > 0x7e0
+            || self.tile_index >= 0x180
+            || self.fx >= 0x20
+            || self.scx > 0x07
+        {
+            return Err(invalid_state("bad fetcher position"));
+        }
+
+        self.bg_fifo.clear();
+        for _ in 0..r.read_usize()? {
+            let attr = BgAttr::from_u8(r.read_u8()?);
+            let tile = TileValue::from_u8(r.read_u8()?);
+            self.bg_fifo.push_back((attr, tile));
+        }
+
+        self.sprite_fetching = r.read_bool()?;
+        self.sprite_fifo.clear();
+        for _ in 0..r.read_usize()? {
+            let mut sprite = Sprite::default();
+            sprite.load_state(r)?;
+            let tile = TileValue::from_u8(r.read_u8()?);
+            self.sprite_fifo.push_back((sprite, tile));
+        }
+
+        Ok(())
+    }
+}
+
 impl Ppu {
     pub fn pixel_fetch_reset(&mut self, window_start: bool) {
         self.fet.ticks = 0;
@@ -1,4 +1,5 @@
 use crate::interrupt::{Interrupt, InterruptHandler};
+use crate::state::{invalid_state, Savestate, StateReader, StateWriter};
 use crate::{GB_LCD_HEIGHT, GB_LCD_WIDTH};
 use bitflags::bitflags;
 
@@ -6,6 +7,10 @@ use fetch::*;
 use palette::*;
 use vram::*;
 
+pub use compat::{key_palette, title_palette};
+pub use palette::{Color, DmgPalette, DMG_PRESETS};
+
+mod compat;
 mod fetch;
 mod palette;
 mod vram;
@@ -258,9 +263,9 @@ impl Ppu {
             0xff43 => self.scx = b,
             0xff44 => {}
             0xff45 => self.lyc = b,
-            0xff47 if !self.cgb => self.bg_palette.write_dmg(0, b),
-            0xff48 if !self.cgb => self.obj_palette.write_dmg(0, b),
-            0xff49 if !self.cgb => self.obj_palette.write_dmg(1, b),
+            0xff47 => self.bg_palette.write_dmg(0, b),
+            0xff48 => self.obj_palette.write_dmg(0, b),
+            0xff49 => self.obj_palette.write_dmg(1, b),
 
             0xff4a => self.winy = b,
             0xff4b => self.winx = b,
@@ -275,6 +280,30 @@ impl Ppu {
         }
     }
 
+    /// Switches to dmg rendering when a cgb boots a dmg cart, keeping the
+    /// colors the boot rom picked.
+    pub fn enter_compat_mode(&mut self) {
+        self.cgb = false;
+        self.vram.enter_compat_mode();
+        self.bg_palette.enter_compat_mode();
+        self.obj_palette.enter_compat_mode();
+    }
+
+    /// Sets the colors of dmg carts, cgb carts pick their own.
+    pub fn set_dmg_palette(&mut self, palette: &DmgPalette) {
+        self.bg_palette.set_dmg_colors(0, palette.bg);
+        self.obj_palette.set_dmg_colors(0, palette.obj0);
+        self.obj_palette.set_dmg_colors(1, palette.obj1);
+    }
+
+    pub fn dmg_palette(&self) -> DmgPalette {
+        DmgPalette {
+            bg: self.bg_palette.dmg_colors(0),
+            obj0: self.obj_palette.dmg_colors(0),
+            obj1: self.obj_palette.dmg_colors(1),
+        }
+    }
+
     pub fn get_frame_buffer(&self) -> &[u8] {
         self.back_buffer.as_ref()
     }
@@ -300,6 +329,86 @@ impl Ppu {
     }
 }
 
+impl Savestate for Ppu {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_bytes(self.frame_buffer.as_ref());
+        w.write_bytes(self.back_buffer.as_ref());
+
+        self.vram.save_state(w);
+
+        w.write_u8(self.lcdc.bits());
+        w.write_u8(self.stat.bits());
+        w.write_u8(self.mode as u8);
+
+        w.write_u8(self.scy);
+        w.write_u8(self.scx);
+        w.write_u8(self.ly);
+        w.write_u8(self.lyc);
+        w.write_u8(self.winy);
+        w.write_u8(self.winx);
+        w.write_u8(self.win_ly);
+
+        w.write_bool(self.hdma_avaliable);
+        self.bg_palette.save_state(w);
+        self.obj_palette.save_state(w);
+        w.write_bool(self.cgb);
+
+        w.write_u32(self.clocks);
+        w.write_usize(self.current_x);
+        w.write_bool(self.ly_154);
+
+        self.fet.save_state(w);
+        w.write_usize(self.oam_buffer.len());
+        for sprite in self.oam_buffer.iter() {
+            sprite.save_state(w);
+        }
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> std::io::Result<()> {
+        r.read_bytes(self.frame_buffer.as_mut())?;
+        r.read_bytes(self.back_buffer.as_mut())?;
+
+        self.vram.load_state(r)?;
+
+        self.lcdc = LCDC::from_bits_truncate(r.read_u8()?);
+        self.stat = STAT::from_bits_truncate(r.read_u8()?);
+        self.mode = match r.read_u8()? {
+            0 => LcdMode::HBlank,
+            1 => LcdMode::VBlank,
+            2 => LcdMode::OamSearch,
+            3 => LcdMode::Transfer,
+            _ => return Err(invalid_state("bad lcd mode")),
+        };
+
+        self.scy = r.read_u8()?;
+        self.scx = r.read_u8()?;
+        self.ly = r.read_u8()?;
+        self.lyc = r.read_u8()?;
+        self.winy = r.read_u8()?;
+        self.winx = r.read_u8()?;
+        self.win_ly = r.read_u8()?;
+
+        self.hdma_avaliable = r.read_bool()?;
+        self.bg_palette.load_state(r)?;
+        self.obj_palette.load_state(r)?;
+        self.cgb = r.read_bool()?;
+
+        self.clocks = r.read_u32()?;
+        self.current_x = r.read_usize()?;
+        self.ly_154 = r.read_bool()?;
+
+        self.fet.load_state(r)?;
+        self.oam_buffer.clear();
+        for _ in 0..r.read_usize()? {
+            let mut sprite = Sprite::default();
+            sprite.load_state(r)?;
+            self.oam_buffer.push(sprite);
+        }
+
+        Ok(())
+    }
+}
+
 bitflags! {
     #[derive(Default)]
     pub struct LCDC: u8 {
@@ -1,52 +1,198 @@
 use super::TileValue;
+use crate::state::{Savestate, StateReader, StateWriter};
 
-type Color = [u8; 3];
-
-// black-white
-// const COLOR_PALETTE: [u32; 4] = [0x00ff_ffff, 0x00c0_c0c0, 0x0060_6060, 0x0000_0000];
-// classic
-// const COLOR_PALETTE: [u32; 4] = [0x00ef_ffde, 0x00ad_d794, 0x0052_9273, 0x0018_3442];
-// bgb
-// const COLOR_PALETTE: [u32; 4] = [0x00e0_f8d0, 0x0088_c070, 0x0034_6856, 0x0008_1820];
-// kirokaze gameboy
-// const COLOR_PALETTE: [u32; 4] = [0x00e2_f3e4, 0x0094e_344, 0x0046_878f, 0x0033_2c50];
-// mist gb
-const COLOR_PALETTE: [[u8; 3]; 4] = [
-    [0xc4, 0xf0, 0xc2],
-    [0x5a, 0xb9, 0xa8],
-    [0x1e, 0x60, 0x6e],
-    [0x2d, 0x1b, 0x00],
+use std::io::{Error, ErrorKind};
+
+pub type Color = [u8; 3];
+
+/// Colors of the dmg shades, for the background and both sprite palettes.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct DmgPalette {
+    pub bg: [Color; 4],
+    pub obj0: [Color; 4],
+    pub obj1: [Color; 4],
+}
+
+/// built in palettes, by name
+pub const DMG_PRESETS: [(&str, DmgPalette); 5] = [
+    (
+        "black-white",
+        DmgPalette::gray(0xff_ffff, 0xc0_c0c0, 0x60_6060, 0x00_0000),
+    ),
+    (
+        "classic",
+        DmgPalette::gray(0xef_ffde, 0xad_d794, 0x52_9273, 0x18_3442),
+    ),
+    (
+        "bgb",
+        DmgPalette::gray(0xe0_f8d0, 0x88_c070, 0x34_6856, 0x08_1820),
+    ),
+    (
+        "kirokaze",
+        DmgPalette::gray(0xe2_f3e4, 0x94_e344, 0x46_878f, 0x33_2c50),
+    ),
+    (
+        "mist",
+        DmgPalette::gray(0xc4_f0c2, 0x5a_b9a8, 0x1e_606e, 0x2d_1b00),
+    ),
 ];
 
+const fn rgb(color: u32) -> Color {
+    [(color >> 16) as u8, (color >> 8) as u8, color as u8]
+}
+
+impl DmgPalette {
+    /// The same four shades, from lightest to darkest, everywhere.
+    pub const fn gray(c0: u32, c1: u32, c2: u32, c3: u32) -> Self {
+        let shades = [rgb(c0), rgb(c1), rgb(c2), rgb(c3)];
+        DmgPalette {
+            bg: shades,
+            obj0: shades,
+            obj1: shades,
+        }
+    }
+
+    pub fn preset(name: &str) -> Option<Self> {
+        DMG_PRESETS
+            .iter()
+            .find(|(preset, _)| *preset == name)
+            .map(|(_, palette)| *palette)
+    }
+
+    /// Reads a palette file, like
+    ///
+    /// ```text
+    /// # lightest to darkest
+    /// bg   = e0f8d0 88c070 346856 081820
+    /// obj0 = ffffff ff8484 943a3a 000000
+    /// obj1 = ffffff 63a5ff 0000ff 000000
+    /// ```
+    ///
+    /// Sprite palettes left out use the background colors.
+    pub fn load<P: AsRef<std::path::Path>>(path: P) -> std::io::Result<Self> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn parse(text: &str) -> std::io::Result<Self> {
+        let mut bg = None;
+        let mut obj0 = None;
+        let mut obj1 = None;
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |msg: &str| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("line {}: {}", number + 1, msg),
+                )
+            };
+
+            let mut parts = line.splitn(2, '=');
+            let name = parts.next().unwrap_or("").trim();
+            let values = parts
+                .next()
+                .ok_or_else(|| error("expected name = colors"))?;
+
+            let mut shades = [[0u8; 3]; 4];
+            let mut count = 0;
+            for value in values.split_whitespace() {
+                let digits = value.trim_start_matches('#').trim_start_matches("0x");
+                let color = match u32::from_str_radix(digits, 16) {
+                    Ok(color) if digits.len() == 6 => color,
+                    _ => return Err(error(&format!("bad color: {}", value))),
+                };
+                if count < 4 {
+                    shades[count] = rgb(color);
+                }
+                count += 1;
+            }
+            if count != 4 {
+                return Err(error("expected 4 colors"));
+            }
+
+            match name {
+                "bg" => bg = Some(shades),
+                "obj0" => obj0 = Some(shades),
+                "obj1" => obj1 = Some(shades),
+                _ => return Err(error(&format!("unknown palette: {}", name))),
+            }
+        }
+
+        let bg = bg.ok_or_else(|| Error::new(ErrorKind::InvalidData, "missing bg colors"))?;
+        Ok(DmgPalette {
+            bg,
+            obj0: obj0.unwrap_or(bg),
+            obj1: obj1.unwrap_or(bg),
+        })
+    }
+}
+
+impl Default for DmgPalette {
+    fn default() -> Self {
+        DmgPalette::preset("mist").unwrap()
+    }
+}
+
 pub struct Palette {
+    // dmg shades, or colors picked by the cgb boot rom in compatibility mode
+    dmg_colors: [[Color; 4]; 2],
     palette_index: [PaletteIndex; 2],
     palettes_rgb: [[Color; 4]; 8],
     palattes_555: [[u16; 4]; 8],
     data_index: usize,
     index_inc: bool,
+    cgb: bool,
 }
 
 impl Palette {
     pub fn build(cgb: bool) -> Self {
+        let shades = DmgPalette::default().bg;
         if !cgb {
             Palette {
+                dmg_colors: [shades; 2],
                 palette_index: [Default::default(); 2],
-                palettes_rgb: [COLOR_PALETTE; 8],
+                palettes_rgb: [shades; 8],
                 palattes_555: [[0u16; 4]; 8],
                 data_index: 0,
                 index_inc: false,
+                cgb,
             }
         } else {
             Palette {
+                dmg_colors: [shades; 2],
                 palette_index: [Default::default(); 2],
                 palettes_rgb: Default::default(),
                 palattes_555: [[0u16; 4]; 8],
                 data_index: 0,
                 index_inc: false,
+                cgb,
             }
         }
     }
 
+    /// Uses the first two cgb palettes as dmg colors from now on.
+    pub fn enter_compat_mode(&mut self) {
+        self.cgb = false;
+        self.dmg_colors = [self.palettes_rgb[0], self.palettes_rgb[1]];
+
+        for pal in 0..2 {
+            self.write_dmg(pal, self.palette_index[pal as usize].raw);
+        }
+    }
+
+    /// Sets the colors of the dmg shades.
+    pub fn set_dmg_colors(&mut self, pal: u8, colors: [Color; 4]) {
+        self.dmg_colors[pal as usize] = colors;
+        self.write_dmg(pal, self.palette_index[pal as usize].raw);
+    }
+
+    pub fn dmg_colors(&self, pal: u8) -> [Color; 4] {
+        self.dmg_colors[pal as usize]
+    }
+
     pub fn read_index(&self) -> u8 {
         self.data_index as u8 | ((self.index_inc as u8) << 7)
     }
@@ -83,22 +229,7 @@ impl Palette {
         }
         self.palattes_555[pal_index][color_index] = color;
 
-        // f e d c b a 9 8 7 6 5 4 3 2 1 0
-        //                       --------- red
-        //             --------- green
-        //   --------- blue
-        let r = color & 0b0001_1111;
-        let g = (color >> 5) & 0b0001_1111;
-        let b = (color >> 10) & 0b0001_1111;
-
-        // ref: https://byuu.net/video/color-emulation
-        let r_adjusted = ((r * 26 + g * 4 + b * 2).min(960) / 4) as u8;
-        let g_adjusted = ((g * 24 + b * 8).min(960) / 4) as u8;
-        let b_adjusted = ((r * 6 + g * 4 + b * 22).min(960) / 4) as u8;
-
-        self.palettes_rgb[pal_index][color_index][0] = r_adjusted;
-        self.palettes_rgb[pal_index][color_index][1] = g_adjusted;
-        self.palettes_rgb[pal_index][color_index][2] = b_adjusted;
+        self.palettes_rgb[pal_index][color_index] = rgb555(color);
 
         if self.index_inc {
             self.data_index = (self.data_index + 1) % 0x40;
@@ -113,8 +244,13 @@ impl Palette {
         let pal = pal as usize;
         self.palette_index[pal] = PaletteIndex::from_u8(data);
 
+        // cgb colors come from palette ram
+        if self.cgb {
+            return;
+        }
+
         for i in 0..4 {
-            self.palettes_rgb[pal][i] = COLOR_PALETTE[self.palette_index[pal].pal[i]];
+            self.palettes_rgb[pal][i] = self.dmg_colors[pal][self.palette_index[pal].pal[i]];
         }
     }
 
@@ -123,6 +259,75 @@ impl Palette {
     }
 }
 
+/// Converts a cgb color to rgb, as the cgb lcd shows it.
+pub fn rgb555(color: u16) -> Color {
+    // f e d c b a 9 8 7 6 5 4 3 2 1 0
+    //                       --------- red
+    //             --------- green
+    //   --------- blue
+    let r = color & 0b0001_1111;
+    let g = (color >> 5) & 0b0001_1111;
+    let b = (color >> 10) & 0b0001_1111;
+
+    // ref: https://byuu.net/video/color-emulation
+    let r_adjusted = ((r * 26 + g * 4 + b * 2).min(960) / 4) as u8;
+    let g_adjusted = ((g * 24 + b * 8).min(960) / 4) as u8;
+    let b_adjusted = ((r * 6 + g * 4 + b * 22).min(960) / 4) as u8;
+
+    [r_adjusted, g_adjusted, b_adjusted]
+}
+
+impl Savestate for Palette {
+    fn save_state(&self, w: &mut StateWriter) {
+        for palette in self.dmg_colors.iter() {
+            for color in palette.iter() {
+                w.write_bytes(color);
+            }
+        }
+        for index in self.palette_index.iter() {
+            w.write_u8(index.raw);
+        }
+        for palette in self.palettes_rgb.iter() {
+            for color in palette.iter() {
+                w.write_bytes(color);
+            }
+        }
+        for palette in self.palattes_555.iter() {
+            for &color in palette.iter() {
+                w.write_u16(color);
+            }
+        }
+        w.write_usize(self.data_index);
+        w.write_bool(self.index_inc);
+        w.write_bool(self.cgb);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> std::io::Result<()> {
+        for palette in self.dmg_colors.iter_mut() {
+            for color in palette.iter_mut() {
+                r.read_bytes(color)?;
+            }
+        }
+        for index in self.palette_index.iter_mut() {
+            *index = PaletteIndex::from_u8(r.read_u8()?);
+        }
+        for palette in self.palettes_rgb.iter_mut() {
+            for color in palette.iter_mut() {
+                r.read_bytes(color)?;
+            }
+        }
+        for palette in self.palattes_555.iter_mut() {
+            for color in palette.iter_mut() {
+                *color = r.read_u16()?;
+            }
+        }
+        self.data_index = r.read_usize()? % 0x40;
+        self.index_inc = r.read_bool()?;
+        self.cgb = r.read_bool()?;
+        Ok(())
+    }
+}
+
 #[derive(Copy, Clone)]
 pub struct PaletteIndex {
     raw: u8,
@@ -152,3 +357,62 @@ impl PaletteIndex {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_error(text: &str) -> String {
+        DmgPalette::parse(text).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn parse() {
+        let palette = DmgPalette::parse(
+            "# lightest to darkest\n\
+             bg   = e0f8d0 88c070 346856 081820\n\
+             \n\
+             obj0 = #ffffff 0xff8484 943a3a 000000\n",
+        )
+        .unwrap();
+        assert_eq!(palette, {
+            let mut expected = DmgPalette::preset("bgb").unwrap();
+            expected.obj0 = [
+                [0xff, 0xff, 0xff],
+                [0xff, 0x84, 0x84],
+                [0x94, 0x3a, 0x3a],
+                [0x00, 0x00, 0x00],
+            ];
+            expected
+        });
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(parse_error("bg e0f8d0"), "line 1: expected name = colors");
+        assert_eq!(
+            parse_error("\nbg = e0f8d0 88c070 346856"),
+            "line 2: expected 4 colors"
+        );
+        assert_eq!(
+            parse_error("bg = e0f8d0 88c070 346856 081820 000000"),
+            "line 1: expected 4 colors"
+        );
+        assert_eq!(
+            parse_error("bg = e0f8d0 88c07 346856 081820"),
+            "line 1: bad color: 88c07"
+        );
+        assert_eq!(
+            parse_error("bg = e0f8d0 88c0zz 346856 081820"),
+            "line 1: bad color: 88c0zz"
+        );
+        assert_eq!(
+            parse_error("obj2 = e0f8d0 88c070 346856 081820"),
+            "line 1: unknown palette: obj2"
+        );
+        assert_eq!(
+            parse_error("obj0 = e0f8d0 88c070 346856 081820"),
+            "missing bg colors"
+        );
+    }
+}
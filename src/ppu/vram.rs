@@ -1,4 +1,5 @@
 use super::LcdMode;
+use crate::state::{invalid_state, Savestate, StateReader, StateWriter};
 
 const TILESET_SIZE: usize = 0x1800;
 const TILEMAP_SIZE: usize = 0x800;
@@ -48,6 +49,19 @@ impl VideoRam {
         }
     }
 
+    pub fn enter_compat_mode(&mut self) {
+        self.cgb = false;
+        self.vram_bank = 0;
+        for attr in self.attr_map.iter_mut() {
+            *attr = Default::default();
+        }
+
+        // sprite palettes are read from other bits
+        for addr in (0x03..OAM_SIZE).step_by(4) {
+            self.write_sprite(addr, self.sprite_table[addr], LcdMode::VBlank);
+        }
+    }
+
     pub fn bank(&self) -> u8 {
         self.vram_bank as u8
     }
@@ -112,19 +126,22 @@ impl VideoRam {
         }
 
         self.tile_sets[self.vram_bank][addr] = data;
+        self.decode_tile_line(self.vram_bank, addr);
+    }
 
+    fn decode_tile_line(&mut self, bank: usize, addr: usize) {
         // 384 tiles * 16 bytes
         // 0001 1111 1111 1110
         // 000T TTTT TTTT YYY0
         let addr = addr & 0x1ffe;
 
-        let tile = &mut self.tiles[self.vram_bank][addr >> 4];
+        let tile = &mut self.tiles[bank][addr >> 4];
         let y = (addr >> 1) & 0b0111;
 
         for x in 0..8 {
             let mask = 1 << (7 - x);
-            let lsb = mask & self.tile_sets[self.vram_bank][addr];
-            let msb = mask & self.tile_sets[self.vram_bank][addr + 1];
+            let lsb = mask & self.tile_sets[bank][addr];
+            let msb = mask & self.tile_sets[bank][addr + 1];
 
             tile[y][x] = match (msb == 0, lsb == 0) {
                 (true, true) => TileValue::B00,
@@ -166,6 +183,49 @@ impl VideoRam {
     }
 }
 
+impl Savestate for VideoRam {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_bytes(self.sprite_table.as_ref());
+        for tile_set in self.tile_sets.iter() {
+            w.write_bytes(tile_set.as_ref());
+        }
+        w.write_bytes(self.tile_map.as_ref());
+        for attr in self.attr_map.iter() {
+            w.write_u8(attr.raw);
+        }
+        w.write_usize(self.vram_bank);
+        w.write_bool(self.cgb);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> std::io::Result<()> {
+        r.read_bytes(self.sprite_table.as_mut())?;
+        for tile_set in self.tile_sets.iter_mut() {
+            r.read_bytes(tile_set.as_mut())?;
+        }
+        r.read_bytes(self.tile_map.as_mut())?;
+        for attr in self.attr_map.iter_mut() {
+            *attr = BgAttr::from_u8(r.read_u8()?);
+        }
+        self.vram_bank = r.read_usize()?;
+        if self.vram_bank > 1 {
+            return Err(invalid_state("bad vram bank"));
+        }
+        self.cgb = r.read_bool()?;
+
+        // rebuild decoded sprites and tiles
+        for addr in 0..OAM_SIZE {
+            self.write_sprite(addr, self.sprite_table[addr], LcdMode::VBlank);
+        }
+        for bank in 0..2 {
+            for addr in (0..TILESET_SIZE).step_by(2) {
+                self.decode_tile_line(bank, addr);
+            }
+        }
+
+        Ok(())
+    }
+}
+
 #[derive(Default, Copy, Clone)]
 pub struct Sprite {
     pub index: usize,
@@ -179,6 +239,33 @@ pub struct Sprite {
     pub vram_bank: u8,
 }
 
+impl Savestate for Sprite {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_usize(self.index);
+        w.write_u16(self.x as u16);
+        w.write_u16(self.y as u16);
+        w.write_u8(self.tile_index);
+        w.write_bool(self.above_bg);
+        w.write_bool(self.flip_y);
+        w.write_bool(self.flip_x);
+        w.write_u8(self.palette);
+        w.write_u8(self.vram_bank);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> std::io::Result<()> {
+        self.index = r.read_usize()?;
+        self.x = r.read_u16()? as i16;
+        self.y = r.read_u16()? as i16;
+        self.tile_index = r.read_u8()?;
+        self.above_bg = r.read_bool()?;
+        self.flip_y = r.read_bool()?;
+        self.flip_x = r.read_bool()?;
+        self.palette = r.read_u8()?;
+        self.vram_bank = r.read_u8()?;
+        Ok(())
+    }
+}
+
 #[derive(Default, Copy, Clone)]
 pub struct BgAttr {
     raw: u8,
@@ -190,7 +277,11 @@ pub struct BgAttr {
 }
 
 impl BgAttr {
-    fn from_u8(data: u8) -> Self {
+    pub fn raw(&self) -> u8 {
+        self.raw
+    }
+
+    pub fn from_u8(data: u8) -> Self {
         BgAttr {
             raw: data,
             bg_pal_index: data & 0b0111,
@@ -211,4 +302,15 @@ pub enum TileValue {
     B11 = 3,
 }
 
+impl TileValue {
+    pub fn from_u8(data: u8) -> TileValue {
+        match data & 0b11 {
+            0 => TileValue::B00,
+            1 => TileValue::B01,
+            2 => TileValue::B10,
+            _ => TileValue::B11,
+        }
+    }
+}
+
 pub type Tile = [[TileValue; 8]; 8];
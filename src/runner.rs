@@ -0,0 +1,199 @@
+use crate::bus::CaptureLink;
+use crate::cart::load_cartridge_with_saves;
+use crate::{CartError, Cartridge, ClockSource, Gameboy, GB_CLOCK_SPEED, GB_DEVICE_FPS};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// `LD B,B`, used by test roms as a software breakpoint
+const BREAKPOINT_OP: u8 = 0x40;
+/// blargg's "test in progress" value at 0xa000
+const SIGNATURE_RUNNING: u8 = 0x80;
+/// blargg's marker at 0xa001-0xa003, meaning results are valid
+const SIGNATURE_MAGIC: [u8; 3] = [0xde, 0xb0, 0x61];
+/// mooneye's registers B, C, D, E, H, L on success
+const FIBONACCI: [u8; 6] = [3, 5, 8, 13, 21, 34];
+/// mooneye's registers on failure
+const FAILURE: [u8; 6] = [0x42; 6];
+/// runs repeat exactly, whatever the time of day
+const CLOCK: ClockSource = ClockSource::Emulated {
+    offset: Duration::from_secs(0),
+};
+
+/// When a test rom is considered done.
+pub enum ExitCondition {
+    /// Passes once the serial output contains the text.
+    SerialPass(String),
+    /// Fails once the serial output contains the text.
+    SerialFail(String),
+    /// Stops at `LD B,B`, mooneye's fibonacci registers pass.
+    Breakpoint,
+    /// Stops when blargg's result code at 0xa000 is ready.
+    Signature,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum Outcome {
+    Passed,
+    Failed(String),
+    /// `LD B,B` was hit, but the registers tell neither pass nor fail.
+    Breakpoint,
+    Timeout,
+}
+
+/// Runs a cartridge without video or audio output.
+pub struct Runner {
+    gameboy: Gameboy,
+    serial: Arc<Mutex<Vec<u8>>>,
+}
+
+impl Runner {
+    /// Loads a rom to run, a saved clock starts at the time it was saved.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, CartError> {
+        Ok(Runner::new(load_cartridge_with_saves(path, None, CLOCK)?))
+    }
+
+    /// Runs a cartridge on emulated time. A saved clock caught up on the time
+    /// since it was saved if the cart was loaded with the host's, see `load`.
+    pub fn new(mut cart: Cartridge) -> Self {
+        cart.set_clock_source(CLOCK);
+        let mut gameboy = Gameboy::new(cart);
+
+        let link = CaptureLink::new();
+        let serial = link.output();
+        gameboy.set_serial_link(Box::new(link));
+
+        Runner { gameboy, serial }
+    }
+
+    /// Runs up to `frames` frames, or until one of the conditions is met.
+    pub fn run(&mut self, frames: u32, conditions: &[ExitCondition]) -> Outcome {
+        let breakpoint = conditions
+            .iter()
+            .any(|c| matches!(c, ExitCondition::Breakpoint));
+        let frame_cycles = GB_CLOCK_SPEED / GB_DEVICE_FPS;
+
+        for _ in 0..frames {
+            let mut current = 0;
+            while current < frame_cycles {
+                if breakpoint {
+                    let pc = self.gameboy.registers().pc;
+                    if self.gameboy.read_memory(pc) == BREAKPOINT_OP {
+                        return self.check_registers();
+                    }
+                }
+                current += self.gameboy.step();
+            }
+            // nobody listens, but the resampler only holds a few frames
+            self.gameboy.apu_output(|_| {});
+
+            if let Some(outcome) = self.check(conditions) {
+                return outcome;
+            }
+        }
+
+        Outcome::Timeout
+    }
+
+    fn check(&self, conditions: &[ExitCondition]) -> Option<Outcome> {
+        let output = self.serial_output();
+
+        for condition in conditions {
+            match condition {
+                ExitCondition::SerialPass(text) if output.contains(text.as_str()) => {
+                    return Some(Outcome::Passed);
+                }
+                ExitCondition::SerialFail(text) if output.contains(text.as_str()) => {
+                    return Some(Outcome::Failed(output));
+                }
+                ExitCondition::Signature => {
+                    let magic = [
+                        self.gameboy.read_memory(0xa001),
+                        self.gameboy.read_memory(0xa002),
+                        self.gameboy.read_memory(0xa003),
+                    ];
+                    let result = self.gameboy.read_memory(0xa000);
+
+                    if magic == SIGNATURE_MAGIC && result != SIGNATURE_RUNNING {
+                        return Some(if result == 0 {
+                            Outcome::Passed
+                        } else {
+                            Outcome::Failed(format!(
+                                "result code {:02x}: {}",
+                                result,
+                                self.signature_text()
+                            ))
+                        });
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        None
+    }
+
+    fn check_registers(&self) -> Outcome {
+        let reg = self.gameboy.registers();
+        let regs = [reg.b, reg.c, reg.d, reg.e, reg.h, reg.l];
+
+        if regs == FIBONACCI {
+            Outcome::Passed
+        } else if regs == FAILURE {
+            Outcome::Failed(format!("{:?}", reg))
+        } else {
+            Outcome::Breakpoint
+        }
+    }
+
+    fn signature_text(&self) -> String {
+        (0xa004..=0xbfff)
+            .map(|addr| self.gameboy.read_memory(addr))
+            .take_while(|&c| c != 0)
+            .map(|c| c as char)
+            .collect()
+    }
+
+    /// Everything the rom has sent through the link port.
+    pub fn serial_output(&self) -> String {
+        String::from_utf8_lossy(&self.serial.lock().unwrap()).into_owned()
+    }
+
+    pub fn gameboy(&self) -> &Gameboy {
+        &self.gameboy
+    }
+
+    pub fn gameboy_mut(&mut self) -> &mut Gameboy {
+        &mut self.gameboy
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cart::{test_rom, SaveFormat};
+
+    #[test]
+    fn saved_clock_does_not_catch_up() {
+        let dir = std::env::temp_dir().join(format!("joy-runner-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let rom_path = dir.join("rtc.gb");
+        // mbc3 with a clock and 8 KiB of ram, saved at the unix epoch
+        std::fs::write(&rom_path, test_rom(0x10, 0x8000, 0x02)).unwrap();
+        std::fs::write(dir.join("rtc.sav"), vec![0u8; 0x2000 + 48]).unwrap();
+
+        let runner = Runner::load(&rom_path).unwrap();
+        let export = dir.join("export.sav");
+        runner
+            .gameboy
+            .bus
+            .cart
+            .export_save(&export, SaveFormat::RtcFooter)
+            .unwrap();
+        let data = std::fs::read(&export).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // seconds, minutes, hours and days
+        assert!(data[0x2000..0x2000 + 20].iter().all(|&b| b == 0));
+    }
+}
@@ -0,0 +1,198 @@
+use std::io::{Error, ErrorKind, Result};
+
+/// save state magic number
+const STATE_MAGIC: &[u8; 4] = b"JOYS";
+/// save state format version, bump on layout changes
+pub const STATE_VERSION: u16 = 5;
+
+/// Machine components that can be snapshotted into a save state.
+pub trait Savestate {
+    fn save_state(&self, w: &mut StateWriter);
+    fn load_state(&mut self, r: &mut StateReader) -> Result<()>;
+}
+
+pub struct StateWriter {
+    buf: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        let mut w = StateWriter { buf: Vec::new() };
+        w.write_bytes(STATE_MAGIC);
+        w.write_u16(STATE_VERSION);
+        w
+    }
+
+    pub fn write_u8(&mut self, data: u8) {
+        self.buf.push(data);
+    }
+
+    pub fn write_bool(&mut self, data: bool) {
+        self.buf.push(data as u8);
+    }
+
+    pub fn write_u16(&mut self, data: u16) {
+        self.buf.extend_from_slice(&data.to_le_bytes());
+    }
+
+    pub fn write_u32(&mut self, data: u32) {
+        self.buf.extend_from_slice(&data.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, data: u64) {
+        self.buf.extend_from_slice(&data.to_le_bytes());
+    }
+
+    pub fn write_i64(&mut self, data: i64) {
+        self.buf.extend_from_slice(&data.to_le_bytes());
+    }
+
+    pub fn write_usize(&mut self, data: usize) {
+        self.write_u64(data as u64);
+    }
+
+    pub fn write_bytes(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+
+    pub fn into_inner(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+pub struct StateReader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(buf: &'a [u8]) -> Result<Self> {
+        let mut r = StateReader { buf };
+
+        let mut magic = [0u8; 4];
+        r.read_bytes(&mut magic)?;
+        if &magic != STATE_MAGIC {
+            return Err(invalid_state("not a save state"));
+        }
+
+        let version = r.read_u16()?;
+        if version != STATE_VERSION {
+            return Err(invalid_state(format!(
+                "unsupported save state version: {}",
+                version
+            )));
+        }
+
+        Ok(r)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8> {
+        let mut data = [0u8; 1];
+        self.read_bytes(&mut data)?;
+        Ok(data[0])
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool> {
+        Ok(self.read_u8()? != 0)
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16> {
+        let mut data = [0u8; 2];
+        self.read_bytes(&mut data)?;
+        Ok(u16::from_le_bytes(data))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32> {
+        let mut data = [0u8; 4];
+        self.read_bytes(&mut data)?;
+        Ok(u32::from_le_bytes(data))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64> {
+        let mut data = [0u8; 8];
+        self.read_bytes(&mut data)?;
+        Ok(u64::from_le_bytes(data))
+    }
+
+    pub fn read_i64(&mut self) -> Result<i64> {
+        let mut data = [0u8; 8];
+        self.read_bytes(&mut data)?;
+        Ok(i64::from_le_bytes(data))
+    }
+
+    pub fn read_usize(&mut self) -> Result<usize> {
+        Ok(self.read_u64()? as usize)
+    }
+
+    pub fn read_bytes(&mut self, data: &mut [u8]) -> Result<()> {
+        if self.buf.len() < data.len() {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                "save state is truncated",
+            ));
+        }
+
+        let (head, tail) = self.buf.split_at(data.len());
+        data.copy_from_slice(head);
+        self.buf = tail;
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+}
+
+pub fn invalid_state<E>(error: E) -> Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    Error::new(ErrorKind::InvalidData, error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut w = StateWriter::new();
+        w.write_u8(0x12);
+        w.write_bool(true);
+        w.write_u16(0x3456);
+        w.write_u32(0x789a_bcde);
+        w.write_u64(u64::MAX - 1);
+        w.write_i64(-2);
+        w.write_usize(0x1234);
+        w.write_bytes(&[1, 2, 3]);
+        let state = w.into_inner();
+
+        let mut r = StateReader::new(&state).unwrap();
+        assert_eq!(r.read_u8().unwrap(), 0x12);
+        assert!(r.read_bool().unwrap());
+        assert_eq!(r.read_u16().unwrap(), 0x3456);
+        assert_eq!(r.read_u32().unwrap(), 0x789a_bcde);
+        assert_eq!(r.read_u64().unwrap(), u64::MAX - 1);
+        assert_eq!(r.read_i64().unwrap(), -2);
+        assert_eq!(r.read_usize().unwrap(), 0x1234);
+        let mut bytes = [0u8; 3];
+        r.read_bytes(&mut bytes).unwrap();
+        assert_eq!(bytes, [1, 2, 3]);
+        assert!(r.is_empty());
+        assert!(r.read_u8().is_err());
+    }
+
+    #[test]
+    fn bad_magic() {
+        let mut state = StateWriter::new().into_inner();
+        state[0] = b'X';
+        assert!(StateReader::new(&state).is_err());
+        assert!(StateReader::new(b"JO").is_err());
+    }
+
+    #[test]
+    fn bad_version() {
+        let mut state = StateWriter::new().into_inner();
+        state[4..6].copy_from_slice(&(STATE_VERSION - 1).to_le_bytes());
+        assert!(StateReader::new(&state).is_err());
+    }
+}
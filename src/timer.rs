@@ -1,4 +1,5 @@
 use crate::interrupt::{Interrupt, InterruptHandler};
+use crate::state::{Savestate, StateReader, StateWriter};
 
 /// divider register
 const DIV_PORT: u16 = 0xff04;
@@ -89,3 +90,25 @@ impl Timer {
         }
     }
 }
+
+impl Savestate for Timer {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u16(self.div_clocks);
+        w.write_u16(self.timer_clocks);
+        w.write_u8(self.tima);
+        w.write_u8(self.tma);
+        w.write_u8(self.tac);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> std::io::Result<()> {
+        self.div_clocks = r.read_u16()?;
+        self.timer_clocks = r.read_u16()?;
+        self.tima = r.read_u8()?;
+        self.tma = r.read_u8()?;
+
+        let tac = r.read_u8()?;
+        self.write(TAC_PORT, tac);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,214 @@
+//! Runs test roms found under `tests/roms` (or `$JOY_TEST_ROMS`) through a
+//! headless gameboy and prints a pass/fail matrix.
+//!
+//! Roms are picked by directory:
+//!
+//! * `blargg/`: serial output "Passed" / "Failed", or the result at 0xa000
+//! * `mooneye/`: fibonacci registers at `LD B,B`
+//! * `acid2/`: frame buffer hash at `LD B,B`, compared with `<rom>.hash`
+//!
+//! Roms listed by file name in `known_failures.txt` are reported, but do not
+//! fail the test. Without any roms the suite is skipped, unless
+//! `JOY_REQUIRE_TEST_ROMS` is set. A tiny built in rom checks the runner
+//! itself either way.
+
+use joy::runner::{ExitCondition, Outcome};
+use joy::Runner;
+
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+
+const MAX_FRAMES: u32 = 60 * 120;
+
+#[derive(Clone, Copy)]
+enum Suite {
+    Blargg,
+    Mooneye,
+    Acid2,
+}
+
+impl Suite {
+    fn name(self) -> &'static str {
+        match self {
+            Suite::Blargg => "blargg",
+            Suite::Mooneye => "mooneye",
+            Suite::Acid2 => "acid2",
+        }
+    }
+
+    fn conditions(self) -> Vec<ExitCondition> {
+        match self {
+            Suite::Blargg => vec![
+                ExitCondition::SerialPass("Passed".to_owned()),
+                ExitCondition::SerialFail("Failed".to_owned()),
+                ExitCondition::Signature,
+            ],
+            Suite::Mooneye | Suite::Acid2 => vec![ExitCondition::Breakpoint],
+        }
+    }
+}
+
+struct Report {
+    suite: Suite,
+    rom: PathBuf,
+    result: Result<(), String>,
+}
+
+fn roms_dir() -> PathBuf {
+    std::env::var_os("JOY_TEST_ROMS")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/roms"))
+}
+
+fn find_roms(dir: &Path, roms: &mut Vec<PathBuf>) {
+    if let Ok(entries) = std::fs::read_dir(dir) {
+        for path in entries.filter_map(|e| e.ok()).map(|e| e.path()) {
+            if path.is_dir() {
+                find_roms(&path, roms);
+            } else if let Some("gb") | Some("gbc") = path.extension().and_then(|e| e.to_str()) {
+                roms.push(path);
+            }
+        }
+    }
+}
+
+// FNV-1a
+fn frame_hash(frame: &[u8]) -> u64 {
+    frame.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| {
+        (hash ^ b as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+fn run_rom(suite: Suite, path: &Path) -> Result<(), String> {
+    let mut runner = Runner::load(path).map_err(|e| e.to_string())?;
+    let outcome = runner.run(MAX_FRAMES, &suite.conditions());
+
+    match (suite, outcome) {
+        (Suite::Acid2, Outcome::Breakpoint) => {
+            let hash = frame_hash(runner.gameboy().get_frame_buffer());
+            let expected = std::fs::read_to_string(path.with_extension("hash"))
+                .map_err(|_| format!("no reference hash, got {:016x}", hash))?;
+
+            if expected.trim() == format!("{:016x}", hash) {
+                Ok(())
+            } else {
+                Err(format!("frame hash {:016x}", hash))
+            }
+        }
+        (_, Outcome::Passed) => Ok(()),
+        (_, Outcome::Failed(reason)) => Err(reason.trim().replace('\n', " ")),
+        (_, Outcome::Breakpoint) => Err("unexpected breakpoint".to_owned()),
+        (_, Outcome::Timeout) => Err("timeout".to_owned()),
+    }
+}
+
+#[test]
+fn conformance() {
+    let dir = roms_dir();
+    if !dir.is_dir() {
+        if std::env::var_os("JOY_REQUIRE_TEST_ROMS").is_some() {
+            panic!("no test roms at {}", dir.display());
+        }
+        println!("no test roms at {}, skipped", dir.display());
+        return;
+    }
+
+    let known_failures = std::fs::read_to_string(dir.join("known_failures.txt"))
+        .unwrap_or_default()
+        .lines()
+        .map(|line| line.trim().to_owned())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect::<Vec<_>>();
+
+    let mut reports = Vec::new();
+    for &suite in [Suite::Blargg, Suite::Mooneye, Suite::Acid2].iter() {
+        let mut roms = Vec::new();
+        find_roms(&dir.join(suite.name()), &mut roms);
+        roms.sort();
+
+        for rom in roms {
+            let result = run_rom(suite, &rom);
+            let name = rom.strip_prefix(&dir).unwrap_or(&rom);
+            reports.push(Report {
+                suite,
+                rom: name.to_owned(),
+                result,
+            });
+        }
+    }
+
+    let mut regressions = Vec::new();
+    println!();
+    for report in reports.iter() {
+        match &report.result {
+            Ok(()) => println!("[PASS] {:8} {}", report.suite.name(), report.rom.display()),
+            Err(reason) => {
+                let known = known_failures
+                    .iter()
+                    .any(|known| report.rom.file_name() == Some(OsStr::new(known)));
+                println!(
+                    "[{}] {:8} {}: {}",
+                    if known { "xfail" } else { "FAIL" },
+                    report.suite.name(),
+                    report.rom.display(),
+                    reason
+                );
+
+                if !known {
+                    regressions.push(report.rom.display().to_string());
+                }
+            }
+        }
+    }
+
+    let passed = reports.iter().filter(|r| r.result.is_ok()).count();
+    println!("\n{} / {} roms passed", passed, reports.len());
+
+    assert!(regressions.is_empty(), "failed roms: {:?}", regressions);
+}
+
+/// A mooneye style rom loading `registers` into B, C, D, E, H and L, then
+/// stopping at `LD B,B`.
+fn breakpoint_rom(registers: [u8; 6]) -> Vec<u8> {
+    let mut rom = vec![0u8; 0x8000];
+    rom[0x104..0x134].copy_from_slice(&joy::cart::NINTENDO_LOGO);
+    rom[0x134..0x138].copy_from_slice(b"TEST");
+    rom[0x14d] = rom[0x134..=0x14c]
+        .iter()
+        .fold(0u8, |sum, &b| sum.wrapping_sub(b).wrapping_sub(1));
+
+    // nop; jp $0150, past the header
+    rom[0x100..0x104].copy_from_slice(&[0x00, 0xc3, 0x50, 0x01]);
+
+    // ld b/c/d/e/h/l, n
+    let mut code = Vec::new();
+    for (&op, &value) in [0x06, 0x0e, 0x16, 0x1e, 0x26, 0x2e].iter().zip(&registers) {
+        code.extend_from_slice(&[op, value]);
+    }
+    // ld b,b then jr -2
+    code.extend_from_slice(&[0x40, 0x18, 0xfe]);
+    rom[0x150..0x150 + code.len()].copy_from_slice(&code);
+    rom
+}
+
+fn run_breakpoint_rom(name: &str, registers: [u8; 6]) -> Outcome {
+    let path = std::env::temp_dir().join(format!("joy-{}-{}.gb", name, std::process::id()));
+    std::fs::write(&path, breakpoint_rom(registers)).unwrap();
+    let mut runner = Runner::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    runner.run(10, &Suite::Mooneye.conditions())
+}
+
+#[test]
+fn runner_breakpoint() {
+    assert_eq!(
+        run_breakpoint_rom("pass", [3, 5, 8, 13, 21, 34]),
+        Outcome::Passed
+    );
+    assert!(matches!(
+        run_breakpoint_rom("fail", [0x42; 6]),
+        Outcome::Failed(_)
+    ));
+    assert_eq!(run_breakpoint_rom("unknown", [1; 6]), Outcome::Breakpoint);
+}